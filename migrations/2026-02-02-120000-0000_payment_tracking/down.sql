@@ -0,0 +1,4 @@
+ALTER TABLE transactions
+    DROP COLUMN IF EXISTS htlc_attempts,
+    DROP COLUMN IF EXISTS failure_code,
+    DROP COLUMN IF EXISTS payment_status;
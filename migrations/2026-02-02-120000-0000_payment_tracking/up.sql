@@ -0,0 +1,5 @@
+-- Progress reported by routerrpc SendPaymentV2 for outgoing payments
+ALTER TABLE transactions
+    ADD COLUMN payment_status VARCHAR(20) CHECK (payment_status IN ('in_flight', 'succeeded', 'failed')),
+    ADD COLUMN failure_code VARCHAR(64),
+    ADD COLUMN htlc_attempts INTEGER NOT NULL DEFAULT 0;
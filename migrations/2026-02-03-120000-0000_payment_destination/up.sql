@@ -0,0 +1,2 @@
+-- Recipient node of outgoing payments; keysend payments have no invoice to decode it from
+ALTER TABLE transactions ADD COLUMN destination VARCHAR(66);
@@ -0,0 +1,6 @@
+UPDATE transactions SET status = 'pending' WHERE status = 'accepted';
+
+ALTER TABLE transactions
+    DROP CONSTRAINT IF EXISTS transactions_status_check,
+    ADD CONSTRAINT transactions_status_check
+        CHECK (status IN ('pending', 'succeeded', 'failed', 'expired'));
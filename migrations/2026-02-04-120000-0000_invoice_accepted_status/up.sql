@@ -0,0 +1,5 @@
+-- Hold invoices that have been paid but not yet settled or cancelled
+ALTER TABLE transactions
+    DROP CONSTRAINT IF EXISTS transactions_status_check,
+    ADD CONSTRAINT transactions_status_check
+        CHECK (status IN ('pending', 'accepted', 'succeeded', 'failed', 'expired'));
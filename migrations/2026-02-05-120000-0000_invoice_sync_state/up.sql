@@ -0,0 +1,8 @@
+-- Last invoice add/settle indices seen from each receiving node, so the
+-- invoice subscription can resume where it left off
+CREATE TABLE invoice_sync_state (
+    node_id VARCHAR(66) PRIMARY KEY,
+    add_index BIGINT NOT NULL DEFAULT 0,
+    settle_index BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,9 @@
+-- Lightning nodes configured for this instance and what each is used for.
+-- Registered at startup from the configuration.
+CREATE TABLE nodes (
+    node_id VARCHAR(66) PRIMARY KEY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    role VARCHAR(10) NOT NULL CHECK (role IN ('receive', 'send', 'both')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,47 @@
+DROP TRIGGER IF EXISTS transaction_ledger_update ON transactions;
+DROP FUNCTION IF EXISTS transaction_ledger_update();
+DROP FUNCTION IF EXISTS post_ledger_entries(transactions);
+DROP FUNCTION IF EXISTS ledger_account(VARCHAR, VARCHAR);
+DROP TABLE IF EXISTS ledger_entries;
+DROP FUNCTION IF EXISTS reject_ledger_change();
+DROP TABLE IF EXISTS accounts;
+
+CREATE TABLE balance (
+    id INTEGER PRIMARY KEY DEFAULT 1,
+    received_sats BIGINT NOT NULL DEFAULT 0,
+    paid_sats BIGINT NOT NULL DEFAULT 0,
+    last_updated TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO balance (id, received_sats, paid_sats)
+SELECT 1,
+    COALESCE(SUM(amount_sats) FILTER (WHERE tx_type = 'invoice'), 0),
+    COALESCE(SUM(amount_sats + COALESCE(fee_sats, 0)) FILTER (WHERE tx_type = 'payment'), 0)
+FROM transactions
+WHERE status = 'succeeded';
+
+CREATE OR REPLACE FUNCTION update_balance()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.status = 'succeeded' THEN
+        IF NEW.tx_type = 'invoice' THEN
+            UPDATE balance SET
+                received_sats = received_sats + NEW.amount_sats,
+                last_updated = NOW()
+            WHERE id = 1;
+        ELSIF NEW.tx_type = 'payment' THEN
+            UPDATE balance SET
+                paid_sats = paid_sats + NEW.amount_sats + COALESCE(NEW.fee_sats, 0),
+                last_updated = NOW()
+            WHERE id = 1;
+        END IF;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER transaction_balance_update
+AFTER INSERT OR UPDATE OF status ON transactions
+FOR EACH ROW
+WHEN (NEW.status = 'succeeded')
+EXECUTE FUNCTION update_balance();
@@ -0,0 +1,144 @@
+-- The single-row `balance` table summed every update to `succeeded`, so a
+-- repeated update counted twice. Balances now come from a ledger instead.
+DROP TRIGGER IF EXISTS transaction_balance_update ON transactions;
+DROP FUNCTION IF EXISTS update_balance();
+DROP TABLE IF EXISTS balance;
+
+-- Accounts of each node:
+--   lightning  the node's own funds (asset)
+--   received   settled invoices (income)
+--   sent       amounts of succeeded payments (expense)
+--   fees       routing fees of succeeded payments (expense)
+CREATE TABLE accounts (
+    id BIGSERIAL PRIMARY KEY,
+    node_id VARCHAR(66) NOT NULL,
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('lightning', 'received', 'sent', 'fees')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (node_id, kind)
+);
+
+-- Each entry moves `amount_sats` from its credit account to its debit
+-- account, so debits and credits always balance. Entries are never changed
+-- or removed: a wrong entry is undone by a reversal entry pointing at it.
+CREATE TABLE ledger_entries (
+    id BIGSERIAL PRIMARY KEY,
+    transaction_id BIGINT NOT NULL REFERENCES transactions(id),
+    entry_type VARCHAR(20) NOT NULL
+        CHECK (entry_type IN ('invoice_settled', 'payment_amount', 'payment_fee', 'reversal')),
+    debit_account_id BIGINT NOT NULL REFERENCES accounts(id),
+    credit_account_id BIGINT NOT NULL REFERENCES accounts(id),
+    amount_sats BIGINT NOT NULL CHECK (amount_sats > 0),
+    reverses_entry_id BIGINT UNIQUE REFERENCES ledger_entries(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (debit_account_id <> credit_account_id),
+    CHECK ((entry_type = 'reversal') = (reverses_entry_id IS NOT NULL))
+);
+
+CREATE INDEX idx_ledger_entries_transaction ON ledger_entries(transaction_id);
+CREATE INDEX idx_ledger_entries_debit ON ledger_entries(debit_account_id);
+CREATE INDEX idx_ledger_entries_credit ON ledger_entries(credit_account_id);
+
+CREATE OR REPLACE FUNCTION reject_ledger_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'ledger entries are immutable, post a reversal instead';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER ledger_entries_immutable
+BEFORE UPDATE OR DELETE ON ledger_entries
+FOR EACH ROW
+EXECUTE FUNCTION reject_ledger_change();
+
+-- The account of `kind` for a node, created on first use
+CREATE OR REPLACE FUNCTION ledger_account(p_node_id VARCHAR, p_kind VARCHAR)
+RETURNS BIGINT AS $$
+    INSERT INTO accounts (node_id, kind) VALUES (p_node_id, p_kind)
+    ON CONFLICT (node_id, kind) DO UPDATE SET kind = EXCLUDED.kind
+    RETURNING id;
+$$ LANGUAGE sql;
+
+-- Bring a transaction's ledger entries in line with its current state.
+-- A succeeded invoice is received; a succeeded payment is paid, its fee
+-- separately; anything else has nothing posted. When the posted entries
+-- differ from that, they are reversed and the expected ones posted, so
+-- repeating an update posts nothing.
+CREATE OR REPLACE FUNCTION post_ledger_entries(tx transactions)
+RETURNS VOID AS $$
+DECLARE
+    expected_amount BIGINT := 0;
+    expected_fee BIGINT := 0;
+    posted_amount BIGINT;
+    posted_fee BIGINT;
+    entry RECORD;
+BEGIN
+    IF tx.status = 'succeeded' THEN
+        expected_amount := tx.amount_sats;
+        IF tx.tx_type = 'payment' THEN
+            expected_fee := COALESCE(tx.fee_sats, 0);
+        END IF;
+    END IF;
+
+    SELECT
+        COALESCE(SUM(e.amount_sats) FILTER (WHERE e.entry_type <> 'payment_fee'), 0),
+        COALESCE(SUM(e.amount_sats) FILTER (WHERE e.entry_type = 'payment_fee'), 0)
+    INTO posted_amount, posted_fee
+    FROM ledger_entries e
+    WHERE e.transaction_id = tx.id
+      AND e.entry_type <> 'reversal'
+      AND NOT EXISTS (SELECT 1 FROM ledger_entries r WHERE r.reverses_entry_id = e.id);
+
+    IF posted_amount = expected_amount AND posted_fee = expected_fee THEN
+        RETURN;
+    END IF;
+
+    FOR entry IN
+        SELECT e.* FROM ledger_entries e
+        WHERE e.transaction_id = tx.id
+          AND e.entry_type <> 'reversal'
+          AND NOT EXISTS (SELECT 1 FROM ledger_entries r WHERE r.reverses_entry_id = e.id)
+    LOOP
+        INSERT INTO ledger_entries
+            (transaction_id, entry_type, debit_account_id, credit_account_id, amount_sats, reverses_entry_id)
+        VALUES
+            (tx.id, 'reversal', entry.credit_account_id, entry.debit_account_id, entry.amount_sats, entry.id);
+    END LOOP;
+
+    IF expected_amount > 0 THEN
+        IF tx.tx_type = 'invoice' THEN
+            INSERT INTO ledger_entries (transaction_id, entry_type, debit_account_id, credit_account_id, amount_sats)
+            VALUES (tx.id, 'invoice_settled',
+                    ledger_account(tx.node_id, 'lightning'), ledger_account(tx.node_id, 'received'),
+                    expected_amount);
+        ELSE
+            INSERT INTO ledger_entries (transaction_id, entry_type, debit_account_id, credit_account_id, amount_sats)
+            VALUES (tx.id, 'payment_amount',
+                    ledger_account(tx.node_id, 'sent'), ledger_account(tx.node_id, 'lightning'),
+                    expected_amount);
+        END IF;
+    END IF;
+
+    IF expected_fee > 0 THEN
+        INSERT INTO ledger_entries (transaction_id, entry_type, debit_account_id, credit_account_id, amount_sats)
+        VALUES (tx.id, 'payment_fee',
+                ledger_account(tx.node_id, 'fees'), ledger_account(tx.node_id, 'lightning'),
+                expected_fee);
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION transaction_ledger_update()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM post_ledger_entries(NEW);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER transaction_ledger_update
+AFTER INSERT OR UPDATE OF status, amount_sats, fee_sats ON transactions
+FOR EACH ROW
+EXECUTE FUNCTION transaction_ledger_update();
+
+-- Post what has already succeeded
+SELECT post_ledger_entries(t) FROM transactions t WHERE t.status = 'succeeded' ORDER BY t.id;
@@ -0,0 +1,94 @@
+DROP TRIGGER IF EXISTS transaction_ledger_update ON transactions;
+
+-- Sub-sat amounts and fees are rounded down
+ALTER TABLE ledger_entries DISABLE TRIGGER ledger_entries_immutable;
+UPDATE ledger_entries SET amount_msat = amount_msat / 1000;
+ALTER TABLE ledger_entries RENAME COLUMN amount_msat TO amount_sats;
+ALTER TABLE ledger_entries ENABLE TRIGGER ledger_entries_immutable;
+
+ALTER TABLE transactions
+    DROP COLUMN amount_sats,
+    DROP COLUMN fee_sats;
+
+ALTER TABLE transactions
+    ADD COLUMN amount_sats BIGINT,
+    ADD COLUMN fee_sats BIGINT;
+
+UPDATE transactions SET
+    amount_sats = amount_msat / 1000,
+    fee_sats = fee_msat / 1000;
+
+ALTER TABLE transactions ALTER COLUMN amount_sats SET NOT NULL;
+
+ALTER TABLE transactions
+    DROP COLUMN amount_msat,
+    DROP COLUMN fee_msat;
+
+CREATE OR REPLACE FUNCTION post_ledger_entries(tx transactions)
+RETURNS VOID AS $$
+DECLARE
+    expected_amount BIGINT := 0;
+    expected_fee BIGINT := 0;
+    posted_amount BIGINT;
+    posted_fee BIGINT;
+    entry RECORD;
+BEGIN
+    IF tx.status = 'succeeded' THEN
+        expected_amount := tx.amount_sats;
+        IF tx.tx_type = 'payment' THEN
+            expected_fee := COALESCE(tx.fee_sats, 0);
+        END IF;
+    END IF;
+
+    SELECT
+        COALESCE(SUM(e.amount_sats) FILTER (WHERE e.entry_type <> 'payment_fee'), 0),
+        COALESCE(SUM(e.amount_sats) FILTER (WHERE e.entry_type = 'payment_fee'), 0)
+    INTO posted_amount, posted_fee
+    FROM ledger_entries e
+    WHERE e.transaction_id = tx.id
+      AND e.entry_type <> 'reversal'
+      AND NOT EXISTS (SELECT 1 FROM ledger_entries r WHERE r.reverses_entry_id = e.id);
+
+    IF posted_amount = expected_amount AND posted_fee = expected_fee THEN
+        RETURN;
+    END IF;
+
+    FOR entry IN
+        SELECT e.* FROM ledger_entries e
+        WHERE e.transaction_id = tx.id
+          AND e.entry_type <> 'reversal'
+          AND NOT EXISTS (SELECT 1 FROM ledger_entries r WHERE r.reverses_entry_id = e.id)
+    LOOP
+        INSERT INTO ledger_entries
+            (transaction_id, entry_type, debit_account_id, credit_account_id, amount_sats, reverses_entry_id)
+        VALUES
+            (tx.id, 'reversal', entry.credit_account_id, entry.debit_account_id, entry.amount_sats, entry.id);
+    END LOOP;
+
+    IF expected_amount > 0 THEN
+        IF tx.tx_type = 'invoice' THEN
+            INSERT INTO ledger_entries (transaction_id, entry_type, debit_account_id, credit_account_id, amount_sats)
+            VALUES (tx.id, 'invoice_settled',
+                    ledger_account(tx.node_id, 'lightning'), ledger_account(tx.node_id, 'received'),
+                    expected_amount);
+        ELSE
+            INSERT INTO ledger_entries (transaction_id, entry_type, debit_account_id, credit_account_id, amount_sats)
+            VALUES (tx.id, 'payment_amount',
+                    ledger_account(tx.node_id, 'sent'), ledger_account(tx.node_id, 'lightning'),
+                    expected_amount);
+        END IF;
+    END IF;
+
+    IF expected_fee > 0 THEN
+        INSERT INTO ledger_entries (transaction_id, entry_type, debit_account_id, credit_account_id, amount_sats)
+        VALUES (tx.id, 'payment_fee',
+                ledger_account(tx.node_id, 'fees'), ledger_account(tx.node_id, 'lightning'),
+                expected_fee);
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER transaction_ledger_update
+AFTER INSERT OR UPDATE OF status, amount_sats, fee_sats ON transactions
+FOR EACH ROW
+EXECUTE FUNCTION transaction_ledger_update();
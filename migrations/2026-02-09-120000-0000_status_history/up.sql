@@ -0,0 +1,26 @@
+-- Every status a transaction went through, and what moved it there.
+-- `from_status` is NULL for the row's first status.
+CREATE TABLE transaction_status_history (
+    id BIGSERIAL PRIMARY KEY,
+    transaction_id BIGINT NOT NULL REFERENCES transactions(id),
+    from_status VARCHAR(20),
+    to_status VARCHAR(20) NOT NULL,
+    source VARCHAR(32) NOT NULL CHECK (source IN (
+        'api',
+        'invoice_subscription',
+        'invoice_sync',
+        'payment_update',
+        'payment_reconciliation',
+        'migration'
+    )),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_status_history_transaction ON transaction_status_history(transaction_id, created_at);
+
+-- Earlier transitions were not recorded; keep what is known
+INSERT INTO transaction_status_history (transaction_id, from_status, to_status, source, created_at)
+SELECT id, NULL, 'pending', 'migration', created_at FROM transactions;
+
+INSERT INTO transaction_status_history (transaction_id, from_status, to_status, source, created_at)
+SELECT id, 'pending', status, 'migration', updated_at FROM transactions WHERE status <> 'pending';
@@ -0,0 +1,38 @@
+DROP TRIGGER IF EXISTS transaction_ledger_update ON transactions;
+
+ALTER TABLE transaction_status_history
+    ALTER COLUMN from_status TYPE VARCHAR(20) USING from_status::TEXT,
+    ALTER COLUMN to_status TYPE VARCHAR(20) USING to_status::TEXT,
+    ALTER COLUMN source TYPE VARCHAR(32) USING source::TEXT;
+
+-- Sources added to the enum later are kept; only new rows are checked
+ALTER TABLE transaction_status_history
+    ADD CONSTRAINT transaction_status_history_source_check CHECK (source IN (
+        'api',
+        'invoice_subscription',
+        'invoice_sync',
+        'payment_update',
+        'payment_reconciliation',
+        'migration'
+    )) NOT VALID;
+
+ALTER TABLE transactions ALTER COLUMN status DROP DEFAULT;
+
+ALTER TABLE transactions
+    ALTER COLUMN tx_type TYPE VARCHAR(20) USING tx_type::TEXT,
+    ALTER COLUMN status TYPE VARCHAR(20) USING status::TEXT;
+
+ALTER TABLE transactions
+    ALTER COLUMN status SET DEFAULT 'pending',
+    ADD CONSTRAINT transactions_tx_type_check CHECK (tx_type IN ('invoice', 'payment')),
+    ADD CONSTRAINT transactions_status_check
+        CHECK (status IN ('pending', 'accepted', 'succeeded', 'failed', 'expired'));
+
+DROP TYPE IF EXISTS transition_source;
+DROP TYPE IF EXISTS tx_status;
+DROP TYPE IF EXISTS tx_type;
+
+CREATE TRIGGER transaction_ledger_update
+AFTER INSERT OR UPDATE OF status, amount_msat, fee_msat ON transactions
+FOR EACH ROW
+EXECUTE FUNCTION transaction_ledger_update();
@@ -0,0 +1,39 @@
+-- Transaction types, statuses and transition sources become Postgres enums
+-- instead of checked VARCHARs
+CREATE TYPE tx_type AS ENUM ('invoice', 'payment');
+CREATE TYPE tx_status AS ENUM ('pending', 'accepted', 'succeeded', 'failed', 'expired');
+CREATE TYPE transition_source AS ENUM (
+    'api',
+    'invoice_subscription',
+    'invoice_sync',
+    'payment_update',
+    'payment_reconciliation',
+    'migration'
+);
+
+-- A column in a trigger's column list cannot change type
+DROP TRIGGER IF EXISTS transaction_ledger_update ON transactions;
+
+ALTER TABLE transactions
+    DROP CONSTRAINT IF EXISTS transactions_tx_type_check,
+    DROP CONSTRAINT IF EXISTS transactions_status_check,
+    ALTER COLUMN status DROP DEFAULT;
+
+ALTER TABLE transactions
+    ALTER COLUMN tx_type TYPE tx_type USING tx_type::tx_type,
+    ALTER COLUMN status TYPE tx_status USING status::tx_status;
+
+ALTER TABLE transactions ALTER COLUMN status SET DEFAULT 'pending';
+
+ALTER TABLE transaction_status_history
+    DROP CONSTRAINT IF EXISTS transaction_status_history_source_check;
+
+ALTER TABLE transaction_status_history
+    ALTER COLUMN from_status TYPE tx_status USING from_status::tx_status,
+    ALTER COLUMN to_status TYPE tx_status USING to_status::tx_status,
+    ALTER COLUMN source TYPE transition_source USING source::transition_source;
+
+CREATE TRIGGER transaction_ledger_update
+AFTER INSERT OR UPDATE OF status, amount_msat, fee_msat ON transactions
+FOR EACH ROW
+EXECUTE FUNCTION transaction_ledger_update();
@@ -0,0 +1,14 @@
+-- Responses of POST requests made with an `Idempotency-Key`, so a retry gets
+-- the first answer back instead of running the request again. A row without a
+-- response is a request still in progress.
+CREATE TABLE idempotency_keys (
+    endpoint VARCHAR(16) NOT NULL CHECK (endpoint IN ('invoice', 'payment', 'keysend')),
+    idempotency_key VARCHAR(255) NOT NULL,
+    -- SHA-256 of the request body, to refuse a key reused for another request
+    request_hash VARCHAR(64) NOT NULL,
+    response_status INTEGER,
+    response_body JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ,
+    PRIMARY KEY (endpoint, idempotency_key)
+);
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS idx_tx_description_trgm;
+DROP INDEX IF EXISTS idx_tx_amount_msat;
+DROP INDEX IF EXISTS idx_tx_node_created_id;
+DROP INDEX IF EXISTS idx_tx_created_id;
+CREATE INDEX idx_tx_created ON transactions(created_at DESC);
@@ -0,0 +1,10 @@
+-- Transactions are listed newest first and paged by (created_at, id), so
+-- the ordering index carries the id as a tie-breaker
+DROP INDEX IF EXISTS idx_tx_created;
+CREATE INDEX idx_tx_created_id ON transactions(created_at DESC, id DESC);
+CREATE INDEX idx_tx_node_created_id ON transactions(node_id, created_at DESC, id DESC);
+CREATE INDEX idx_tx_amount_msat ON transactions(amount_msat);
+
+-- Description search matches substrings, which only a trigram index serves
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX idx_tx_description_trgm ON transactions USING GIN (description gin_trgm_ops);
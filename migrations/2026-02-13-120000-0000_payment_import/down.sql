@@ -0,0 +1,7 @@
+DROP TABLE IF EXISTS payment_sync_state;
+
+-- Enum values cannot be dropped; `payment_import` history rows stay as they are
+
+ALTER TABLE transactions DROP COLUMN IF EXISTS origin;
+
+DROP TYPE IF EXISTS tx_origin;
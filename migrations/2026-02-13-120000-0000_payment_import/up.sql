@@ -0,0 +1,14 @@
+-- Payments found on a send node that THOrs did not make (e.g. sent with
+-- lncli) are imported as `external` transactions
+CREATE TYPE tx_origin AS ENUM ('thors', 'external');
+
+ALTER TABLE transactions ADD COLUMN origin tx_origin NOT NULL DEFAULT 'thors';
+
+ALTER TYPE transition_source ADD VALUE IF NOT EXISTS 'payment_import';
+
+-- Last payment index imported from each sending node
+CREATE TABLE payment_sync_state (
+    node_id VARCHAR(66) PRIMARY KEY,
+    payment_index BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
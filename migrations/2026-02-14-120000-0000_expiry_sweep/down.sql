@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_tx_pending_expires_at;
+
+-- Enum values cannot be dropped; `expiry_sweep` history rows stay as they are
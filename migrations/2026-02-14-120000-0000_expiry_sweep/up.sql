@@ -0,0 +1,6 @@
+-- Overdue invoices are expired by a periodic sweep
+ALTER TYPE transition_source ADD VALUE IF NOT EXISTS 'expiry_sweep';
+
+-- Pending invoices are found by expiry
+CREATE INDEX idx_tx_pending_expires_at ON transactions(expires_at)
+    WHERE status = 'pending' AND tx_type = 'invoice';
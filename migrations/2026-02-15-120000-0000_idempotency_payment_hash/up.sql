@@ -0,0 +1,6 @@
+-- The payment a request under a key sent. Once it is set the key is never
+-- released: a retry answers from the payment instead of sending it again.
+ALTER TABLE idempotency_keys ADD COLUMN payment_hash VARCHAR(64);
+
+-- Keys older than IDEMPOTENCY_KEY_TTL_SECS are pruned
+CREATE INDEX idx_idempotency_keys_created_at ON idempotency_keys(created_at);
@@ -0,0 +1,3 @@
+DROP TRIGGER IF EXISTS transaction_settled_at ON transactions;
+DROP FUNCTION IF EXISTS set_settled_at();
+ALTER TABLE transactions DROP COLUMN IF EXISTS settled_at;
@@ -0,0 +1,30 @@
+-- When a transaction succeeded. Unlike `updated_at` it never moves once set,
+-- so exports can date settled transactions by it.
+ALTER TABLE transactions ADD COLUMN settled_at TIMESTAMPTZ;
+
+-- Settled before this column existed: the first transition to succeeded,
+-- or the last update for rows that predate the status history
+UPDATE transactions t
+SET settled_at = COALESCE(
+    (SELECT MIN(h.created_at)
+     FROM transaction_status_history h
+     WHERE h.transaction_id = t.id AND h.to_status = 'succeeded'),
+    t.updated_at
+)
+WHERE t.status = 'succeeded';
+
+-- Rows that succeed without a settle time from LND are stamped with now
+CREATE OR REPLACE FUNCTION set_settled_at()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.status = 'succeeded' AND NEW.settled_at IS NULL THEN
+        NEW.settled_at := NOW();
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER transaction_settled_at
+BEFORE INSERT OR UPDATE OF status ON transactions
+FOR EACH ROW
+EXECUTE FUNCTION set_settled_at();
@@ -0,0 +1,2 @@
+ALTER TABLE invoice_sync_state DROP COLUMN IF EXISTS full_sync_complete;
+ALTER TABLE invoice_sync_state DROP COLUMN IF EXISTS sync_add_index;
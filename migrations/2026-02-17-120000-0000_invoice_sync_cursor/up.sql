@@ -0,0 +1,9 @@
+-- The paged invoice sync keeps its own resume point. The invoice subscription
+-- moves `add_index` past invoices a full load has not listed yet, so resuming
+-- an interrupted load from there would skip them for good.
+--
+-- Whether earlier full loads finished is not recorded, so every node loads
+-- its invoices once more.
+ALTER TABLE invoice_sync_state
+    ADD COLUMN sync_add_index BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN full_sync_complete BOOLEAN NOT NULL DEFAULT FALSE;
@@ -1,7 +1,10 @@
 use leptos::prelude::*;
 use leptos_meta::*;
 
+use crate::components::functions::AmountUnit;
 use crate::components::*;
+use crate::models::NodeRole;
+use crate::server::functions::get_node_status_fn;
 
 /// SSR shell: provides the full HTML document structure for server-side rendering.
 /// This is called by `leptos_routes_with_context` in main.rs.
@@ -47,6 +50,20 @@ fn HomePage() -> impl IntoView {
         set_clear_send_nonce.update(|value| *value += 1);
     });
 
+    // Only offer the panels some configured node can serve
+    let nodes = LocalResource::new(|| async { get_node_status_fn().await.ok() });
+    let has_role = move |can: fn(&NodeRole) -> bool| {
+        nodes
+            .get()
+            .flatten()
+            .is_some_and(|nodes| nodes.iter().any(|node| can(&node.role)))
+    };
+    let can_receive = move || has_role(NodeRole::can_receive);
+    let can_send = move || has_role(NodeRole::can_send);
+
+    // Unit every amount is shown in, switched from the balance panel
+    provide_context(RwSignal::new(AmountUnit::default()));
+
     view! {
         <div class="container">
             <header class="app-header">
@@ -55,19 +72,25 @@ fn HomePage() -> impl IntoView {
             </header>
 
             <main class="app-main">
+                <NodeStatusBanner/>
+
                 <div class="top-row">
                     <BalanceDisplay/>
                 </div>
 
                 <div class="panels-row">
-                    <ReceivePanel
-                        clear_nonce=clear_receive_nonce
-                        on_create_invoice=clear_send
-                    />
-                    <SendPanel
-                        clear_nonce=clear_send_nonce
-                        on_pay_invoice=clear_receive
-                    />
+                    <Show when=can_receive>
+                        <ReceivePanel
+                            clear_nonce=clear_receive_nonce
+                            on_create_invoice=clear_send
+                        />
+                    </Show>
+                    <Show when=can_send>
+                        <SendPanel
+                            clear_nonce=clear_send_nonce
+                            on_pay_invoice=clear_receive
+                        />
+                    </Show>
                 </div>
 
                 <div class="bottom-row">
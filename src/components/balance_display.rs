@@ -1,3 +1,4 @@
+use crate::components::functions::{format_msat, AmountUnit};
 use crate::components::use_websocket_events;
 use crate::server::functions::get_balance_fn;
 use leptos::prelude::*;
@@ -6,17 +7,29 @@ use leptos::prelude::*;
 #[component]
 pub fn BalanceDisplay() -> impl IntoView {
     let ws_event = use_websocket_events();
+    let unit = expect_context::<RwSignal<AmountUnit>>();
 
     // LocalResource for WASM compatibility (not Send)
     // Refetch when WebSocket events arrive
     let balance = LocalResource::new(move || {
         let _trigger = ws_event.get(); // Trigger refetch on WS event
-        async move { get_balance_fn().await.ok() }
+        async move { get_balance_fn(None).await.ok() }
     });
 
     view! {
         <div class="panel balance-display">
-            <h2>"Balance"</h2>
+            <div class="balance-header">
+                <h2>"Balance"</h2>
+                <button
+                    class="btn btn-secondary"
+                    on:click=move |_| unit.update(|unit| *unit = unit.toggled())
+                >
+                    {move || match unit.get() {
+                        AmountUnit::Sats => "Show msats",
+                        AmountUnit::Msats => "Show sats",
+                    }}
+                </button>
+            </div>
 
             <Transition fallback=|| view! { <p>"Loading balance..."</p> }>
                 {move || Suspend::new(async move {
@@ -27,21 +40,28 @@ pub fn BalanceDisplay() -> impl IntoView {
                                     <div class="balance-item">
                                         <span class="balance-label">"Total Balance"</span>
                                         <span class="balance-value balance-total">
-                                            {bal.total_balance}" sats"
+                                            {move || format_msat(bal.total_balance_msat, unit.get())}
                                         </span>
                                     </div>
 
                                     <div class="balance-item">
                                         <span class="balance-label">"Received"</span>
                                         <span class="balance-value balance-received">
-                                            ""{bal.received_sats}" sats"
+                                            {move || format_msat(bal.received_msat, unit.get())}
                                         </span>
                                     </div>
 
                                     <div class="balance-item">
                                         <span class="balance-label">"Paid"</span>
                                         <span class="balance-value balance-paid">
-                                            ""{bal.paid_sats}" sats"
+                                            {move || format_msat(bal.paid_msat, unit.get())}
+                                        </span>
+                                    </div>
+
+                                    <div class="balance-item">
+                                        <span class="balance-label">"Fees"</span>
+                                        <span class="balance-value balance-paid">
+                                            {move || format_msat(bal.fee_msat, unit.get())}
                                         </span>
                                     </div>
 
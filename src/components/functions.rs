@@ -86,6 +86,43 @@ pub(crate) fn format_amount(amount_msats: Option<u64>) -> String {
     }
 }
 
+/// Unit amounts are shown in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum AmountUnit {
+    #[default]
+    Sats,
+    Msats,
+}
+
+impl AmountUnit {
+    pub(crate) fn toggled(self) -> Self {
+        match self {
+            AmountUnit::Sats => AmountUnit::Msats,
+            AmountUnit::Msats => AmountUnit::Sats,
+        }
+    }
+}
+
+/// Format a millisatoshi amount in `unit`. Sats keep any sub-sat part as
+/// decimals (e.g. "1.5 sats"), so nothing is lost in either unit.
+pub(crate) fn format_msat(msat: i64, unit: AmountUnit) -> String {
+    match unit {
+        AmountUnit::Msats => format!("{} msats", msat),
+        AmountUnit::Sats if msat % 1000 == 0 => format!("{} sats", msat / 1000),
+        AmountUnit::Sats => {
+            let sign = if msat < 0 { "-" } else { "" };
+            let msat = msat.unsigned_abs();
+            let fraction = format!("{:03}", msat % 1000);
+            format!(
+                "{}{}.{} sats",
+                sign,
+                msat / 1000,
+                fraction.trim_end_matches('0')
+            )
+        }
+    }
+}
+
 /// Format seconds into a short human-readable duration (e.g. "1h 30m").
 pub(crate) fn format_expiry(seconds: u64) -> String {
     if seconds == 0 {
@@ -178,7 +215,7 @@ fn parse_amount_msats(hrp: &str) -> Result<Option<u64>, DecodeError> {
             .ok_or(DecodeError::AmountOverflow)?,
         Some('n') => value.checked_mul(100).ok_or(DecodeError::AmountOverflow)?,
         Some('p') => {
-            if value % 10 != 0 {
+            if !value.is_multiple_of(10) {
                 return Err(DecodeError::SubMillisatoshi);
             }
             value / 10
@@ -1,5 +1,6 @@
 pub mod balance_display;
 pub mod functions;
+pub mod node_status_banner;
 pub mod qr_code;
 pub mod receive_panel;
 pub mod send_panel;
@@ -8,6 +9,7 @@ pub mod use_websocket;
 
 // Re-export components
 pub use balance_display::BalanceDisplay;
+pub use node_status_banner::NodeStatusBanner;
 pub use qr_code::QrCode;
 pub use receive_panel::ReceivePanel;
 pub use send_panel::SendPanel;
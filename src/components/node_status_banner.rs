@@ -0,0 +1,55 @@
+use crate::components::use_websocket_events;
+use crate::dto::{InvoiceEvent, NodeHealth, NodeStatus};
+use crate::server::functions::get_node_status_fn;
+use leptos::prelude::*;
+
+/// Banner shown while any configured node cannot be reached.
+/// Starts from the server's view and follows `NodeHealthChanged` events.
+#[component]
+pub fn NodeStatusBanner() -> impl IntoView {
+    let (nodes, set_nodes) = signal(Vec::<NodeStatus>::new());
+
+    let initial = LocalResource::new(|| async { get_node_status_fn().await.ok() });
+    Effect::new(move |_| {
+        if let Some(Some(statuses)) = initial.get() {
+            set_nodes.set(statuses);
+        }
+    });
+
+    let ws_event = use_websocket_events();
+    Effect::new(move |_| {
+        if let Some(InvoiceEvent::NodeHealthChanged { node }) = ws_event.get() {
+            set_nodes.update(|nodes| {
+                match nodes.iter_mut().find(|known| known.node_id == node.node_id) {
+                    Some(known) => *known = node,
+                    None => nodes.push(node),
+                }
+            });
+        }
+    });
+
+    view! {
+        {move || {
+            nodes
+                .get()
+                .into_iter()
+                .filter_map(|node| {
+                    let (class, message) = match node.health {
+                        NodeHealth::Connected => return None,
+                        NodeHealth::Degraded => {
+                            ("node-banner node-banner-degraded", "is not responding reliably")
+                        }
+                        NodeHealth::Down => {
+                            ("node-banner node-banner-down", "is unreachable, reconnecting...")
+                        }
+                    };
+                    Some(view! {
+                        <div class=class>
+                            {format!("⚠ Node {} {}", node.name, message)}
+                        </div>
+                    })
+                })
+                .collect_view()
+        }}
+    }
+}
@@ -1,8 +1,10 @@
 use leptos::prelude::*;
 
-use crate::components::functions::format_expiry;
-use crate::components::QrCode;
-use crate::server::functions::create_invoice_fn;
+use crate::components::functions::{format_expiry, format_msat, AmountUnit};
+use crate::components::{use_websocket_events, QrCode};
+use crate::dto::CreateInvoiceRequest;
+use crate::models::TxStatus;
+use crate::server::functions::{cancel_invoice_fn, create_invoice_fn, get_invoice_defaults_fn};
 
 /// Panel for receiving Lightning payments (generating invoices)
 #[component]
@@ -12,25 +14,61 @@ pub fn ReceivePanel(
     /// Called when the user starts creating an invoice
     on_create_invoice: Callback<()>,
 ) -> impl IntoView {
-    let (created_amount_sats, set_created_amount_sats) = signal(None::<i64>);
+    let unit = expect_context::<RwSignal<AmountUnit>>();
+    let (created_amount_msat, set_created_amount_msat) = signal(None::<i64>);
     let (created_description, set_created_description) = signal(None::<String>);
     let (created_expiry_seconds, set_created_expiry_seconds) = signal(None::<u64>);
     let (amount, set_amount) = signal(String::new());
     let (description, set_description) = signal(String::new());
     let (invoice, set_invoice) = signal(String::new());
     let (payment_hash, set_payment_hash) = signal(None::<String>);
+    let (invoice_status, set_invoice_status) = signal(None::<TxStatus>);
+    let (cancelling, set_cancelling) = signal(false);
     let (loading, set_loading) = signal(false);
     let (error, set_error) = signal(None::<String>);
     let (copied, set_copied) = signal(false);
+    let (expiry, set_expiry) = signal(String::new());
+    let (cltv_expiry, set_cltv_expiry) = signal(String::new());
+    let (private, set_private) = signal(None::<bool>);
+    let (description_hash, set_description_hash) = signal(String::new());
+    let (fallback_addr, set_fallback_addr) = signal(String::new());
+
+    let defaults = LocalResource::new(|| async { get_invoice_defaults_fn().await.ok() });
 
     let has_invoice = move || !invoice.get().is_empty();
+    let is_open = move || {
+        matches!(
+            invoice_status.get(),
+            Some(TxStatus::Pending | TxStatus::Accepted)
+        )
+    };
+
+    // Follow the shown invoice as it gets paid, cancelled or expires
+    let ws_event = use_websocket_events();
+    Effect::new(move |_| {
+        if let Some(event) = ws_event.get() {
+            let Some(tx) = event.tx() else { return };
+            let shown = payment_hash.get_untracked();
+            if shown.as_deref() == Some(tx.payment_hash.as_str())
+                && tx.tx_type == crate::models::TxType::Invoice
+            {
+                set_invoice_status.set(Some(tx.status));
+            }
+        }
+    });
 
     let reset_panel = move || {
         set_amount.set(String::new());
         set_description.set(String::new());
+        set_expiry.set(String::new());
+        set_cltv_expiry.set(String::new());
+        set_private.set(None);
+        set_description_hash.set(String::new());
+        set_fallback_addr.set(String::new());
         set_invoice.set(String::new());
         set_payment_hash.set(None);
-        set_created_amount_sats.set(None);
+        set_invoice_status.set(None);
+        set_created_amount_msat.set(None);
         set_created_description.set(None);
         set_created_expiry_seconds.set(None);
         set_error.set(None);
@@ -47,14 +85,32 @@ pub fn ReceivePanel(
     });
 
     let on_submit = move |_| {
-        let amount_sats = match amount.get().parse::<i64>() {
-            Ok(amt) if amt > 0 => amt,
+        // An empty amount creates an invoice the payer can pay any amount to
+        let amount_sats = match optional_number::<i64>(&amount.get()) {
+            Ok(None) => 0,
+            Ok(Some(amt)) if amt >= 0 => amt,
             _ => {
                 set_error.set(Some("Invalid amount".to_string()));
                 return;
             }
         };
 
+        // Empty optional fields fall back to the server defaults
+        let expiry_secs = match optional_number::<i64>(&expiry.get()) {
+            Ok(value) => value,
+            Err(()) => {
+                set_error.set(Some("Invalid expiry".to_string()));
+                return;
+            }
+        };
+        let cltv = match optional_number::<u64>(&cltv_expiry.get()) {
+            Ok(value) => value,
+            Err(()) => {
+                set_error.set(Some("Invalid CLTV expiry".to_string()));
+                return;
+            }
+        };
+
         on_create_invoice.run(());
 
         let desc = if description.get().is_empty() {
@@ -62,29 +118,48 @@ pub fn ReceivePanel(
         } else {
             Some(description.get())
         };
+        let request = CreateInvoiceRequest {
+            amount_sats: Some(amount_sats),
+            amount_msat: None,
+            description: desc.clone(),
+            description_hash: Some(description_hash.get()).filter(|hash| !hash.is_empty()),
+            expiry_secs,
+            private: private.get(),
+            cltv_expiry: cltv,
+            fallback_addr: Some(fallback_addr.get()).filter(|addr| !addr.is_empty()),
+            hold: false,
+            node_id: None,
+        };
+        let shown_description = desc.clone().or_else(|| {
+            request
+                .description_hash
+                .clone()
+                .map(|hash| format!("Hash {}", hash))
+        });
 
         set_loading.set(true);
         set_error.set(None);
         set_invoice.set(String::new());
         set_payment_hash.set(None);
-        set_created_amount_sats.set(None);
+        set_invoice_status.set(None);
+        set_created_amount_msat.set(None);
         set_created_description.set(None);
         set_created_expiry_seconds.set(None);
         set_copied.set(false);
 
         leptos::task::spawn_local(async move {
-            let expiry_seconds = 3600u64;
-
-            match create_invoice_fn(amount_sats, desc.clone()).await {
+            match create_invoice_fn(request, None).await {
                 Ok(response) => {
                     set_invoice.set(response.payment_request);
                     set_payment_hash.set(Some(response.payment_hash));
-                    set_created_amount_sats.set(Some(amount_sats));
-                    set_created_description.set(desc.clone());
-                    set_created_expiry_seconds.set(Some(expiry_seconds));
+                    set_invoice_status.set(Some(TxStatus::Pending));
+                    set_created_amount_msat.set(Some(response.amount_msat));
+                    set_created_description.set(shown_description);
+                    set_created_expiry_seconds.set(Some(response.expiry_secs as u64));
                     set_error.set(None);
                     set_amount.set(String::new());
                     set_description.set(String::new());
+                    set_description_hash.set(String::new());
                 }
                 Err(e) => {
                     set_error.set(Some(format!("Error creating invoice: {}", e)));
@@ -94,6 +169,23 @@ pub fn ReceivePanel(
         });
     };
 
+    let cancel_invoice = move |_| {
+        let Some(hash) = payment_hash.get() else {
+            return;
+        };
+
+        set_cancelling.set(true);
+        set_error.set(None);
+
+        leptos::task::spawn_local(async move {
+            match cancel_invoice_fn(hash).await {
+                Ok(()) => set_invoice_status.set(Some(TxStatus::Expired)),
+                Err(e) => set_error.set(Some(format!("Error cancelling invoice: {}", e))),
+            }
+            set_cancelling.set(false);
+        });
+    };
+
     let copy_invoice = move |_| {
         #[cfg(not(feature = "ssr"))]
         {
@@ -119,7 +211,7 @@ pub fn ReceivePanel(
                     id="amount"
                     type="number"
                     class="input"
-                    placeholder="1000"
+                    placeholder="Leave empty for any amount"
                     prop:value=amount
                     on:input=move |ev| set_amount.set(event_target_value(&ev))
                 />
@@ -137,6 +229,88 @@ pub fn ReceivePanel(
                 />
             </div>
 
+            <details class="advanced-options">
+                <summary>"Advanced options"</summary>
+
+                <div class="form-group">
+                    <label for="expiry">"Expiry (seconds)"</label>
+                    <input
+                        id="expiry"
+                        type="number"
+                        min="1"
+                        class="input"
+                        placeholder=move || {
+                            defaults
+                                .get()
+                                .flatten()
+                                .map(|d| format!("Default: {}", d.expiry_secs))
+                                .unwrap_or_default()
+                        }
+                        prop:value=expiry
+                        on:input=move |ev| set_expiry.set(event_target_value(&ev))
+                    />
+                </div>
+
+                <div class="form-group">
+                    <label for="cltv_expiry">"Final CLTV Expiry (blocks)"</label>
+                    <input
+                        id="cltv_expiry"
+                        type="number"
+                        min="18"
+                        class="input"
+                        placeholder=move || {
+                            match defaults.get().flatten().map(|d| d.cltv_expiry) {
+                                Some(0) => "Default: node setting".to_string(),
+                                Some(blocks) => format!("Default: {}", blocks),
+                                None => String::new(),
+                            }
+                        }
+                        prop:value=cltv_expiry
+                        on:input=move |ev| set_cltv_expiry.set(event_target_value(&ev))
+                    />
+                </div>
+
+                <div class="form-group">
+                    <label for="description_hash">"Description Hash (replaces description)"</label>
+                    <input
+                        id="description_hash"
+                        type="text"
+                        class="input input-mono"
+                        placeholder="sha256 hex"
+                        prop:value=description_hash
+                        on:input=move |ev| set_description_hash.set(event_target_value(&ev))
+                    />
+                </div>
+
+                <div class="form-group">
+                    <label for="fallback_addr">"On-chain Fallback Address"</label>
+                    <input
+                        id="fallback_addr"
+                        type="text"
+                        class="input input-mono"
+                        placeholder="bc1..."
+                        prop:value=fallback_addr
+                        on:input=move |ev| set_fallback_addr.set(event_target_value(&ev))
+                    />
+                </div>
+
+                <div class="form-group checkbox-group">
+                    <label>
+                        <input
+                            type="checkbox"
+                            prop:checked=move || {
+                                private
+                                    .get()
+                                    .or_else(|| defaults.get().flatten().map(|d| d.private))
+                                    .unwrap_or(false)
+                            }
+                            on:change=move |ev| set_private.set(Some(event_target_checked(&ev)))
+                        />
+                        " Include private channel route hints"
+                    </label>
+                </div>
+            </details>
+
             <Show when=move || error.get().is_some()>
                 <div class="error-message">
                     {move || error.get().unwrap_or_default()}
@@ -153,11 +327,19 @@ pub fn ReceivePanel(
 
             <Show when=has_invoice>
                 <div class="invoice-result">
-                    <h3>"Invoice Created!"</h3>
+                    <h3>
+                        {move || match invoice_status.get() {
+                            Some(TxStatus::Succeeded) => "Invoice Paid!",
+                            Some(TxStatus::Expired) => "Invoice Cancelled",
+                            _ => "Invoice Created!",
+                        }}
+                    </h3>
 
-                    <div class="qr-container">
-                        <QrCode data=Signal::derive(move || invoice.get()) />
-                    </div>
+                    <Show when=is_open>
+                        <div class="qr-container">
+                            <QrCode data=Signal::derive(move || invoice.get()) />
+                        </div>
+                    </Show>
 
                     <div class="invoice-display">
                         <code class="invoice-string">
@@ -175,9 +357,15 @@ pub fn ReceivePanel(
                         <p>
                             <strong>"Amount: "</strong>
                             {move || {
-                                created_amount_sats
+                                created_amount_msat
                                     .get()
-                                    .map(|amt| format!("{} sats", amt))
+                                    .map(|amt| {
+                                        if amt == 0 {
+                                            "Any amount".to_string()
+                                        } else {
+                                            format_msat(amt, unit.get())
+                                        }
+                                    })
                                     .unwrap_or_else(|| "-".to_string())
                             }}
                         </p>
@@ -205,8 +393,27 @@ pub fn ReceivePanel(
                             </p>
                         </Show>
                     </div>
+
+                    <Show when=is_open>
+                        <button
+                            class="btn btn-secondary"
+                            on:click=cancel_invoice
+                            disabled=move || cancelling.get()
+                        >
+                            {move || if cancelling.get() { "Cancelling..." } else { "Cancel Invoice" }}
+                        </button>
+                    </Show>
                 </div>
             </Show>
         </div>
     }
 }
+
+/// Parse an optional numeric form field; an empty field is `Ok(None)`.
+fn optional_number<T: std::str::FromStr>(value: &str) -> Result<Option<T>, ()> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(|_| ())
+}
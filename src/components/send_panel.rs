@@ -1,12 +1,14 @@
 use leptos::prelude::*;
+use std::collections::BTreeMap;
 
 use crate::components::functions::{
-    decode_payment_request_local, format_amount, format_expiry, DecodedInvoice,
+    decode_payment_request_local, format_amount, format_expiry, format_msat, AmountUnit,
+    DecodedInvoice,
 };
-use crate::dto::PaymentResponse;
-use crate::server::functions::pay_invoice_fn;
+use crate::dto::{FeeLimit, KeysendRequest, PaymentResponse};
+use crate::server::functions::{get_fee_limit_fn, keysend_fn, pay_invoice_fn};
 
-/// Panel for sending Lightning payments (paying invoices)
+/// Panel for sending Lightning payments (paying invoices or keysend)
 #[component]
 pub fn SendPanel(
     /// Increment to clear the panel state (used by ReceivePanel)
@@ -14,15 +16,47 @@ pub fn SendPanel(
     /// Called when the user starts a payment
     on_pay_invoice: Callback<()>,
 ) -> impl IntoView {
+    let unit = expect_context::<RwSignal<AmountUnit>>();
     let (payment_request, set_payment_request) = signal(String::new());
     let (decoded_invoice, set_decoded_invoice) = signal(None::<DecodedInvoice>);
     let (decode_error, set_decode_error) = signal(None::<String>);
     let (payment_result, set_payment_result) = signal(None::<PaymentResponse>);
     let (loading, set_loading) = signal(false);
     let (error, set_error) = signal(None::<String>);
+    let (amount, set_amount) = signal(String::new());
+    let (max_fee, set_max_fee) = signal(String::new());
+    let (max_fee_unit, set_max_fee_unit) = signal("sats".to_string());
+    let (keysend_mode, set_keysend_mode) = signal(false);
+    let (destination, set_destination) = signal(String::new());
+    let (custom_records, set_custom_records) = signal(String::new());
+
+    let default_fee_limit = LocalResource::new(|| async { get_fee_limit_fn().await.ok() });
+
+    // The cap the next payment will use: the typed value, else the server default
+    let fee_limit = move || {
+        let value = max_fee.get();
+        if value.trim().is_empty() {
+            return Ok(default_fee_limit.get().flatten());
+        }
+        let limit = if max_fee_unit.get() == "percent" {
+            value.trim().parse::<f64>().ok().map(|p| (None, Some(p)))
+        } else {
+            value.trim().parse::<i64>().ok().map(|s| (Some(s), None))
+        };
+        let (sats, percent) = limit.ok_or_else(|| "Invalid fee limit".to_string())?;
+        FeeLimit::from_parts(sats, percent)
+    };
+
+    // Amountless invoices need the payer to choose the amount
+    let is_amountless =
+        move || decoded_invoice.with(|d| d.as_ref().is_some_and(|d| d.amount_msats.is_none()));
+    let needs_amount = move || keysend_mode.get() || is_amountless();
 
     let reset_panel = move || {
         set_payment_request.set(String::new());
+        set_amount.set(String::new());
+        set_destination.set(String::new());
+        set_custom_records.set(String::new());
         set_decoded_invoice.set(None);
         set_decode_error.set(None);
         set_payment_result.set(None);
@@ -61,11 +95,52 @@ pub fn SendPanel(
     };
 
     let on_submit = move |_| {
+        let keysend = keysend_mode.get();
         let pr = payment_request.get();
-        if pr.is_empty() {
+        let dest = destination.get().trim().to_string();
+        if !keysend && pr.is_empty() {
             set_error.set(Some("Please enter a payment request".to_string()));
             return;
         }
+        if keysend && dest.is_empty() {
+            set_error.set(Some("Please enter the recipient's node pubkey".to_string()));
+            return;
+        }
+
+        let records = match parse_custom_records(&custom_records.get()) {
+            Ok(records) => records,
+            Err(e) => {
+                set_error.set(Some(e));
+                return;
+            }
+        };
+
+        let amount_sats = if needs_amount() {
+            match amount.get().trim().parse::<i64>() {
+                Ok(amt) if amt > 0 => Some(amt),
+                _ => {
+                    set_error.set(Some("Enter the amount to pay".to_string()));
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        // An empty field leaves the choice to the server default
+        let (max_fee_sats, max_fee_percent) = if max_fee.get().trim().is_empty() {
+            (None, None)
+        } else {
+            match fee_limit() {
+                Ok(Some(FeeLimit::Sats(sats))) => (Some(sats), None),
+                Ok(Some(FeeLimit::Percent(percent))) => (None, Some(percent)),
+                Ok(None) => (None, None),
+                Err(e) => {
+                    set_error.set(Some(e));
+                    return;
+                }
+            }
+        };
 
         on_pay_invoice.run(());
 
@@ -73,11 +148,40 @@ pub fn SendPanel(
         set_error.set(None);
         set_payment_result.set(None);
         set_payment_request.set(String::new());
+        set_amount.set(String::new());
+        set_destination.set(String::new());
+        set_custom_records.set(String::new());
         set_decoded_invoice.set(None);
         set_decode_error.set(None);
 
         leptos::task::spawn_local(async move {
-            match pay_invoice_fn(pr).await {
+            let result = if keysend {
+                keysend_fn(
+                    KeysendRequest {
+                        destination: dest,
+                        amount_sats,
+                        amount_msat: None,
+                        custom_records: records,
+                        max_fee_sats,
+                        max_fee_percent,
+                        node_id: None,
+                    },
+                    None,
+                )
+                .await
+            } else {
+                pay_invoice_fn(
+                    pr,
+                    amount_sats,
+                    None,
+                    max_fee_sats,
+                    max_fee_percent,
+                    None,
+                    None,
+                )
+                .await
+            };
+            match result {
                 Ok(response) => {
                     set_payment_result.set(Some(response));
                     set_error.set(None);
@@ -95,17 +199,60 @@ pub fn SendPanel(
             <h2>"Send Payment"</h2>
 
             <div class="form-group">
-                <label for="payment_request">"Lightning Invoice"</label>
-                <textarea
-                    id="payment_request"
-                    class="input input-mono textarea-auto"
-                    rows="7"
-                    placeholder="lnbc..."
-                    prop:value=payment_request
-                    on:input=on_input
-                />
+                <label for="send_mode">"Pay To"</label>
+                <select
+                    id="send_mode"
+                    class="input"
+                    prop:value=move || if keysend_mode.get() { "keysend" } else { "invoice" }
+                    on:change=move |ev| {
+                        reset_panel();
+                        set_keysend_mode.set(event_target_value(&ev) == "keysend");
+                    }
+                >
+                    <option value="invoice">"Lightning invoice"</option>
+                    <option value="keysend">"Node pubkey (keysend)"</option>
+                </select>
             </div>
 
+            <Show when=move || !keysend_mode.get()>
+                <div class="form-group">
+                    <label for="payment_request">"Lightning Invoice"</label>
+                    <textarea
+                        id="payment_request"
+                        class="input input-mono textarea-auto"
+                        rows="7"
+                        placeholder="lnbc..."
+                        prop:value=payment_request
+                        on:input=on_input
+                    />
+                </div>
+            </Show>
+
+            <Show when=move || keysend_mode.get()>
+                <div class="form-group">
+                    <label for="destination">"Recipient Node Pubkey"</label>
+                    <input
+                        id="destination"
+                        type="text"
+                        class="input input-mono"
+                        placeholder="02..."
+                        prop:value=destination
+                        on:input=move |ev| set_destination.set(event_target_value(&ev))
+                    />
+                </div>
+                <div class="form-group">
+                    <label for="custom_records">"Custom Records (optional)"</label>
+                    <textarea
+                        id="custom_records"
+                        class="input input-mono"
+                        rows="3"
+                        placeholder="65537=68656c6c6f"
+                        prop:value=custom_records
+                        on:input=move |ev| set_custom_records.set(event_target_value(&ev))
+                    />
+                </div>
+            </Show>
+
             <Show when=move || decode_error.get().is_some()>
                 <div class="error-message">
                     {move || decode_error.get().unwrap_or_default()}
@@ -117,7 +264,7 @@ pub fn SendPanel(
                     <h3>"Invoice Details"</h3>
                     {move || {
                         decoded_invoice.get().map(|decoded| {
-                            let amount = format_amount(decoded.amount_msats);
+                            let amount_label = format_amount(decoded.amount_msats);
                             let description = decoded
                                 .description
                                 .unwrap_or_else(|| "No message".to_string());
@@ -126,7 +273,7 @@ pub fn SendPanel(
                                 <div class="invoice-details">
                                     <p>
                                         <strong>"Amount: "</strong>
-                                        {amount}
+                                        {amount_label}
                                     </p>
                                     <p>
                                         <strong>"Message: "</strong>
@@ -136,6 +283,27 @@ pub fn SendPanel(
                                         <strong>"Expiry: "</strong>
                                         {format!("{} ({}s)", expiry, decoded.expiry_seconds)}
                                     </p>
+                                    <p>
+                                        <strong>"Max Fee: "</strong>
+                                        {move || {
+                                            let amount_msat = decoded
+                                                .amount_msats
+                                                .map(|msat| msat as i64)
+                                                .unwrap_or_else(|| {
+                                                    amount.get().trim().parse::<i64>().unwrap_or(0)
+                                                        * 1000
+                                                });
+                                            match fee_limit() {
+                                                Ok(Some(limit)) => format!(
+                                                    "{} sats ({})",
+                                                    limit.limit_msat(amount_msat) / 1000,
+                                                    limit,
+                                                ),
+                                                Ok(None) => "Server default".to_string(),
+                                                Err(e) => e,
+                                            }
+                                        }}
+                                    </p>
                                 </div>
                             }
                         })
@@ -143,6 +311,56 @@ pub fn SendPanel(
                 </div>
             </Show>
 
+            <Show when=needs_amount>
+                <div class="form-group">
+                    <label for="pay_amount">"Amount to Pay (sats)"</label>
+                    <input
+                        id="pay_amount"
+                        type="number"
+                        min="1"
+                        class="input"
+                        placeholder=move || {
+                            if keysend_mode.get() {
+                                "Amount to send"
+                            } else {
+                                "This invoice accepts any amount"
+                            }
+                        }
+                        prop:value=amount
+                        on:input=move |ev| set_amount.set(event_target_value(&ev))
+                    />
+                </div>
+            </Show>
+
+            <div class="form-group">
+                <label for="max_fee">"Max Routing Fee (optional)"</label>
+                <div class="input-row">
+                    <input
+                        id="max_fee"
+                        type="number"
+                        min="0"
+                        class="input"
+                        placeholder=move || {
+                            default_fee_limit
+                                .get()
+                                .flatten()
+                                .map(|limit| format!("Default: {}", limit))
+                                .unwrap_or_default()
+                        }
+                        prop:value=max_fee
+                        on:input=move |ev| set_max_fee.set(event_target_value(&ev))
+                    />
+                    <select
+                        class="input input-unit"
+                        prop:value=max_fee_unit
+                        on:change=move |ev| set_max_fee_unit.set(event_target_value(&ev))
+                    >
+                        <option value="sats">"sats"</option>
+                        <option value="percent">"%"</option>
+                    </select>
+                </div>
+            </div>
+
             <Show when=move || error.get().is_some()>
                 <div class="error-message">
                     {move || error.get().unwrap_or_default()}
@@ -154,7 +372,11 @@ pub fn SendPanel(
                 on:click=on_submit
                 disabled=move || loading.get()
             >
-                {move || if loading.get() { "Paying..." } else { "Pay Invoice" }}
+                {move || match (loading.get(), keysend_mode.get()) {
+                    (true, _) => "Paying...",
+                    (false, true) => "Send Keysend",
+                    (false, false) => "Pay Invoice",
+                }}
             </button>
 
             <Show when=move || payment_result.get().is_some()>
@@ -165,7 +387,11 @@ pub fn SendPanel(
                             <div class="payment-details">
                                 <p>
                                     <strong>"Amount: "</strong>
-                                    {result.amount_sats}" sats"
+                                    {format_msat(result.amount_msat, unit.get())}
+                                </p>
+                                <p>
+                                    <strong>"Fee: "</strong>
+                                    {format_msat(result.fee_msat, unit.get())}
                                 </p>
                                 <p>
                                     <strong>"Payment Hash: "</strong>
@@ -183,3 +409,22 @@ pub fn SendPanel(
         </div>
     }
 }
+
+/// Parse keysend custom records, one `type=hexvalue` pair per line.
+fn parse_custom_records(input: &str) -> Result<BTreeMap<u64, String>, String> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (record_type, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid custom record \"{}\", expected type=hex", line))?;
+            let record_type = record_type
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid custom record type \"{}\"", record_type.trim()))?;
+            Ok((record_type, value.trim().to_string()))
+        })
+        .collect()
+}
@@ -1,21 +1,28 @@
+use crate::components::functions::{format_msat, AmountUnit};
 #[cfg(not(feature = "ssr"))]
 use crate::components::use_websocket_events;
+#[cfg(not(feature = "ssr"))]
+use crate::dto::TransactionFilter;
 use crate::models::Transaction;
 use leptos::prelude::*;
 
-#[cfg(not(feature = "ssr"))]
-use crate::dto::InvoiceEvent;
 #[cfg(not(feature = "ssr"))]
 use crate::server::functions::get_transactions_fn;
 
 /// Component to display transaction history with real-time updates.
-/// Loads the full list once on mount, then reactively updates individual
-/// entries when WebSocket events arrive (no full refetch needed).
+/// Loads the newest page once on mount, then reactively updates individual
+/// entries when WebSocket events arrive (no full refetch needed). Older
+/// pages are appended on request.
 #[component]
 pub fn TransactionList() -> impl IntoView {
     let (transactions, _set_transactions) = signal(Vec::<Transaction>::new());
     let (loading, _set_loading) = signal(true);
+    let (next_cursor, _set_next_cursor) = signal(None::<String>);
+    let (total_count, _set_total_count) = signal(0_i64);
+    let (loading_more, _set_loading_more) = signal(false);
     let (expanded_id, set_expanded_id) = signal(None::<i64>);
+    let (export_format, set_export_format) = signal("csv".to_string());
+    let unit = expect_context::<RwSignal<AmountUnit>>();
 
     // Load initial transactions on mount
     #[cfg(not(feature = "ssr"))]
@@ -23,6 +30,12 @@ pub fn TransactionList() -> impl IntoView {
     #[cfg(not(feature = "ssr"))]
     let set_loading = _set_loading;
     #[cfg(not(feature = "ssr"))]
+    let set_next_cursor = _set_next_cursor;
+    #[cfg(not(feature = "ssr"))]
+    let set_total_count = _set_total_count;
+    #[cfg(not(feature = "ssr"))]
+    let set_loading_more = _set_loading_more;
+    #[cfg(not(feature = "ssr"))]
     let ws_event = use_websocket_events();
 
     #[cfg(not(feature = "ssr"))]
@@ -30,36 +43,62 @@ pub fn TransactionList() -> impl IntoView {
         let set_transactions = set_transactions.clone();
         let set_loading = set_loading.clone();
         leptos::task::spawn_local(async move {
-            match get_transactions_fn(Some(50), Some(0)).await {
-                Ok(txs) => set_transactions.set(txs),
+            match get_transactions_fn(TransactionFilter::default(), None, None).await {
+                Ok(page) => {
+                    set_transactions.set(page.transactions);
+                    set_next_cursor.set(page.next_cursor);
+                    set_total_count.set(page.total_count);
+                }
                 Err(_) => set_transactions.set(Vec::new()),
             }
             set_loading.set(false);
         });
     }
 
+    // Append the next older page; the cursor keeps it from overlapping rows
+    // that arrived since the first page
+    let load_more = move |_| {
+        #[cfg(not(feature = "ssr"))]
+        {
+            let Some(cursor) = next_cursor.get_untracked() else {
+                return;
+            };
+            set_loading_more.set(true);
+            leptos::task::spawn_local(async move {
+                if let Ok(page) =
+                    get_transactions_fn(TransactionFilter::default(), Some(cursor), None).await
+                {
+                    set_transactions.update(|txs| {
+                        for tx in page.transactions {
+                            if !txs.iter().any(|t| t.id == tx.id) {
+                                txs.push(tx);
+                            }
+                        }
+                    });
+                    set_next_cursor.set(page.next_cursor);
+                    set_total_count.set(page.total_count);
+                }
+                set_loading_more.set(false);
+            });
+        }
+    };
+
     // React to WebSocket events: update the list in-place
     #[cfg(not(feature = "ssr"))]
     {
         let set_transactions = set_transactions.clone();
         Effect::new(move |_| {
-            if let Some(event) = ws_event.get() {
-                let tx = match &event {
-                    InvoiceEvent::InvoiceCreated { tx } => tx.clone(),
-                    InvoiceEvent::InvoiceSettled { tx } => tx.clone(),
-                    InvoiceEvent::InvoiceExpired { tx } => tx.clone(),
-                    InvoiceEvent::PaymentSucceeded { tx } => tx.clone(),
-                };
-
-                let tx_type = tx.tx_type();
+            if let Some(tx) = ws_event.get().and_then(|event| event.tx().cloned()) {
+                let tx_type = tx.tx_type;
                 set_transactions.update(|txs| {
                     if let Some(existing) = txs
                         .iter_mut()
-                        .find(|t| t.payment_hash == tx.payment_hash && t.tx_type() == tx_type)
+                        .find(|t| t.payment_hash == tx.payment_hash && t.tx_type == tx_type)
                     {
                         *existing = tx;
                     } else {
                         txs.insert(0, tx);
+                        set_total_count.update(|count| *count += 1);
                     }
                 });
             }
@@ -68,7 +107,29 @@ pub fn TransactionList() -> impl IntoView {
 
     view! {
         <div class="panel transaction-list">
-            <h2>"Transaction History"</h2>
+            <div class="tx-header">
+                <h2>"Transaction History"</h2>
+                <div class="tx-export">
+                    <select
+                        class="input"
+                        aria-label="Export format"
+                        prop:value=move || export_format.get()
+                        on:change=move |ev| set_export_format.set(event_target_value(&ev))
+                    >
+                        <option value="csv">"CSV"</option>
+                        <option value="jsonl">"JSON Lines"</option>
+                        <option value="beancount">"Beancount"</option>
+                    </select>
+                    // Streamed by the server straight into a download
+                    <a
+                        class="btn btn-secondary"
+                        href=move || format!("/api/transactions/export?format={}", export_format.get())
+                        download
+                    >
+                        "Export"
+                    </a>
+                </div>
+            </div>
 
             {move || {
                 if loading.get() {
@@ -93,22 +154,40 @@ pub fn TransactionList() -> impl IntoView {
                                     key=|tx| tx.id
                                     children=move |tx: Transaction| {
                                         let tx_id = tx.id;
-                                        let tx_type = tx.tx_type();
-                                        let status = tx.status();
+                                        let tx_type = tx.tx_type;
+                                        let status = tx.status;
+                                        let external = tx.origin == crate::models::TxOrigin::External;
                                         let created_at = tx.created_at.format("%Y-%m-%d %H:%M").to_string();
                                         let created_at_full = tx.created_at.format("%Y-%m-%d %H:%M:%S").to_string();
                                         let updated_at_full = tx.updated_at.format("%Y-%m-%d %H:%M:%S").to_string();
                                         let description = tx.description.clone().unwrap_or_else(|| "-".to_string());
                                         let preimage = tx.preimage.clone().unwrap_or_else(|| "-".to_string());
-                                        let fee_sats = tx
-                                            .fee_sats
-                                            .map(|fee| format!("{} sats", fee))
-                                            .unwrap_or_else(|| "-".to_string());
+                                        let amount_msat = tx.amount_msat;
+                                        let fee_msat = tx.fee_msat;
+                                        let fee = move || {
+                                            fee_msat
+                                                .map(|fee| format_msat(fee, unit.get()))
+                                                .unwrap_or_else(|| "-".to_string())
+                                        };
                                         let failure_reason = tx.failure_reason.clone().unwrap_or_else(|| "-".to_string());
+                                        let failure_code = tx.failure_code.clone().unwrap_or_else(|| "-".to_string());
+                                        let payment_status = tx
+                                            .payment_status()
+                                            .map(|status| match status {
+                                                crate::models::PaymentStatus::InFlight => "In flight",
+                                                crate::models::PaymentStatus::Succeeded => "Succeeded",
+                                                crate::models::PaymentStatus::Failed => "Failed",
+                                            })
+                                            .unwrap_or("-");
+                                        let htlc_attempts = tx.htlc_attempts;
                                         let expires_at = tx
                                             .expires_at
                                             .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
                                             .unwrap_or_else(|| "-".to_string());
+                                        let settled_at = tx
+                                            .settled_at
+                                            .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                                            .unwrap_or_else(|| "-".to_string());
 
                                         let toggle_row = Callback::new(move |_| {
                                             set_expanded_id.update(|current| {
@@ -133,19 +212,26 @@ pub fn TransactionList() -> impl IntoView {
                                                                 crate::models::TxType::Payment => "Payment",
                                                             }}
                                                         </span>
+                                                        {external.then(|| view! {
+                                                            <span class="badge badge-external" title="Made outside THOrs">
+                                                                "External"
+                                                            </span>
+                                                        })}
                                                     </td>
                                                     <td class="amount">
-                                                        {tx.amount_sats}" sats"
+                                                        {move || format_msat(amount_msat, unit.get())}
                                                     </td>
                                                     <td>
                                                         <span class={format!("badge badge-{}", match status {
                                                             crate::models::TxStatus::Pending => "pending",
+                                                            crate::models::TxStatus::Accepted => "accepted",
                                                             crate::models::TxStatus::Succeeded => "success",
                                                             crate::models::TxStatus::Failed => "error",
                                                             crate::models::TxStatus::Expired => "expired",
                                                         })}>
                                                             {match status {
                                                                 crate::models::TxStatus::Pending => "Pending",
+                                                                crate::models::TxStatus::Accepted => "Accepted",
                                                                 crate::models::TxStatus::Succeeded => "Succeeded",
                                                                 crate::models::TxStatus::Failed => "Failed",
                                                                 crate::models::TxStatus::Expired => "Expired",
@@ -176,22 +262,29 @@ pub fn TransactionList() -> impl IntoView {
                                                                 <p><strong>"Status: "</strong>{
                                                                     match status {
                                                                         crate::models::TxStatus::Pending => "Pending",
+                                                                        crate::models::TxStatus::Accepted => "Accepted",
                                                                         crate::models::TxStatus::Succeeded => "Succeeded",
                                                                         crate::models::TxStatus::Failed => "Failed",
                                                                         crate::models::TxStatus::Expired => "Expired",
                                                                     }
                                                                 }</p>
-                                                                <p><strong>"Amount: "</strong>{tx.amount_sats}" sats"</p>
+                                                                <p><strong>"Amount: "</strong>{move || format_msat(amount_msat, unit.get())}</p>
                                                                 <p><strong>"Description: "</strong>{description.clone()}</p>
                                                                 <p><strong>"Payment Hash: "</strong><code>{tx.payment_hash.clone()}</code></p>
                                                                 <p><strong>"Payment Request: "</strong><code>{tx.payment_request.clone()}</code></p>
                                                                 <p><strong>"Preimage: "</strong><code>{preimage.clone()}</code></p>
-                                                                <p><strong>"Fee: "</strong>{fee_sats.clone()}</p>
+                                                                <p><strong>"Fee: "</strong>{fee}</p>
                                                                 <p><strong>"Failure Reason: "</strong>{failure_reason.clone()}</p>
+                                                                {(tx_type == crate::models::TxType::Payment).then(|| view! {
+                                                                    <p><strong>"Router Status: "</strong>{payment_status}</p>
+                                                                    <p><strong>"Failure Code: "</strong>{failure_code.clone()}</p>
+                                                                    <p><strong>"HTLC Attempts: "</strong>{htlc_attempts}</p>
+                                                                })}
                                                                 <p><strong>"Expires At (UTC): "</strong>{expires_at.clone()}</p>
                                                                 <p><strong>"Node ID: "</strong><code>{tx.node_id.clone()}</code></p>
                                                                 <p><strong>"Created At (UTC): "</strong>{created_at_full.clone()}</p>
                                                                 <p><strong>"Updated At (UTC): "</strong>{updated_at_full.clone()}</p>
+                                                                <p><strong>"Settled At (UTC): "</strong>{settled_at.clone()}</p>
                                                                 <button
                                                                         class="btn btn-secondary btn-inline"
                                                                         on:click=move |_| toggle_row.run(())
@@ -209,6 +302,21 @@ pub fn TransactionList() -> impl IntoView {
                                 />
                             </tbody>
                         </table>
+                        <div class="tx-footer">
+                            <span class="tx-count">
+                                {move || format!("Showing {} of {}", transactions.get().len(), total_count.get())}
+                            </span>
+                            <Show when=move || next_cursor.get().is_some()>
+                                <button
+                                    class="btn btn-secondary"
+                                    on:click=load_more
+                                    disabled=move || loading_more.get()
+                                    type="button"
+                                >
+                                    {move || if loading_more.get() { "Loading..." } else { "Load more" }}
+                                </button>
+                            </Show>
+                        </div>
                     }.into_any()
                 }
             }}
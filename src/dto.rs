@@ -1,12 +1,60 @@
-use crate::models::Transaction;
+use crate::models::{NodeRole, Transaction, TransitionSource, TxOrigin, TxStatus, TxType};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 // ===== Invoice DTOs =====
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CreateInvoiceRequest {
-    pub amount_sats: i64,
+    /// Requested amount; zero lets the payer choose. Set this or `amount_msat`.
+    #[serde(default)]
+    pub amount_sats: Option<i64>,
+    #[serde(default)]
+    pub amount_msat: Option<i64>,
     pub description: Option<String>,
+    /// Hex SHA-256 of a description kept elsewhere. Replaces `description`.
+    #[serde(default)]
+    pub description_hash: Option<String>,
+    /// Seconds until the invoice expires.
+    #[serde(default)]
+    pub expiry_secs: Option<i64>,
+    /// Include route hints for private channels.
+    #[serde(default)]
+    pub private: Option<bool>,
+    /// CLTV delta for the final hop.
+    #[serde(default)]
+    pub cltv_expiry: Option<u64>,
+    /// On-chain address to pay if the Lightning payment fails.
+    #[serde(default)]
+    pub fallback_addr: Option<String>,
+    /// Create a hold invoice: payments stay locked until explicitly settled
+    /// or cancelled.
+    #[serde(default)]
+    pub hold: bool,
+    /// Pubkey of the node to create the invoice on. Defaults to the first
+    /// receive node.
+    #[serde(default)]
+    pub node_id: Option<String>,
+}
+
+/// Server-side defaults for the optional invoice settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InvoiceDefaults {
+    pub expiry_secs: i64,
+    pub private: bool,
+    /// Zero leaves the choice to the node.
+    pub cltv_expiry: u64,
+}
+
+impl Default for InvoiceDefaults {
+    fn default() -> Self {
+        Self {
+            expiry_secs: 3600, // 1 hour
+            private: false,
+            cltv_expiry: 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,6 +62,18 @@ pub struct InvoiceResponse {
     pub payment_request: String,
     pub payment_hash: String,
     pub amount_sats: i64,
+    pub amount_msat: i64,
+    pub expiry_secs: i64,
+    /// Preimage that settles a hold invoice. Only set for hold invoices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<String>,
+}
+
+/// Releases the funds of an accepted hold invoice.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettleInvoiceRequest {
+    /// Hex-encoded preimage returned when the hold invoice was created.
+    pub preimage: String,
 }
 
 // ===== Payment DTOs =====
@@ -21,31 +81,276 @@ pub struct InvoiceResponse {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PayInvoiceRequest {
     pub payment_request: String,
+    /// Amount to pay. Required for amountless invoices, rejected otherwise.
+    /// Set this or `amount_msat`.
+    #[serde(default)]
+    pub amount_sats: Option<i64>,
+    #[serde(default)]
+    pub amount_msat: Option<i64>,
+    /// Fixed routing fee cap. Mutually exclusive with `max_fee_percent`.
+    #[serde(default)]
+    pub max_fee_sats: Option<i64>,
+    /// Routing fee cap as a percentage of the amount.
+    #[serde(default)]
+    pub max_fee_percent: Option<f64>,
+    /// Pubkey of the node to pay from. Defaults to the first send node.
+    #[serde(default)]
+    pub node_id: Option<String>,
+}
+
+/// Spontaneous payment straight to a node pubkey, without an invoice.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeysendRequest {
+    /// Hex-encoded identity pubkey of the recipient.
+    pub destination: String,
+    /// Set this or `amount_msat`.
+    #[serde(default)]
+    pub amount_sats: Option<i64>,
+    #[serde(default)]
+    pub amount_msat: Option<i64>,
+    /// Extra TLV records for the recipient, keyed by record type (>= 65536),
+    /// with hex-encoded values. Ordered, so equal requests serialize alike.
+    #[serde(default)]
+    pub custom_records: BTreeMap<u64, String>,
+    #[serde(default)]
+    pub max_fee_sats: Option<i64>,
+    #[serde(default)]
+    pub max_fee_percent: Option<f64>,
+    /// Pubkey of the node to pay from. Defaults to the first send node.
+    #[serde(default)]
+    pub node_id: Option<String>,
+}
+
+/// Upper bound on the routing fee of an outgoing payment.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeLimit {
+    /// Fixed cap in satoshis.
+    Sats(i64),
+    /// Cap as a percentage of the payment amount.
+    Percent(f64),
+}
+
+impl FeeLimit {
+    /// Build a limit from the optional request fields. At most one may be set;
+    /// `Ok(None)` means the caller did not ask for a specific limit.
+    pub fn from_parts(
+        max_fee_sats: Option<i64>,
+        max_fee_percent: Option<f64>,
+    ) -> Result<Option<Self>, String> {
+        match (max_fee_sats, max_fee_percent) {
+            (Some(_), Some(_)) => {
+                Err("Set either max_fee_sats or max_fee_percent, not both".to_string())
+            }
+            (Some(sats), None) if sats < 0 => Err("max_fee_sats must not be negative".to_string()),
+            (Some(sats), None) if sats.checked_mul(1000).is_none() => {
+                Err("max_fee_sats is too large".to_string())
+            }
+            (Some(sats), None) => Ok(Some(FeeLimit::Sats(sats))),
+            (None, Some(percent)) if !(0.0..=100.0).contains(&percent) => {
+                Err("max_fee_percent must be between 0 and 100".to_string())
+            }
+            (None, Some(percent)) => Ok(Some(FeeLimit::Percent(percent))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The cap in millisatoshis for a payment of `amount_msat`.
+    pub fn limit_msat(&self, amount_msat: i64) -> i64 {
+        match *self {
+            FeeLimit::Sats(sats) => sats.saturating_mul(1000),
+            FeeLimit::Percent(percent) => (amount_msat as f64 * percent / 100.0) as i64,
+        }
+    }
+}
+
+impl std::fmt::Display for FeeLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FeeLimit::Sats(sats) => write!(f, "{} sats", sats),
+            FeeLimit::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaymentResponse {
     pub payment_hash: String,
     pub preimage: String,
+    /// Whole sats, rounded down. `amount_msat` is exact.
     pub amount_sats: i64,
+    pub amount_msat: i64,
+    /// Whole sats, rounded down. `fee_msat` is exact.
+    pub fee_sats: i64,
+    pub fee_msat: i64,
+}
+
+// ===== Transaction Listing DTOs =====
+
+/// Narrows a transaction listing; unset fields match every transaction.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransactionFilter {
+    #[serde(default)]
+    pub tx_type: Option<TxType>,
+    #[serde(default)]
+    pub status: Option<TxStatus>,
+    #[serde(default)]
+    pub node_id: Option<String>,
+    #[serde(default)]
+    pub origin: Option<TxOrigin>,
+    /// Created at or after this time.
+    #[serde(default)]
+    pub created_from: Option<DateTime<Utc>>,
+    /// Created before this time.
+    #[serde(default)]
+    pub created_to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub min_amount_msat: Option<i64>,
+    #[serde(default)]
+    pub max_amount_msat: Option<i64>,
+    /// Case-insensitive substring of the description.
+    #[serde(default)]
+    pub search: Option<String>,
+}
+
+/// One page of a transaction listing, newest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    /// Pass back as `cursor` for the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+    /// Transactions matching the filter, across all pages.
+    pub total_count: i64,
+}
+
+// ===== Status History DTOs =====
+
+/// One step in the status timeline of a payment hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusChangeDto {
+    /// Whether the invoice or the payment with this hash changed.
+    pub tx_type: TxType,
+    /// `None` for the transaction's first status.
+    pub from_status: Option<TxStatus>,
+    pub to_status: TxStatus,
+    pub source: TransitionSource,
+    pub changed_at: String,
 }
 
 // ===== Balance DTOs =====
 
+/// Sats fields drop any sub-sat part; the `_msat` fields are exact.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BalanceDto {
     pub received_sats: i64,
+    pub received_msat: i64,
     pub paid_sats: i64,
+    pub paid_msat: i64,
+    pub fee_sats: i64,
+    pub fee_msat: i64,
     pub total_balance: i64,
+    pub total_balance_msat: i64,
     pub last_updated: String,
 }
 
+// ===== Ledger DTOs =====
+
+/// A transaction whose ledger entries do not match it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerMismatchDto {
+    pub payment_hash: String,
+    pub tx_type: TxType,
+    pub node_id: String,
+    pub expected_msat: i64,
+    pub posted_msat: i64,
+}
+
+/// Outcome of checking the ledger's invariants.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerCheckDto {
+    /// Whether every invariant holds.
+    pub ok: bool,
+    pub mismatches: Vec<LedgerMismatchDto>,
+    pub invalid_entries: i64,
+}
+
+// ===== Node Status DTOs =====
+
+/// Connectivity of a Lightning node as seen by THOrs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeHealth {
+    /// The last call reached the node.
+    Connected,
+    /// Recent calls failed; the node may be restarting.
+    Degraded,
+    /// The node cannot be reached and is being reconnected with backoff.
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub name: String,
+    pub node_id: String,
+    pub role: NodeRole,
+    pub health: NodeHealth,
+}
+
 // ===== Real-time Event DTOs =====
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum InvoiceEvent {
     InvoiceCreated { tx: Transaction },
+    InvoiceAccepted { tx: Transaction },
     InvoiceSettled { tx: Transaction },
     InvoiceExpired { tx: Transaction },
+    PaymentInFlight { tx: Transaction },
     PaymentSucceeded { tx: Transaction },
+    PaymentFailed { tx: Transaction },
+    NodeHealthChanged { node: NodeStatus },
+}
+
+impl InvoiceEvent {
+    /// The transaction row carried by this event, if it is about one.
+    pub fn tx(&self) -> Option<&Transaction> {
+        match self {
+            InvoiceEvent::InvoiceCreated { tx }
+            | InvoiceEvent::InvoiceAccepted { tx }
+            | InvoiceEvent::InvoiceSettled { tx }
+            | InvoiceEvent::InvoiceExpired { tx }
+            | InvoiceEvent::PaymentInFlight { tx }
+            | InvoiceEvent::PaymentSucceeded { tx }
+            | InvoiceEvent::PaymentFailed { tx } => Some(tx),
+            InvoiceEvent::NodeHealthChanged { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_limit_from_parts() {
+        assert_eq!(FeeLimit::from_parts(None, None), Ok(None));
+        assert_eq!(
+            FeeLimit::from_parts(Some(10), None),
+            Ok(Some(FeeLimit::Sats(10)))
+        );
+        assert_eq!(
+            FeeLimit::from_parts(None, Some(0.5)),
+            Ok(Some(FeeLimit::Percent(0.5)))
+        );
+        assert_eq!(
+            FeeLimit::from_parts(Some(0), None),
+            Ok(Some(FeeLimit::Sats(0)))
+        );
+
+        assert!(FeeLimit::from_parts(Some(10), Some(1.0)).is_err());
+        assert!(FeeLimit::from_parts(Some(-1), None).is_err());
+        assert!(FeeLimit::from_parts(Some(i64::MAX / 1000 + 1), None).is_err());
+        assert!(FeeLimit::from_parts(None, Some(-0.1)).is_err());
+        assert!(FeeLimit::from_parts(None, Some(100.1)).is_err());
+        assert!(FeeLimit::from_parts(None, Some(f64::NAN)).is_err());
+    }
 }
@@ -8,6 +8,8 @@ pub enum AppError {
     EmptyEnv(String),
     #[error("Environment variable {0} contains invalid Unicode")]
     InvalidEnv(String),
+    #[error("Environment variable {0} has an invalid value")]
+    ParseEnv(String),
     #[error("Secret file {0} is empty")]
     EmptySecret(String),
     #[error("Failed to read secret file {path}: {source}")]
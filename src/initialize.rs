@@ -1,15 +1,21 @@
 use crate::app::{shell, App};
+use crate::dto::{FeeLimit, InvoiceDefaults};
 use crate::errors::{AppError, Result as AppResult};
-use crate::server::{api, background, db, lnd, sse, AppState, InvoiceEvent};
+use crate::models::{NewNode, NodeRole};
+use crate::server::export::{is_beancount_account, BeancountAccounts};
+use crate::server::lnd::{LightningBackend, LightningClients, LndConnection};
+use crate::server::{api, background, db, sse, AppState, InvoiceEvent, Node, NodeRegistry};
 
+use axum::middleware;
 use axum::routing::{get, post};
 use axum::Router;
 use diesel::Connection;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use leptos::prelude::*;
 use leptos_axum::{generate_route_list, LeptosRoutes};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
-use tonic_lnd::Client as LndClient;
 use tower_http::cors::{Any, CorsLayer};
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
@@ -18,12 +24,31 @@ const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 pub struct Config {
     pub database_url: String,
     pub run_migrations: bool,
-    pub lnd_endpoint: String,
-    pub lnd_cert_path: String,
-    pub lnd_macaroon_path: String,
-    pub lnd_send_endpoint: String,
-    pub lnd_send_cert_path: String,
-    pub lnd_send_macaroon_path: String,
+    /// Every LND node to connect to, in the order they are preferred.
+    pub nodes: Vec<NodeConfig>,
+    /// Number of connections opened to each node for API calls.
+    pub lnd_pool_size: usize,
+    /// Deadline for a single unary LND call.
+    pub lnd_call_timeout: Duration,
+    /// How often pending payments are reconciled with the sending nodes and
+    /// payments made without THOrs imported.
+    pub payment_sync_interval: Duration,
+    /// Number of payments fetched per `ListPayments` call when importing.
+    pub payment_sync_page_size: u64,
+    /// How often pending invoices past their expiry are marked expired.
+    pub invoice_expiry_sweep_interval: Duration,
+    /// How long idempotency keys are kept before they are pruned.
+    pub idempotency_key_ttl: Duration,
+    /// Routing fee cap applied when a payment request does not set its own.
+    pub fee_limit: FeeLimit,
+    /// Settings applied to invoices that do not specify their own.
+    pub invoice_defaults: InvoiceDefaults,
+    /// Number of invoices fetched per `ListInvoices` call during the startup sync.
+    pub invoice_sync_page_size: u64,
+    /// Run the startup invoice sync in the background instead of before serving.
+    pub invoice_sync_background: bool,
+    /// Accounts Beancount exports post to.
+    pub beancount_accounts: BeancountAccounts,
 }
 
 impl Config {
@@ -35,16 +60,148 @@ impl Config {
             run_migrations: std::env::var("RUN_MIGRATIONS")
                 .map(|v| v == "true")
                 .unwrap_or(false),
-            lnd_endpoint: read_env("LND_ENDPOINT")?,
-            lnd_cert_path: read_env("LND_CERT_PATH")?,
-            lnd_macaroon_path: read_env("LND_MACAROON_PATH")?,
-            lnd_send_endpoint: read_env("LND_SEND_ENDPOINT")?,
-            lnd_send_cert_path: read_env("LND_SEND_CERT_PATH")?,
-            lnd_send_macaroon_path: read_env("LND_SEND_MACAROON_PATH")?,
+            nodes: read_node_configs()?,
+            lnd_pool_size: read_lnd_pool_size()?,
+            lnd_call_timeout: Duration::from_secs(read_env_or("LND_CALL_TIMEOUT_SECS", 30)?),
+            payment_sync_interval: Duration::from_secs(read_env_or(
+                "PAYMENT_SYNC_INTERVAL_SECS",
+                60,
+            )?),
+            payment_sync_page_size: read_page_size("PAYMENT_SYNC_PAGE_SIZE")?,
+            invoice_expiry_sweep_interval: Duration::from_secs(read_env_or(
+                "INVOICE_EXPIRY_SWEEP_INTERVAL_SECS",
+                60,
+            )?),
+            idempotency_key_ttl: Duration::from_secs(read_env_or(
+                "IDEMPOTENCY_KEY_TTL_SECS",
+                86_400,
+            )?),
+            fee_limit: read_fee_limit()?,
+            invoice_defaults: read_invoice_defaults()?,
+            invoice_sync_page_size: read_page_size("INVOICE_SYNC_PAGE_SIZE")?,
+            invoice_sync_background: read_env_or("INVOICE_SYNC_BACKGROUND", false)?,
+            beancount_accounts: read_beancount_accounts()?,
         })
     }
 }
 
+/// Connection settings of one configured LND node
+pub struct NodeConfig {
+    pub name: String,
+    pub endpoint: String,
+    pub cert_path: String,
+    pub macaroon_path: String,
+    pub role: NodeRole,
+}
+
+/// `LND_NODES` lists node names; each one is configured through
+/// `LND_<NAME>_ENDPOINT`, `LND_<NAME>_CERT_PATH`, `LND_<NAME>_MACAROON_PATH`
+/// and `LND_<NAME>_ROLE` (`receive`, `send` or `both`, default `both`).
+/// Without it, `LND_*` configures a receiver and `LND_SEND_*` a sender, and
+/// either may be left out to run with only one role.
+fn read_node_configs() -> AppResult<Vec<NodeConfig>> {
+    let nodes = match read_env_opt::<String>("LND_NODES")? {
+        Some(names) => names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let prefix = format!("LND_{}", name.to_uppercase());
+                let role_var = format!("{prefix}_ROLE");
+                let role = match read_env_opt::<String>(&role_var)? {
+                    Some(role) => NodeRole::parse(&role).ok_or(AppError::ParseEnv(role_var))?,
+                    None => NodeRole::Both,
+                };
+
+                read_node_config(name, &prefix, role)
+            })
+            .collect::<AppResult<Vec<_>>>()?,
+        None => {
+            let mut nodes = Vec::new();
+            if read_env_opt::<String>("LND_ENDPOINT")?.is_some() {
+                nodes.push(read_node_config("receiver", "LND", NodeRole::Receive)?);
+            }
+            if read_env_opt::<String>("LND_SEND_ENDPOINT")?.is_some() {
+                nodes.push(read_node_config("sender", "LND_SEND", NodeRole::Send)?);
+            }
+            nodes
+        }
+    };
+
+    if nodes.is_empty() {
+        return Err(AppError::MissingConfig(
+            "LND_NODES, LND_ENDPOINT or LND_SEND_ENDPOINT".to_string(),
+        ));
+    }
+    Ok(nodes)
+}
+
+/// Read `<prefix>_ENDPOINT`, `<prefix>_CERT_PATH` and `<prefix>_MACAROON_PATH`
+fn read_node_config(name: &str, prefix: &str, role: NodeRole) -> AppResult<NodeConfig> {
+    Ok(NodeConfig {
+        name: name.to_string(),
+        endpoint: read_env(&format!("{prefix}_ENDPOINT"))?,
+        cert_path: read_env(&format!("{prefix}_CERT_PATH"))?,
+        macaroon_path: read_env(&format!("{prefix}_MACAROON_PATH"))?,
+        role,
+    })
+}
+
+fn read_invoice_defaults() -> AppResult<InvoiceDefaults> {
+    let fallback = InvoiceDefaults::default();
+
+    Ok(InvoiceDefaults {
+        expiry_secs: read_env_or("INVOICE_EXPIRY_SECS", fallback.expiry_secs)?,
+        private: read_env_or("INVOICE_PRIVATE", fallback.private)?,
+        cltv_expiry: read_env_or("INVOICE_CLTV_EXPIRY", fallback.cltv_expiry)?,
+    })
+}
+
+fn read_lnd_pool_size() -> AppResult<usize> {
+    match read_env_or("LND_POOL_SIZE", 4)? {
+        0 => Err(AppError::ParseEnv("LND_POOL_SIZE".to_string())),
+        size => Ok(size),
+    }
+}
+
+fn read_page_size(name: &str) -> AppResult<u64> {
+    match read_env_or(name, 1000)? {
+        0 => Err(AppError::ParseEnv(name.to_string())),
+        size => Ok(size),
+    }
+}
+
+/// `BEANCOUNT_*_ACCOUNT` override the accounts of Beancount exports.
+fn read_beancount_accounts() -> AppResult<BeancountAccounts> {
+    let fallback = BeancountAccounts::default();
+    let read_account = |name: &str, default: String| {
+        let account = read_env_or(name, default)?;
+        if is_beancount_account(&account) {
+            Ok(account)
+        } else {
+            Err(AppError::ParseEnv(name.to_string()))
+        }
+    };
+
+    Ok(BeancountAccounts {
+        lightning: read_account("BEANCOUNT_LIGHTNING_ACCOUNT", fallback.lightning)?,
+        income: read_account("BEANCOUNT_INCOME_ACCOUNT", fallback.income)?,
+        payments: read_account("BEANCOUNT_PAYMENTS_ACCOUNT", fallback.payments)?,
+        fees: read_account("BEANCOUNT_FEES_ACCOUNT", fallback.fees)?,
+    })
+}
+
+/// `FEE_LIMIT_SATS` sets a fixed cap, `FEE_LIMIT_PERCENT` a relative one.
+/// Without either, payments may spend up to 5% of their amount on fees.
+fn read_fee_limit() -> AppResult<FeeLimit> {
+    let sats = read_env_opt("FEE_LIMIT_SATS")?;
+    let percent = read_env_opt("FEE_LIMIT_PERCENT")?;
+
+    FeeLimit::from_parts(sats, percent)
+        .map(|limit| limit.unwrap_or(FeeLimit::Percent(5.0)))
+        .map_err(|e| AppError::Server(format!("Invalid fee limit configuration: {e}")))
+}
+
 fn read_env(name: &str) -> AppResult<String> {
     match std::env::var(name) {
         Ok(value) => {
@@ -60,6 +217,23 @@ fn read_env(name: &str) -> AppResult<String> {
     }
 }
 
+/// Read and parse an optional environment variable.
+fn read_env_opt<T: std::str::FromStr>(name: &str) -> AppResult<Option<T>> {
+    match read_env(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| AppError::ParseEnv(name.to_string())),
+        Err(AppError::MissingConfig(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read and parse an optional environment variable, falling back to `default`.
+fn read_env_or<T: std::str::FromStr>(name: &str, default: T) -> AppResult<T> {
+    Ok(read_env_opt(name)?.unwrap_or(default))
+}
+
 /// Run embedded Diesel migrations
 pub fn run_migrations(database_url: &str) -> AppResult<()> {
     tracing::info!("Running database migrations...");
@@ -73,62 +247,86 @@ pub fn run_migrations(database_url: &str) -> AppResult<()> {
     Ok(())
 }
 
-/// Connect to a single LND node
+/// Connect to a single LND node with `pool_size` connections and keep
+/// reconnecting to it in the background whenever it becomes unreachable
 async fn connect_lnd_client(
     endpoint: &str,
     cert_path: &str,
     macaroon_path: &str,
+    pool_size: usize,
+    call_timeout: Duration,
     label: &str,
-) -> AppResult<LndClient> {
+) -> AppResult<LightningClients> {
     tracing::info!(
         endpoint,
         cert_path,
         macaroon_path,
+        pool_size,
         "Connecting to LND ({label})"
     );
 
-    lnd::connect(
-        endpoint.to_string(),
-        cert_path.to_string(),
-        macaroon_path.to_string(),
-    )
-    .await
-    .map_err(|e| AppError::Server(format!("Failed to connect to LND ({label}): {e:?}")))
-}
-
-/// Create the three LND connections: receive API, subscription, send API
-pub async fn setup_lnd_clients(config: &Config) -> AppResult<(LndClient, LndClient, LndClient)> {
-    let api_receive = connect_lnd_client(
-        &config.lnd_endpoint,
-        &config.lnd_cert_path,
-        &config.lnd_macaroon_path,
-        "receiver",
-    )
-    .await?;
-
-    let subscription = connect_lnd_client(
-        &config.lnd_endpoint,
-        &config.lnd_cert_path,
-        &config.lnd_macaroon_path,
-        "subscription",
-    )
-    .await?;
-
-    let api_send = connect_lnd_client(
-        &config.lnd_send_endpoint,
-        &config.lnd_send_cert_path,
-        &config.lnd_send_macaroon_path,
-        "sender",
-    )
-    .await?;
+    let connection = LndConnection {
+        endpoint: endpoint.to_string(),
+        cert_path: cert_path.to_string(),
+        macaroon_path: macaroon_path.to_string(),
+        pool_size,
+    };
+    let clients = LightningClients::connect(connection, call_timeout)
+        .await
+        .map_err(|e| AppError::Server(format!("Failed to connect to LND ({label}): {e:?}")))?;
+
+    tokio::spawn(clients.clone().maintain_connection());
+    Ok(clients)
+}
+
+/// Connect to every configured node, learn its pubkey and record it in the
+/// `nodes` table
+pub async fn setup_nodes(config: &Config, db_pool: &db::DbPool) -> AppResult<NodeRegistry> {
+    let mut nodes = Vec::with_capacity(config.nodes.len());
+
+    for node_config in &config.nodes {
+        let clients = connect_lnd_client(
+            &node_config.endpoint,
+            &node_config.cert_path,
+            &node_config.macaroon_path,
+            config.lnd_pool_size,
+            config.lnd_call_timeout,
+            &node_config.name,
+        )
+        .await?;
+        let node_id = fetch_node_pubkey(&clients, &node_config.name).await?;
+
+        db::register_node(
+            db_pool,
+            &NewNode::new(node_id.clone(), node_config.name.clone(), node_config.role),
+        )
+        .await
+        .map_err(|e| AppError::Server(format!("Failed to register node: {e}")))?;
+
+        nodes.push(Node {
+            name: node_config.name.clone(),
+            node_id,
+            role: node_config.role,
+            lnd: Arc::new(clients),
+        });
+    }
+
+    let registry = NodeRegistry::new(nodes).map_err(AppError::Server)?;
+    if !registry.can_receive() {
+        tracing::warn!("No receive node configured, invoice endpoints are disabled");
+    }
+    if !registry.can_send() {
+        tracing::warn!("No send node configured, payment endpoints are disabled");
+    }
 
     tracing::info!("Connected to all LND nodes successfully");
-    Ok((api_receive, subscription, api_send))
+    Ok(registry)
 }
 
 /// Fetch a node's public key
-pub async fn fetch_node_pubkey(client: &mut LndClient, label: &str) -> AppResult<String> {
-    let pubkey = lnd::get_node_pubkey(client)
+pub async fn fetch_node_pubkey(lnd: &dyn LightningBackend, label: &str) -> AppResult<String> {
+    let pubkey = lnd
+        .get_node_pubkey()
         .await
         .map_err(|e| AppError::Server(format!("Failed to fetch {label} node ID: {e:?}")))?;
 
@@ -146,13 +344,42 @@ pub fn build_router(app_state: AppState, leptos_options: LeptosOptions) -> Route
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let api_router = Router::new()
+    // Invoice and payment routes answer `RoleDisabled` when no node can
+    // serve them
+    let receive_router = Router::new()
         .route("/invoice", post(api::create_invoice))
-        .route("/invoice/{payment_hash}", get(api::get_invoice))
+        .route(
+            "/invoice/{payment_hash}",
+            get(api::get_invoice).delete(api::cancel_invoice),
+        )
+        .route("/invoice/{payment_hash}/settle", post(api::settle_invoice))
+        .route("/invoice/{payment_hash}/cancel", post(api::cancel_invoice))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), NodeRole::Receive),
+            api::require_role,
+        ));
+
+    let send_router = Router::new()
         .route("/payment", post(api::pay_invoice))
         .route("/payment/{payment_hash}", get(api::get_payment))
+        .route("/keysend", post(api::keysend))
+        .route_layer(middleware::from_fn_with_state(
+            (app_state.clone(), NodeRole::Send),
+            api::require_role,
+        ));
+
+    let api_router = Router::new()
+        .merge(receive_router)
+        .merge(send_router)
         .route("/transactions", get(api::list_transactions))
+        .route("/transactions/export", get(api::export_transactions))
+        .route(
+            "/transactions/{payment_hash}/timeline",
+            get(api::get_status_timeline),
+        )
         .route("/balance", get(api::get_balance))
+        .route("/ledger/check", get(api::check_ledger))
+        .route("/status", get(api::get_status))
         .with_state(app_state.clone());
 
     Router::new()
@@ -174,17 +401,56 @@ pub fn build_router(app_state: AppState, leptos_options: LeptosOptions) -> Route
         .with_state(leptos_options)
 }
 
-/// Spawn the background invoice subscription task
+/// Spawn an invoice subscription for every receive node, a payment
+/// reconciliation task for every send node and the invoice expiry sweep
 pub fn spawn_background_tasks(
-    subscription_lnd: LndClient,
+    nodes: &NodeRegistry,
     db_pool: db::DbPool,
     broadcast_tx: broadcast::Sender<InvoiceEvent>,
-    receive_node_id: String,
+    payment_sync_interval: Duration,
+    payment_sync_page_size: u64,
+    invoice_expiry_sweep_interval: Duration,
+    idempotency_key_ttl: Duration,
 ) {
-    tokio::spawn(background::subscribe_to_invoices(
-        subscription_lnd,
-        db_pool,
+    for node in nodes.receivers() {
+        tokio::spawn(background::subscribe_to_invoices(
+            node.lnd.clone(),
+            db_pool.clone(),
+            broadcast_tx.clone(),
+            node.node_id.clone(),
+        ));
+    }
+
+    for node in nodes.senders() {
+        tokio::spawn(background::reconcile_payments(
+            node.lnd.clone(),
+            db_pool.clone(),
+            broadcast_tx.clone(),
+            node.node_id.clone(),
+            payment_sync_interval,
+            payment_sync_page_size,
+        ));
+    }
+
+    tokio::spawn(background::sweep_expired_invoices(
+        nodes.clone(),
+        db_pool.clone(),
         broadcast_tx,
-        receive_node_id,
+        invoice_expiry_sweep_interval,
+    ));
+
+    tokio::spawn(background::prune_idempotency_keys(
+        db_pool,
+        idempotency_key_ttl,
     ));
 }
+
+/// Broadcast a `NodeHealthChanged` event whenever a node changes health
+pub fn spawn_health_broadcasts(app_state: &AppState) {
+    for node in app_state.nodes.iter() {
+        tokio::spawn(background::broadcast_node_health(
+            node.clone(),
+            app_state.broadcast_tx.clone(),
+        ));
+    }
+}
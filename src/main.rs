@@ -5,10 +5,10 @@ use tokio::sync::broadcast;
 
 use thors::errors::Result as AppResult;
 use thors::initialize::{
-    build_router, fetch_node_pubkey, run_migrations, setup_lnd_clients, spawn_background_tasks,
+    build_router, run_migrations, setup_nodes, spawn_background_tasks, spawn_health_broadcasts,
     Config,
 };
-use thors::server::{background, db, lnd, AppState, InvoiceEvent};
+use thors::server::{background, db, AppState, InvoiceEvent};
 
 #[cfg(feature = "ssr")]
 #[tokio::main]
@@ -26,42 +26,92 @@ async fn main() -> AppResult<()> {
     // Initialize database pool
     let db_pool = db::create_pool(&config.database_url);
 
-    // Setup LND client connections (receive, subscription, send)
-    let (mut api_lnd_receive, mut subscription_lnd, mut api_lnd_send) =
-        setup_lnd_clients(&config).await?;
-
-    // Fetch node public keys
-    let receive_node_id = fetch_node_pubkey(&mut api_lnd_receive, "receiver").await?;
-    let send_node_id = fetch_node_pubkey(&mut api_lnd_send, "sender").await?;
-
-    // Wrap LND clients for shared access
-    let lnd_receive = lnd::LightningClients::from_client(api_lnd_receive);
-    let lnd_send = lnd::LightningClients::from_client(api_lnd_send);
+    // Requests a previous run never finished hold their idempotency keys
+    // without a response; release them so retries run them again. Keys bound
+    // to a sent payment stay, and retries answer from the payment instead
+    match db::release_unfinished_idempotency_keys(&db_pool).await {
+        Ok(0) => {}
+        Ok(released) => tracing::info!("Released {released} unfinished idempotency keys"),
+        Err(e) => tracing::warn!("Failed to release unfinished idempotency keys: {e}"),
+    }
 
-    // Sync existing invoices from LND at startup
-    background::sync_invoices_from_lnd(&mut subscription_lnd, &db_pool, &receive_node_id).await;
+    // Connect to every configured LND node and record it in the database
+    let nodes = setup_nodes(&config, &db_pool).await?;
 
     // Setup broadcast channel for SSE events
     let (broadcast_tx, _) = broadcast::channel::<InvoiceEvent>(100);
 
-    // Spawn background invoice subscription task
+    // Import payments sent without THOrs, then settle or fail payments left
+    // pending by a previous run
+    for node in nodes.senders() {
+        background::sync_payments_from_lnd(
+            node.lnd.as_ref(),
+            &db_pool,
+            &broadcast_tx,
+            &node.node_id,
+            config.payment_sync_page_size,
+        )
+        .await;
+        background::reconcile_pending_payments(
+            node.lnd.as_ref(),
+            &db_pool,
+            &broadcast_tx,
+            &node.node_id,
+        )
+        .await;
+    }
+
+    // Spawn background invoice subscription and payment reconciliation tasks.
+    // The subscriptions start before the invoice sync so settlements that
+    // happen while a long sync is paging through older invoices arrive live.
     spawn_background_tasks(
-        subscription_lnd,
+        &nodes,
         db_pool.clone(),
         broadcast_tx.clone(),
-        receive_node_id.clone(),
+        config.payment_sync_interval,
+        config.payment_sync_page_size,
+        config.invoice_expiry_sweep_interval,
+        config.idempotency_key_ttl,
     );
 
+    // Sync existing invoices from every receive node, optionally while the
+    // server is already up
+    let invoice_sync = {
+        let db_pool = db_pool.clone();
+        let nodes = nodes.clone();
+        let page_size = config.invoice_sync_page_size;
+        async move {
+            for node in nodes.receivers() {
+                background::sync_invoices_from_lnd(
+                    node.lnd.as_ref(),
+                    &db_pool,
+                    &node.node_id,
+                    page_size,
+                )
+                .await;
+            }
+        }
+    };
+    if config.invoice_sync_background {
+        tracing::info!("Running invoice sync in the background");
+        tokio::spawn(invoice_sync);
+    } else {
+        invoice_sync.await;
+    }
+
     // Build application state
     let app_state = AppState {
         db_pool,
-        lnd_receive,
-        lnd_send,
+        nodes,
         broadcast_tx,
-        receive_node_id,
-        send_node_id,
+        fee_limit: config.fee_limit,
+        invoice_defaults: config.invoice_defaults,
+        beancount_accounts: config.beancount_accounts,
     };
 
+    // Tell connected clients when a node goes down or comes back
+    spawn_health_broadcasts(&app_state);
+
     // Get Leptos configuration
     let leptos_options = get_configuration(None)
         .expect("Failed to load Leptos configuration")
@@ -79,10 +129,18 @@ async fn main() -> AppResult<()> {
     tracing::info!("API endpoints:");
     tracing::info!("  POST /api/invoice");
     tracing::info!("  GET  /api/invoice/:payment_hash");
+    tracing::info!("  DELETE /api/invoice/:payment_hash");
+    tracing::info!("  POST /api/invoice/:payment_hash/settle");
+    tracing::info!("  POST /api/invoice/:payment_hash/cancel");
     tracing::info!("  POST /api/payment");
     tracing::info!("  GET  /api/payment/:payment_hash");
+    tracing::info!("  POST /api/keysend");
     tracing::info!("  GET  /api/transactions");
+    tracing::info!("  GET  /api/transactions/export");
+    tracing::info!("  GET  /api/transactions/:payment_hash/timeline");
     tracing::info!("  GET  /api/balance");
+    tracing::info!("  GET  /api/ledger/check");
+    tracing::info!("  GET  /api/status");
     tracing::info!("  GET  /events (SSE)");
 
     axum::serve(listener, app.into_make_service()).await?;
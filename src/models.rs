@@ -1,12 +1,16 @@
 #[cfg(feature = "ssr")]
-use crate::schema::{balance, transactions};
+use crate::schema::{
+    idempotency_keys, invoice_sync_state, nodes, transaction_status_history, transactions,
+};
 use chrono::{DateTime, Utc};
 #[cfg(feature = "ssr")]
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
-// Simple enums (not mapped to PostgreSQL ENUM types)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+// Mapped to the `tx_type` and `tx_status` PostgreSQL ENUM types
+#[cfg_attr(feature = "ssr", derive(diesel::AsExpression, diesel::FromSqlRow))]
+#[cfg_attr(feature = "ssr", diesel(sql_type = crate::schema::sql_types::TxType))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TxType {
     Invoice,
@@ -21,7 +25,7 @@ impl TxType {
         }
     }
 
-    pub fn from_str(s: &str) -> Option<Self> {
+    pub fn parse(s: &str) -> Option<Self> {
         match s {
             "invoice" => Some(TxType::Invoice),
             "payment" => Some(TxType::Payment),
@@ -30,10 +34,14 @@ impl TxType {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(diesel::AsExpression, diesel::FromSqlRow))]
+#[cfg_attr(feature = "ssr", diesel(sql_type = crate::schema::sql_types::TxStatus))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TxStatus {
     Pending,
+    /// A hold invoice has been paid and waits to be settled or cancelled.
+    Accepted,
     Succeeded,
     Failed,
     Expired,
@@ -43,52 +51,341 @@ impl TxStatus {
     pub fn as_str(&self) -> &'static str {
         match self {
             TxStatus::Pending => "pending",
+            TxStatus::Accepted => "accepted",
             TxStatus::Succeeded => "succeeded",
             TxStatus::Failed => "failed",
             TxStatus::Expired => "expired",
         }
     }
 
-    pub fn from_str(s: &str) -> Option<Self> {
+    pub fn parse(s: &str) -> Option<Self> {
         match s {
             "pending" => Some(TxStatus::Pending),
+            "accepted" => Some(TxStatus::Accepted),
             "succeeded" => Some(TxStatus::Succeeded),
             "failed" => Some(TxStatus::Failed),
             "expired" => Some(TxStatus::Expired),
             _ => None,
         }
     }
+
+    /// Succeeded, failed and expired transactions are done. Only a late
+    /// settlement of an expired invoice still changes one.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            TxStatus::Succeeded | TxStatus::Failed | TxStatus::Expired
+        )
+    }
+
+    /// Whether a transaction may move from this status to `next`. Pending
+    /// may become anything else, an accepted hold invoice may still settle,
+    /// fail or expire, and an expired invoice may still settle if LND took
+    /// the payment before it was cancelled. Other final statuses stay as
+    /// they are.
+    pub fn can_transition_to(&self, next: TxStatus) -> bool {
+        match self {
+            TxStatus::Pending => next != TxStatus::Pending,
+            TxStatus::Accepted => next.is_final(),
+            TxStatus::Expired => next == TxStatus::Succeeded,
+            TxStatus::Succeeded | TxStatus::Failed => false,
+        }
+    }
+}
+
+/// Whether THOrs made a transaction or found it on a node.
+#[cfg_attr(feature = "ssr", derive(diesel::AsExpression, diesel::FromSqlRow))]
+#[cfg_attr(feature = "ssr", diesel(sql_type = crate::schema::sql_types::TxOrigin))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxOrigin {
+    /// Created through the API or the UI.
+    #[default]
+    Thors,
+    /// Imported from a node that made it without THOrs, e.g. with lncli.
+    External,
+}
+
+impl TxOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxOrigin::Thors => "thors",
+            TxOrigin::External => "external",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "thors" => Some(TxOrigin::Thors),
+            "external" => Some(TxOrigin::External),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+mod pg_enums {
+    use std::io::Write;
+
+    use diesel::deserialize::{self, FromSql};
+    use diesel::pg::{Pg, PgValue};
+    use diesel::serialize::{self, IsNull, Output, ToSql};
+
+    use super::{TransitionSource, TxOrigin, TxStatus, TxType};
+    use crate::schema::sql_types;
+
+    impl ToSql<sql_types::TxType, Pg> for TxType {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+            out.write_all(self.as_str().as_bytes())?;
+            Ok(IsNull::No)
+        }
+    }
+
+    impl FromSql<sql_types::TxType, Pg> for TxType {
+        fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+            let value = std::str::from_utf8(bytes.as_bytes())?;
+            TxType::parse(value).ok_or_else(|| format!("Unknown tx_type {value:?}").into())
+        }
+    }
+
+    impl ToSql<sql_types::TxStatus, Pg> for TxStatus {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+            out.write_all(self.as_str().as_bytes())?;
+            Ok(IsNull::No)
+        }
+    }
+
+    impl FromSql<sql_types::TxStatus, Pg> for TxStatus {
+        fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+            let value = std::str::from_utf8(bytes.as_bytes())?;
+            TxStatus::parse(value).ok_or_else(|| format!("Unknown tx_status {value:?}").into())
+        }
+    }
+
+    impl ToSql<sql_types::TxOrigin, Pg> for TxOrigin {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+            out.write_all(self.as_str().as_bytes())?;
+            Ok(IsNull::No)
+        }
+    }
+
+    impl FromSql<sql_types::TxOrigin, Pg> for TxOrigin {
+        fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+            let value = std::str::from_utf8(bytes.as_bytes())?;
+            TxOrigin::parse(value).ok_or_else(|| format!("Unknown tx_origin {value:?}").into())
+        }
+    }
+
+    impl ToSql<sql_types::TransitionSource, Pg> for TransitionSource {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+            out.write_all(self.as_str().as_bytes())?;
+            Ok(IsNull::No)
+        }
+    }
+
+    impl FromSql<sql_types::TransitionSource, Pg> for TransitionSource {
+        fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+            let value = std::str::from_utf8(bytes.as_bytes())?;
+            TransitionSource::parse(value)
+                .ok_or_else(|| format!("Unknown transition_source {value:?}").into())
+        }
+    }
+}
+
+/// What moved a transaction to a new status.
+#[cfg_attr(feature = "ssr", derive(diesel::AsExpression, diesel::FromSqlRow))]
+#[cfg_attr(
+    feature = "ssr",
+    diesel(sql_type = crate::schema::sql_types::TransitionSource)
+)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionSource {
+    /// An HTTP request, from the REST API or the UI.
+    Api,
+    /// A receive node's live invoice subscription.
+    InvoiceSubscription,
+    /// The startup invoice sync.
+    InvoiceSync,
+    /// The router updates of a payment being sent.
+    PaymentUpdate,
+    /// Reconciling pending payments with the send node.
+    PaymentReconciliation,
+    /// Importing payments a send node made without THOrs.
+    PaymentImport,
+    /// The periodic sweep of invoices past their expiry.
+    ExpirySweep,
+    /// Recorded when the history was introduced, for earlier transactions.
+    Migration,
+}
+
+impl TransitionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransitionSource::Api => "api",
+            TransitionSource::InvoiceSubscription => "invoice_subscription",
+            TransitionSource::InvoiceSync => "invoice_sync",
+            TransitionSource::PaymentUpdate => "payment_update",
+            TransitionSource::PaymentReconciliation => "payment_reconciliation",
+            TransitionSource::PaymentImport => "payment_import",
+            TransitionSource::ExpirySweep => "expiry_sweep",
+            TransitionSource::Migration => "migration",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "api" => Some(TransitionSource::Api),
+            "invoice_subscription" => Some(TransitionSource::InvoiceSubscription),
+            "invoice_sync" => Some(TransitionSource::InvoiceSync),
+            "payment_update" => Some(TransitionSource::PaymentUpdate),
+            "payment_reconciliation" => Some(TransitionSource::PaymentReconciliation),
+            "payment_import" => Some(TransitionSource::PaymentImport),
+            "expiry_sweep" => Some(TransitionSource::ExpirySweep),
+            "migration" => Some(TransitionSource::Migration),
+            _ => None,
+        }
+    }
+}
+
+/// Progress of an outgoing payment as reported by LND's router.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    InFlight,
+    Succeeded,
+    Failed,
 }
 
-// Transaction model (String fields instead of enums)
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::InFlight => "in_flight",
+            PaymentStatus::Succeeded => "succeeded",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "in_flight" => Some(PaymentStatus::InFlight),
+            "succeeded" => Some(PaymentStatus::Succeeded),
+            "failed" => Some(PaymentStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// What a configured Lightning node may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    Receive,
+    Send,
+    Both,
+}
+
+impl NodeRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeRole::Receive => "receive",
+            NodeRole::Send => "send",
+            NodeRole::Both => "both",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "receive" => Some(NodeRole::Receive),
+            "send" => Some(NodeRole::Send),
+            "both" => Some(NodeRole::Both),
+            _ => None,
+        }
+    }
+
+    pub fn can_receive(&self) -> bool {
+        matches!(self, NodeRole::Receive | NodeRole::Both)
+    }
+
+    pub fn can_send(&self) -> bool {
+        matches!(self, NodeRole::Send | NodeRole::Both)
+    }
+}
+
+/// Ledger account of a node. Each node has one of every kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountKind {
+    /// The node's own funds.
+    Lightning,
+    /// Settled invoices.
+    Received,
+    /// Amounts of succeeded payments.
+    Sent,
+    /// Routing fees of succeeded payments.
+    Fees,
+}
+
+impl AccountKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountKind::Lightning => "lightning",
+            AccountKind::Received => "received",
+            AccountKind::Sent => "sent",
+            AccountKind::Fees => "fees",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "lightning" => Some(AccountKind::Lightning),
+            "received" => Some(AccountKind::Received),
+            "sent" => Some(AccountKind::Sent),
+            "fees" => Some(AccountKind::Fees),
+            _ => None,
+        }
+    }
+}
+
+// Transaction model
 #[cfg_attr(feature = "ssr", derive(Queryable, Selectable))]
 #[cfg_attr(feature = "ssr", diesel(table_name = transactions))]
 #[cfg_attr(feature = "ssr", diesel(check_for_backend(diesel::pg::Pg)))]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
     pub id: i64,
-    tx_type: String, // Private, use getter
+    pub tx_type: TxType,
     pub payment_hash: String,
     pub payment_request: String,
+    pub amount_msat: i64,
+    /// `amount_msat` in whole sats, rounded down.
     pub amount_sats: i64,
     pub description: Option<String>,
-    status: String, // Private, use getter
+    pub status: TxStatus,
     pub preimage: Option<String>,
+    pub fee_msat: Option<i64>,
+    /// `fee_msat` in whole sats, rounded down.
     pub fee_sats: Option<i64>,
     pub failure_reason: Option<String>,
     pub expires_at: Option<DateTime<Utc>>,
     pub node_id: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    payment_status: Option<String>, // Private, use getter
+    pub failure_code: Option<String>,
+    pub htlc_attempts: i32,
+    /// Recipient node pubkey, recorded for outgoing payments.
+    pub destination: Option<String>,
+    pub origin: TxOrigin,
+    /// When it succeeded. Set once, unlike `updated_at`.
+    pub settled_at: Option<DateTime<Utc>>,
 }
 
 impl Transaction {
-    pub fn tx_type(&self) -> TxType {
-        TxType::from_str(&self.tx_type).unwrap()
-    }
-
-    pub fn status(&self) -> TxStatus {
-        TxStatus::from_str(&self.status).unwrap()
+    pub fn payment_status(&self) -> Option<PaymentStatus> {
+        self.payment_status
+            .as_deref()
+            .and_then(PaymentStatus::parse)
     }
 }
 
@@ -97,39 +394,108 @@ impl Transaction {
 #[derive(Debug, Insertable)]
 #[diesel(table_name = transactions)]
 pub struct NewTransaction {
-    pub tx_type: String,
+    pub tx_type: TxType,
     pub payment_hash: String,
     pub payment_request: String,
-    pub amount_sats: i64,
+    pub amount_msat: i64,
     pub description: Option<String>,
-    pub status: String,
+    pub status: TxStatus,
     pub expires_at: Option<DateTime<Utc>>,
     pub node_id: String,
+    pub destination: Option<String>,
+    pub origin: TxOrigin,
+    /// `None` inserts the current time.
+    pub created_at: Option<DateTime<Utc>>,
+    pub preimage: Option<String>,
+    pub fee_msat: Option<i64>,
+    pub failure_reason: Option<String>,
+    pub payment_status: Option<String>,
+    pub failure_code: Option<String>,
+    pub htlc_attempts: Option<i32>,
+    /// When LND says it succeeded. `None` stamps the time it is recorded.
+    pub settled_at: Option<DateTime<Utc>>,
 }
 
 #[cfg(feature = "ssr")]
 impl NewTransaction {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         tx_type: TxType,
         payment_hash: String,
         payment_request: String,
-        amount_sats: i64,
+        amount_msat: i64,
         description: Option<String>,
         status: TxStatus,
         expires_at: Option<DateTime<Utc>>,
         node_id: String,
     ) -> Self {
         Self {
-            tx_type: tx_type.as_str().to_string(),
+            tx_type,
             payment_hash,
             payment_request,
-            amount_sats,
+            amount_msat,
             description,
-            status: status.as_str().to_string(),
+            status,
             expires_at,
             node_id,
+            destination: None,
+            origin: TxOrigin::Thors,
+            created_at: None,
+            preimage: None,
+            fee_msat: None,
+            failure_reason: None,
+            payment_status: None,
+            failure_code: None,
+            htlc_attempts: None,
+            settled_at: None,
         }
     }
+
+    /// Record when LND says the transaction succeeded.
+    pub fn with_settled_at(mut self, settled_at: Option<DateTime<Utc>>) -> Self {
+        self.settled_at = settled_at;
+        self
+    }
+
+    /// Record the node an outgoing payment is sent to.
+    pub fn with_destination(mut self, destination: String) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Mark a transaction found on a node, made at `created_at`.
+    pub fn external(mut self, created_at: DateTime<Utc>) -> Self {
+        self.origin = TxOrigin::External;
+        self.created_at = Some(created_at);
+        self
+    }
+
+    /// Record how a payment ended: preimage and fee once it succeeded, the
+    /// reason once it failed.
+    pub fn with_outcome(
+        mut self,
+        preimage: Option<String>,
+        fee_msat: Option<i64>,
+        failure_reason: Option<String>,
+    ) -> Self {
+        self.preimage = preimage;
+        self.fee_msat = fee_msat;
+        self.failure_reason = failure_reason;
+        self
+    }
+
+    /// Attach router progress, as `UpdateTransaction::with_payment_progress`.
+    pub fn with_payment_progress(
+        mut self,
+        payment_status: PaymentStatus,
+        failure_code: Option<String>,
+        htlc_attempts: i32,
+    ) -> Self {
+        self.payment_status = Some(payment_status.as_str().to_string());
+        self.failure_code = failure_code;
+        self.htlc_attempts = Some(htlc_attempts);
+        self
+    }
 }
 
 // Update struct
@@ -137,11 +503,14 @@ impl NewTransaction {
 #[derive(Debug, AsChangeset)]
 #[diesel(table_name = transactions)]
 pub struct UpdateTransaction {
-    pub status: Option<String>,
+    pub status: Option<TxStatus>,
     pub preimage: Option<String>,
-    pub fee_sats: Option<i64>,
+    pub fee_msat: Option<i64>,
     pub failure_reason: Option<String>,
     pub updated_at: DateTime<Utc>,
+    pub payment_status: Option<String>,
+    pub failure_code: Option<String>,
+    pub htlc_attempts: Option<i32>,
 }
 
 #[cfg(feature = "ssr")]
@@ -149,33 +518,203 @@ impl UpdateTransaction {
     pub fn new(
         status: Option<TxStatus>,
         preimage: Option<String>,
-        fee_sats: Option<i64>,
+        fee_msat: Option<i64>,
         failure_reason: Option<String>,
     ) -> Self {
         Self {
-            status: status.map(|s| s.as_str().to_string()),
+            status,
             preimage,
-            fee_sats,
+            fee_msat,
             failure_reason,
             updated_at: Utc::now(),
+            payment_status: None,
+            failure_code: None,
+            htlc_attempts: None,
         }
     }
+
+    /// Attach router progress (`SendPaymentV2` status, failure code, HTLC count).
+    pub fn with_payment_progress(
+        mut self,
+        payment_status: PaymentStatus,
+        failure_code: Option<String>,
+        htlc_attempts: i32,
+    ) -> Self {
+        self.payment_status = Some(payment_status.as_str().to_string());
+        self.failure_code = failure_code;
+        self.htlc_attempts = Some(htlc_attempts);
+        self
+    }
+}
+
+/// One recorded status transition of a transaction.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = transaction_status_history)]
+pub struct StatusChange {
+    pub id: i64,
+    pub transaction_id: i64,
+    /// `None` for the transaction's first status.
+    pub from_status: Option<TxStatus>,
+    pub to_status: TxStatus,
+    pub source: TransitionSource,
+    pub created_at: DateTime<Utc>,
 }
 
-// Balance model
 #[cfg(feature = "ssr")]
-#[derive(Debug, Clone, Queryable, Selectable, Serialize, Deserialize)]
-#[diesel(table_name = balance)]
-pub struct Balance {
-    pub id: i32,
-    pub received_sats: i64,
-    pub paid_sats: i64,
-    pub last_updated: DateTime<Utc>,
+#[derive(Debug, Insertable)]
+#[diesel(table_name = transaction_status_history)]
+pub struct NewStatusChange {
+    pub transaction_id: i64,
+    pub from_status: Option<TxStatus>,
+    pub to_status: TxStatus,
+    pub source: TransitionSource,
 }
 
 #[cfg(feature = "ssr")]
-impl Balance {
-    pub fn total_balance(&self) -> i64 {
-        self.received_sats - self.paid_sats
+impl NewStatusChange {
+    pub fn new(
+        transaction_id: i64,
+        from_status: Option<TxStatus>,
+        to_status: TxStatus,
+        source: TransitionSource,
+    ) -> Self {
+        Self {
+            transaction_id,
+            from_status,
+            to_status,
+            source,
+        }
+    }
+}
+
+/// Last invoice indices seen from a receiving node's invoice subscription,
+/// and how far the startup sync has listed its invoices.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy, Default, Queryable, Selectable)]
+#[diesel(table_name = invoice_sync_state)]
+pub struct InvoiceIndices {
+    pub add_index: i64,
+    pub settle_index: i64,
+    /// Add index a full load resumes after.
+    pub sync_add_index: i64,
+    /// Whether every invoice has been loaded once; later syncs only fetch
+    /// invoices added after `add_index`.
+    pub full_sync_complete: bool,
+}
+
+/// A configured node as recorded in the `nodes` table.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Insertable)]
+#[diesel(table_name = nodes)]
+pub struct NewNode {
+    pub node_id: String,
+    pub name: String,
+    pub role: String,
+}
+
+#[cfg(feature = "ssr")]
+impl NewNode {
+    pub fn new(node_id: String, name: String, role: NodeRole) -> Self {
+        Self {
+            node_id,
+            name,
+            role: role.as_str().to_string(),
+        }
+    }
+}
+
+/// POST endpoints that accept an `Idempotency-Key`. The REST API and the
+/// server functions share one key space per endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdempotentEndpoint {
+    Invoice,
+    Payment,
+    Keysend,
+}
+
+impl IdempotentEndpoint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdempotentEndpoint::Invoice => "invoice",
+            IdempotentEndpoint::Payment => "payment",
+            IdempotentEndpoint::Keysend => "keysend",
+        }
+    }
+}
+
+/// A request made under an idempotency key, with its response once it has
+/// finished.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = idempotency_keys)]
+pub struct IdempotencyRecord {
+    pub request_hash: String,
+    pub response_status: Option<i32>,
+    pub response_body: Option<serde_json::Value>,
+    /// The payment the request sent, once it has been sent.
+    pub payment_hash: Option<String>,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Insertable)]
+#[diesel(table_name = idempotency_keys)]
+pub struct NewIdempotencyKey {
+    pub endpoint: String,
+    pub idempotency_key: String,
+    pub request_hash: String,
+}
+
+#[cfg(feature = "ssr")]
+impl NewIdempotencyKey {
+    pub fn new(
+        endpoint: IdempotentEndpoint,
+        idempotency_key: String,
+        request_hash: String,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.as_str().to_string(),
+            idempotency_key,
+            request_hash,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [TxStatus; 5] = [
+        TxStatus::Pending,
+        TxStatus::Accepted,
+        TxStatus::Succeeded,
+        TxStatus::Failed,
+        TxStatus::Expired,
+    ];
+
+    fn allowed(from: TxStatus) -> Vec<TxStatus> {
+        ALL.into_iter()
+            .filter(|next| from.can_transition_to(*next))
+            .collect()
+    }
+
+    #[test]
+    fn status_transitions() {
+        assert_eq!(
+            allowed(TxStatus::Pending),
+            [
+                TxStatus::Accepted,
+                TxStatus::Succeeded,
+                TxStatus::Failed,
+                TxStatus::Expired
+            ]
+        );
+        assert_eq!(
+            allowed(TxStatus::Accepted),
+            [TxStatus::Succeeded, TxStatus::Failed, TxStatus::Expired]
+        );
+        assert_eq!(allowed(TxStatus::Expired), [TxStatus::Succeeded]);
+        assert!(allowed(TxStatus::Succeeded).is_empty());
+        assert!(allowed(TxStatus::Failed).is_empty());
     }
 }
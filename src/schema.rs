@@ -1,41 +1,168 @@
 // @generated automatically by Diesel CLI.
 
+#[cfg(feature = "ssr")]
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "transition_source"))]
+    pub struct TransitionSource;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tx_origin"))]
+    pub struct TxOrigin;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tx_status"))]
+    pub struct TxStatus;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tx_type"))]
+    pub struct TxType;
+}
+
 #[cfg(feature = "ssr")]
 mod schema_inner {
     diesel::table! {
-        balance (id) {
-            id -> Int4,
-            received_sats -> Int8,
-            paid_sats -> Int8,
-            last_updated -> Timestamptz,
+        accounts (id) {
+            id -> Int8,
+            #[max_length = 66]
+            node_id -> Varchar,
+            #[max_length = 20]
+            kind -> Varchar,
+            created_at -> Timestamptz,
         }
     }
 
     diesel::table! {
-        transactions (id) {
+        idempotency_keys (endpoint, idempotency_key) {
+            #[max_length = 16]
+            endpoint -> Varchar,
+            #[max_length = 255]
+            idempotency_key -> Varchar,
+            #[max_length = 64]
+            request_hash -> Varchar,
+            response_status -> Nullable<Int4>,
+            response_body -> Nullable<Jsonb>,
+            created_at -> Timestamptz,
+            completed_at -> Nullable<Timestamptz>,
+            #[max_length = 64]
+            payment_hash -> Nullable<Varchar>,
+        }
+    }
+
+    diesel::table! {
+        invoice_sync_state (node_id) {
+            #[max_length = 66]
+            node_id -> Varchar,
+            add_index -> Int8,
+            settle_index -> Int8,
+            updated_at -> Timestamptz,
+            sync_add_index -> Int8,
+            full_sync_complete -> Bool,
+        }
+    }
+
+    diesel::table! {
+        ledger_entries (id) {
             id -> Int8,
+            transaction_id -> Int8,
             #[max_length = 20]
-            tx_type -> Varchar,
+            entry_type -> Varchar,
+            debit_account_id -> Int8,
+            credit_account_id -> Int8,
+            amount_msat -> Int8,
+            reverses_entry_id -> Nullable<Int8>,
+            created_at -> Timestamptz,
+        }
+    }
+
+    diesel::table! {
+        nodes (node_id) {
+            #[max_length = 66]
+            node_id -> Varchar,
+            #[max_length = 64]
+            name -> Varchar,
+            #[max_length = 10]
+            role -> Varchar,
+            created_at -> Timestamptz,
+            updated_at -> Timestamptz,
+        }
+    }
+
+    diesel::table! {
+        payment_sync_state (node_id) {
+            #[max_length = 66]
+            node_id -> Varchar,
+            payment_index -> Int8,
+            updated_at -> Timestamptz,
+        }
+    }
+
+    diesel::table! {
+        use diesel::sql_types::*;
+        use crate::schema::sql_types::TransitionSource;
+        use crate::schema::sql_types::TxStatus;
+
+        transaction_status_history (id) {
+            id -> Int8,
+            transaction_id -> Int8,
+            from_status -> Nullable<TxStatus>,
+            to_status -> TxStatus,
+            source -> TransitionSource,
+            created_at -> Timestamptz,
+        }
+    }
+
+    diesel::table! {
+        use diesel::sql_types::*;
+        use crate::schema::sql_types::TxOrigin;
+        use crate::schema::sql_types::TxStatus;
+        use crate::schema::sql_types::TxType;
+
+        transactions (id) {
+            id -> Int8,
+            tx_type -> TxType,
             #[max_length = 64]
             payment_hash -> Varchar,
             payment_request -> Text,
-            amount_sats -> Int8,
             description -> Nullable<Text>,
-            #[max_length = 20]
-            status -> Varchar,
+            status -> TxStatus,
             #[max_length = 64]
             preimage -> Nullable<Varchar>,
-            fee_sats -> Nullable<Int8>,
             failure_reason -> Nullable<Text>,
             expires_at -> Nullable<Timestamptz>,
             #[max_length = 66]
             node_id -> Varchar,
             created_at -> Timestamptz,
             updated_at -> Timestamptz,
+            #[max_length = 20]
+            payment_status -> Nullable<Varchar>,
+            #[max_length = 64]
+            failure_code -> Nullable<Varchar>,
+            htlc_attempts -> Int4,
+            #[max_length = 66]
+            destination -> Nullable<Varchar>,
+            amount_msat -> Int8,
+            fee_msat -> Nullable<Int8>,
+            amount_sats -> Int8,
+            fee_sats -> Nullable<Int8>,
+            origin -> TxOrigin,
+            settled_at -> Nullable<Timestamptz>,
         }
     }
 
-    diesel::allow_tables_to_appear_in_same_query!(balance, transactions,);
+    diesel::joinable!(ledger_entries -> transactions (transaction_id));
+    diesel::joinable!(transaction_status_history -> transactions (transaction_id));
+
+    diesel::allow_tables_to_appear_in_same_query!(
+        accounts,
+        idempotency_keys,
+        invoice_sync_state,
+        ledger_entries,
+        nodes,
+        payment_sync_state,
+        transaction_status_history,
+        transactions,
+    );
 }
 
 #[cfg(feature = "ssr")]
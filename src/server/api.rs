@@ -1,15 +1,24 @@
+use std::future::Future;
+
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    body::Body,
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
     Json,
 };
-use serde::Deserialize;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use super::AppState;
 use crate::dto::*;
-use crate::models::{NewTransaction, TxStatus, TxType, UpdateTransaction};
-use crate::server::{db, lnd};
+use crate::models::{IdempotentEndpoint, NodeRole, TxStatus, TxType};
+use crate::server::export::ExportFormat;
+use crate::server::idempotency::{self, Execution, IdempotencyError, Outcome};
+use crate::server::lnd::InvoiceParams;
+use crate::server::{background, db, lnd, payments};
 
 // ===== Typed API errors =====
 
@@ -27,6 +36,15 @@ pub enum ApiError {
     #[error("Payment failed: {0}")]
     PaymentFailed(String),
 
+    #[error("No node is configured to {}, so this endpoint is disabled", .0.as_str())]
+    RoleDisabled(NodeRole),
+
+    #[error("Estimated routing fee of {fee_sats} sats exceeds the fee limit of {limit_sats} sats")]
+    FeeLimitExceeded { fee_sats: i64, limit_sats: i64 },
+
+    #[error(transparent)]
+    Idempotency(#[from] IdempotencyError),
+
     #[error(transparent)]
     Lnd(#[from] lnd::LndError),
 
@@ -34,21 +52,124 @@ pub enum ApiError {
     Database(#[from] db::DbError),
 }
 
+impl From<background::FollowPaymentError> for ApiError {
+    fn from(err: background::FollowPaymentError) -> Self {
+        match err {
+            background::FollowPaymentError::Lnd(e) => Self::Lnd(e),
+            background::FollowPaymentError::Db(e) => Self::Database(e),
+        }
+    }
+}
+
+impl ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_)
+            | Self::DuplicatePayment
+            | Self::PaymentFailed(_)
+            | Self::FeeLimitExceeded { .. }
+            | Self::Idempotency(IdempotencyError::InvalidKey) => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Idempotency(IdempotencyError::Mismatch | IdempotencyError::InProgress) => {
+                StatusCode::CONFLICT
+            }
+            Self::RoleDisabled(_) => StatusCode::NOT_IMPLEMENTED,
+            Self::Lnd(lnd::LndError::Timeout(_)) => StatusCode::GATEWAY_TIMEOUT,
+            Self::Lnd(_) | Self::Database(_) | Self::Idempotency(IdempotencyError::Database(_)) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn body(&self) -> serde_json::Value {
+        serde_json::json!({ "error": self.to_string() })
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
-        let status = match self {
-            Self::BadRequest(_) | Self::DuplicatePayment | Self::PaymentFailed(_) => {
-                StatusCode::BAD_REQUEST
-            }
-            Self::NotFound(_) => StatusCode::NOT_FOUND,
-            Self::Lnd(_) | Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+        (self.status_code(), Json(self.body())).into_response()
+    }
+}
 
-        (
-            status,
-            Json(serde_json::json!({ "error": self.to_string() })),
+/// Route layer refusing every request when no configured node has `role`.
+pub async fn require_role(
+    State((state, role)): State<(AppState, NodeRole)>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if !state.nodes.supports(role) {
+        return Err(ApiError::RoleDisabled(role));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Run a POST handler under the request's `Idempotency-Key`, if it sent one.
+async fn idempotent<R, Fut>(
+    state: &AppState,
+    headers: &HeaderMap,
+    endpoint: IdempotentEndpoint,
+    request_hash: String,
+    handler: Fut,
+) -> Result<Response, ApiError>
+where
+    R: Serialize + Send + 'static,
+    Fut: Future<Output = Result<(StatusCode, R), ApiError>> + Send + 'static,
+{
+    let Some(key) = headers.get(idempotency::HEADER) else {
+        let (status, body) = handler.await?;
+        return Ok((status, Json(body)).into_response());
+    };
+    let key = key.to_str().map_err(|_| IdempotencyError::InvalidKey)?;
+
+    let execution = idempotency::execute(
+        &state.db_pool,
+        endpoint,
+        key.to_string(),
+        request_hash,
+        handler,
+        keep_response,
+    )
+    .await?;
+
+    match execution {
+        Execution::Ran(result) => {
+            let (status, body) = result?;
+            Ok((status, Json(body)).into_response())
+        }
+        Execution::Replayed(outcome) => Ok((
+            outcome.status,
+            [(idempotency::REPLAYED_HEADER, "true")],
+            Json(outcome.body),
         )
-            .into_response()
+            .into_response()),
+        Execution::Dispatched(payment_hash) => {
+            let payment = payments::replay(&state.db_pool, &payment_hash).await?;
+            Ok((
+                StatusCode::OK,
+                [(idempotency::REPLAYED_HEADER, "true")],
+                Json(payment),
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Responses and client errors are kept for retries. Server errors are not,
+/// as the same request may well succeed once retried; a payment that was
+/// already sent is answered from its current state instead.
+fn keep_response<R: Serialize>(result: &Result<(StatusCode, R), ApiError>) -> Option<Outcome> {
+    match result {
+        Ok((status, body)) => Some(Outcome {
+            status: *status,
+            body: serde_json::to_value(body).unwrap_or_default(),
+        }),
+        Err(e) if e.status_code().is_client_error() => Some(Outcome {
+            status: e.status_code(),
+            body: e.body(),
+        }),
+        Err(_) => None,
     }
 }
 
@@ -56,27 +177,57 @@ impl IntoResponse for ApiError {
 
 pub async fn create_invoice(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(body): Json<CreateInvoiceRequest>,
-) -> Result<impl IntoResponse, ApiError> {
-    if body.amount_sats <= 0 {
-        return Err(ApiError::BadRequest("amount_sats must be positive".into()));
-    }
+) -> Result<Response, ApiError> {
+    let request_hash = idempotency::fingerprint(&body);
+    let handler = run_create_invoice(state.clone(), body);
+    idempotent(
+        &state,
+        &headers,
+        IdempotentEndpoint::Invoice,
+        request_hash,
+        handler,
+    )
+    .await
+}
 
-    let lnd_invoice = state
-        .lnd_receive
-        .create_invoice(body.amount_sats, body.description.clone())
-        .await?;
+async fn run_create_invoice(
+    state: AppState,
+    body: CreateInvoiceRequest,
+) -> Result<(StatusCode, InvoiceResponse), ApiError> {
+    let node = state
+        .nodes
+        .receiver(body.node_id.as_deref())
+        .map_err(ApiError::BadRequest)?;
+    let hold = body.hold;
+    let params =
+        InvoiceParams::resolve(body, &state.invoice_defaults).map_err(ApiError::BadRequest)?;
+    let (amount_msat, expiry_secs) = (params.amount_msat, params.expiry_secs);
+
+    // Hold invoices are created against a preimage only the caller gets to see
+    let (lnd_invoice, preimage) = if hold {
+        let preimage = lnd::random_preimage();
+        let payment_hash = Sha256::digest(&preimage).to_vec();
+        let invoice = node.lnd.create_hold_invoice(params, payment_hash).await?;
+        (invoice, Some(hex::encode(preimage)))
+    } else {
+        (node.lnd.create_invoice(params).await?, None)
+    };
 
     // Do not insert here: invoice events are persisted by the background LND
     // subscription to avoid duplicate inserts and sequence gaps.
 
     Ok((
         StatusCode::CREATED,
-        Json(InvoiceResponse {
+        InvoiceResponse {
             payment_request: lnd_invoice.payment_request,
             payment_hash: hex::encode(&lnd_invoice.r_hash),
-            amount_sats: body.amount_sats,
-        }),
+            amount_sats: amount_msat / 1000,
+            amount_msat,
+            expiry_secs,
+            preimage,
+        },
     ))
 }
 
@@ -86,105 +237,153 @@ pub async fn get_invoice(
     State(state): State<AppState>,
     Path(payment_hash): Path<String>,
 ) -> Result<Json<crate::models::Transaction>, ApiError> {
-    let tx = db::get_transaction_by_hash(&state.db_pool, TxType::Invoice, &payment_hash).await?;
-
-    match tx {
-        Some(tx) if tx.tx_type() == TxType::Invoice => Ok(Json(tx)),
-        Some(_) => Err(ApiError::NotFound(
-            "Invoice not found (hash belongs to a payment)".into(),
-        )),
-        None => Err(ApiError::NotFound("Invoice not found".into())),
-    }
+    find_invoice(&state, &payment_hash).await.map(Json)
 }
 
-// ===== POST /api/payment =====
+// ===== POST /api/invoice/{payment_hash}/settle =====
 
-pub async fn pay_invoice(
+pub async fn settle_invoice(
     State(state): State<AppState>,
-    Json(body): Json<PayInvoiceRequest>,
-) -> Result<Json<PaymentResponse>, ApiError> {
-    if body.payment_request.is_empty() {
-        return Err(ApiError::BadRequest("payment_request is required".into()));
+    Path(payment_hash): Path<String>,
+    Json(body): Json<SettleInvoiceRequest>,
+) -> Result<StatusCode, ApiError> {
+    // Hashes are stored and computed in lowercase hex
+    let payment_hash = payment_hash.to_lowercase();
+    let preimage = match hex::decode(body.preimage.trim()) {
+        Ok(bytes) if bytes.len() == 32 => bytes,
+        _ => {
+            return Err(ApiError::BadRequest(
+                "preimage must be a hex-encoded 32-byte value".into(),
+            ))
+        }
+    };
+    if hex::encode(Sha256::digest(&preimage)) != payment_hash {
+        return Err(ApiError::BadRequest(
+            "preimage does not match the payment hash".into(),
+        ));
     }
 
-    // Decode invoice
-    let decoded = state
-        .lnd_send
-        .decode_payment_request(body.payment_request.clone())
+    let tx = find_invoice(&state, &payment_hash).await?;
+    if tx.status != TxStatus::Accepted {
+        return Err(ApiError::BadRequest(format!(
+            "Only accepted invoices can be settled (status: {})",
+            tx.status.as_str()
+        )));
+    }
+
+    // The invoice subscription records the settlement and broadcasts it
+    invoice_node(&state, &tx)
+        .map_err(ApiError::BadRequest)?
+        .settle_invoice(preimage)
         .await?;
 
-    // Avoid duplicate payment records for the same invoice
-    let existing =
-        db::get_transaction_by_hash(&state.db_pool, TxType::Payment, &decoded.payment_hash).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ===== POST /api/invoice/{payment_hash}/cancel =====
+// ===== DELETE /api/invoice/{payment_hash} =====
 
-    if existing.is_some() {
-        return Err(ApiError::DuplicatePayment);
+pub async fn cancel_invoice(
+    State(state): State<AppState>,
+    Path(payment_hash): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let tx = find_invoice(&state, &payment_hash).await?;
+    if tx.status == TxStatus::Succeeded {
+        return Err(ApiError::BadRequest(
+            "Invoice is already settled and cannot be cancelled".into(),
+        ));
     }
 
-    // Save as pending
-    let new_tx = NewTransaction::new(
-        TxType::Payment,
-        decoded.payment_hash.clone(),
-        body.payment_request.clone(),
-        decoded.num_satoshis,
-        Some(decoded.description.clone()),
-        TxStatus::Pending,
-        None,
-        state.send_node_id.clone(),
-    );
-
-    db::create_transaction(&state.db_pool, new_tx).await?;
-
-    // Send payment via LND
-    let payment = state.lnd_send.send_payment(body.payment_request).await?;
-
-    if !payment.payment_error.is_empty() {
-        // Update status to failed
-        let update = UpdateTransaction::new(
-            Some(TxStatus::Failed),
-            None,
-            None,
-            Some(payment.payment_error.clone()),
-        );
-        let _ = db::update_transaction_status(
-            &state.db_pool,
-            TxType::Payment,
-            &decoded.payment_hash,
-            update,
-        )
-        .await;
+    // The invoice stops being payable and held payments are failed back to the
+    // payer; the invoice subscription marks the row expired and broadcasts it
+    let hash_bytes = hex::decode(&payment_hash)
+        .map_err(|_| ApiError::BadRequest("payment_hash must be hex-encoded".into()))?;
+    invoice_node(&state, &tx)
+        .map_err(ApiError::BadRequest)?
+        .cancel_invoice(hash_bytes)
+        .await?;
 
-        return Err(ApiError::PaymentFailed(payment.payment_error));
-    }
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    // Update status to succeeded
-    let update = UpdateTransaction::new(
-        Some(TxStatus::Succeeded),
-        Some(hex::encode(&payment.payment_preimage)),
-        payment
-            .payment_route
-            .as_ref()
-            .map(|r| r.total_fees_msat / 1000),
-        None,
-    );
-
-    let tx = db::update_transaction_status(
-        &state.db_pool,
-        TxType::Payment,
-        &decoded.payment_hash,
-        update,
+async fn find_invoice(
+    state: &AppState,
+    payment_hash: &str,
+) -> Result<crate::models::Transaction, ApiError> {
+    db::get_transaction_by_hash(&state.db_pool, TxType::Invoice, payment_hash)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))
+}
+
+/// The node that issued an invoice.
+fn invoice_node<'a>(
+    state: &'a AppState,
+    tx: &crate::models::Transaction,
+) -> Result<&'a dyn lnd::LightningBackend, String> {
+    state
+        .nodes
+        .get(&tx.node_id)
+        .map(|node| node.lnd.as_ref())
+        .ok_or_else(|| {
+            format!(
+                "Invoice belongs to node {}, which is not configured",
+                tx.node_id
+            )
+        })
+}
+
+// ===== POST /api/payment =====
+
+pub async fn pay_invoice(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<PayInvoiceRequest>,
+) -> Result<Response, ApiError> {
+    let request_hash = idempotency::fingerprint(&body);
+    let handler = run_pay_invoice(state.clone(), body);
+    idempotent(
+        &state,
+        &headers,
+        IdempotentEndpoint::Payment,
+        request_hash,
+        handler,
     )
-    .await?;
+    .await
+}
 
-    let _ = state
-        .broadcast_tx
-        .send(InvoiceEvent::PaymentSucceeded { tx });
+async fn run_pay_invoice(
+    state: AppState,
+    body: PayInvoiceRequest,
+) -> Result<(StatusCode, PaymentResponse), ApiError> {
+    let payment = payments::pay_invoice(state, body).await?;
+    Ok((StatusCode::OK, payment))
+}
 
-    Ok(Json(PaymentResponse {
-        payment_hash: decoded.payment_hash,
-        preimage: hex::encode(&payment.payment_preimage),
-        amount_sats: decoded.num_satoshis,
-    }))
+// ===== POST /api/keysend =====
+
+pub async fn keysend(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<KeysendRequest>,
+) -> Result<Response, ApiError> {
+    let request_hash = idempotency::fingerprint(&body);
+    let handler = run_keysend(state.clone(), body);
+    idempotent(
+        &state,
+        &headers,
+        IdempotentEndpoint::Keysend,
+        request_hash,
+        handler,
+    )
+    .await
+}
+
+async fn run_keysend(
+    state: AppState,
+    body: KeysendRequest,
+) -> Result<(StatusCode, PaymentResponse), ApiError> {
+    let payment = payments::keysend(state, body).await?;
+    Ok((StatusCode::OK, payment))
 }
 
 // ===== GET /api/payment/{payment_hash} =====
@@ -193,15 +392,10 @@ pub async fn get_payment(
     State(state): State<AppState>,
     Path(payment_hash): Path<String>,
 ) -> Result<Json<crate::models::Transaction>, ApiError> {
-    let tx = db::get_transaction_by_hash(&state.db_pool, TxType::Payment, &payment_hash).await?;
-
-    match tx {
-        Some(tx) if tx.tx_type() == TxType::Payment => Ok(Json(tx)),
-        Some(_) => Err(ApiError::NotFound(
-            "Payment not found (hash belongs to an invoice)".into(),
-        )),
-        None => Err(ApiError::NotFound("Payment not found".into())),
-    }
+    db::get_transaction_by_hash(&state.db_pool, TxType::Payment, &payment_hash)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound("Payment not found".into()))
 }
 
 // ===== GET /api/transactions =====
@@ -209,34 +403,148 @@ pub async fn get_payment(
 #[derive(Debug, Deserialize)]
 pub struct TransactionsQuery {
     pub limit: Option<i64>,
-    pub offset: Option<i64>,
+    pub cursor: Option<String>,
 }
 
+/// Filters come from the same query string, through `TransactionFilter`.
 pub async fn list_transactions(
     State(state): State<AppState>,
     Query(params): Query<TransactionsQuery>,
-) -> Result<Json<Vec<crate::models::Transaction>>, ApiError> {
-    let txs = db::list_transactions(
-        &state.db_pool,
-        params.limit.unwrap_or(50),
-        params.offset.unwrap_or(0),
+    Query(filter): Query<TransactionFilter>,
+) -> Result<Json<TransactionPage>, ApiError> {
+    let after = match params.cursor.as_deref() {
+        Some(cursor) => Some(
+            db::TransactionCursor::decode(cursor)
+                .ok_or_else(|| ApiError::BadRequest("Invalid cursor".into()))?,
+        ),
+        None => None,
+    };
+
+    let listing = db::list_transactions(&state.db_pool, &filter, after, params.limit).await?;
+
+    Ok(Json(listing.into_page()))
+}
+
+// ===== GET /api/transactions/export =====
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub format: ExportFormat,
+}
+
+/// Every transaction matching the listing filters, streamed as a download.
+pub async fn export_transactions(
+    State(state): State<AppState>,
+    Query(params): Query<ExportQuery>,
+    Query(filter): Query<TransactionFilter>,
+) -> Response {
+    let format = params.format;
+    let accounts = state.beancount_accounts.clone();
+
+    let header = Ok(format.header(&accounts));
+    let header = stream::once(async move { header });
+    let rows = db::stream_transactions(state.db_pool.clone(), filter)
+        .map_ok(move |tx| format.row(&tx, &accounts))
+        .inspect_err(|e| tracing::error!("Transaction export failed: {e}"));
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", format.file_name()),
+            ),
+        ],
+        Body::from_stream(header.chain(rows)),
     )
-    .await?;
+        .into_response()
+}
 
-    Ok(Json(txs))
+// ===== GET /api/transactions/{payment_hash}/timeline =====
+
+pub async fn get_status_timeline(
+    State(state): State<AppState>,
+    Path(payment_hash): Path<String>,
+) -> Result<Json<Vec<StatusChangeDto>>, ApiError> {
+    let timeline = db::get_status_timeline(&state.db_pool, &payment_hash).await?;
+
+    if timeline.is_empty() {
+        return Err(ApiError::NotFound("Transaction not found".into()));
+    }
+
+    Ok(Json(
+        timeline
+            .into_iter()
+            .map(|(tx_type, change)| StatusChangeDto {
+                tx_type,
+                from_status: change.from_status,
+                to_status: change.to_status,
+                source: change.source,
+                changed_at: change.created_at.to_rfc3339(),
+            })
+            .collect(),
+    ))
 }
 
 // ===== GET /api/balance =====
 
-pub async fn get_balance(State(state): State<AppState>) -> Result<Json<BalanceDto>, ApiError> {
-    let balance =
-        db::get_balance_summary(&state.db_pool, &state.receive_node_id, &state.send_node_id)
-            .await?;
+#[derive(Debug, Deserialize)]
+pub struct BalanceQuery {
+    /// Only count this node's transactions.
+    pub node_id: Option<String>,
+}
+
+pub async fn get_balance(
+    State(state): State<AppState>,
+    Query(params): Query<BalanceQuery>,
+) -> Result<Json<BalanceDto>, ApiError> {
+    let balance = db::get_balance_summary(&state.db_pool, params.node_id.as_deref()).await?;
 
     Ok(Json(BalanceDto {
-        received_sats: balance.received_sats,
-        paid_sats: balance.paid_sats,
-        total_balance: balance.pending_received_sats - balance.pending_paid_sats,
+        received_sats: balance.received_msat / 1000,
+        received_msat: balance.received_msat,
+        paid_sats: balance.paid_msat / 1000,
+        paid_msat: balance.paid_msat,
+        fee_sats: balance.fees_msat / 1000,
+        fee_msat: balance.fees_msat,
+        total_balance: balance.balance_msat / 1000,
+        total_balance_msat: balance.balance_msat,
         last_updated: balance.last_updated.to_rfc3339(),
     }))
 }
+
+// ===== GET /api/ledger/check =====
+
+pub async fn check_ledger(State(state): State<AppState>) -> Result<Json<LedgerCheckDto>, ApiError> {
+    let check = db::check_ledger(&state.db_pool).await?;
+
+    if !check.mismatches.is_empty() || check.invalid_entries > 0 {
+        tracing::warn!(
+            "Ledger check failed: {} mismatched transactions, {} invalid entries",
+            check.mismatches.len(),
+            check.invalid_entries
+        );
+    }
+
+    Ok(Json(LedgerCheckDto {
+        ok: check.mismatches.is_empty() && check.invalid_entries == 0,
+        mismatches: check
+            .mismatches
+            .into_iter()
+            .map(|m| LedgerMismatchDto {
+                payment_hash: m.payment_hash,
+                tx_type: m.tx_type,
+                node_id: m.node_id,
+                expected_msat: m.expected_msat,
+                posted_msat: m.posted_msat,
+            })
+            .collect(),
+        invalid_entries: check.invalid_entries,
+    }))
+}
+
+// ===== GET /api/status =====
+
+pub async fn get_status(State(state): State<AppState>) -> Json<Vec<NodeStatus>> {
+    Json(state.nodes.statuses())
+}
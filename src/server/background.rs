@@ -1,83 +1,181 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use chrono::{DateTime, Utc};
 use tokio::sync::broadcast;
 use tokio_stream::StreamExt;
+use tonic_lnd::lnrpc;
 use tonic_lnd::lnrpc::invoice::InvoiceState;
-use tonic_lnd::{lnrpc, Client as LndClient};
+use tonic_lnd::lnrpc::payment::PaymentStatus as LndPaymentStatus;
+use tonic_lnd::lnrpc::PaymentFailureReason;
 
 use crate::dto::InvoiceEvent;
-use crate::models::{NewTransaction, TxStatus, TxType};
-use crate::server::db::{self, DbPool};
-
-/// Sync all existing invoices from LND into the database at startup.
-/// For each invoice: insert if not in DB, update status if changed, skip if identical.
-pub async fn sync_invoices_from_lnd(lnd_client: &mut LndClient, db_pool: &DbPool, node_id: &str) {
-    tracing::info!("Syncing existing invoices from LND...");
-
-    let request = lnrpc::ListInvoiceRequest {
-        pending_only: false,
-        index_offset: 0,
-        num_max_invoices: u64::MAX,
-        reversed: false,
+use crate::models::{
+    NewTransaction, PaymentStatus, Transaction, TransitionSource, TxStatus, TxType,
+    UpdateTransaction,
+};
+use crate::server::db::{self, DbError, DbPool};
+use crate::server::lnd::{LightningBackend, LndError, PaymentStream};
+use crate::server::nodes::{Node, NodeRegistry};
+
+/// A pending payment LND has no record of is only failed once it is this old,
+/// so a row inserted just before `SendPaymentV2` reaches the node is left alone.
+const UNDISPATCHED_PAYMENT_GRACE_SECS: i64 = 300;
+
+/// An invoice is only expired this long after its expiry, so a payment that
+/// arrived just in time has its settlement recorded first.
+const EXPIRED_INVOICE_GRACE_SECS: i64 = 60;
+
+/// How often idempotency keys past their TTL are deleted.
+const IDEMPOTENCY_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Stored `failure_code` of payments LND found no route for.
+pub const NO_ROUTE_FAILURE_CODE: &str = "no_route";
+
+/// Catch up at startup on invoices the subscription has not seen. Until a
+/// full load of every invoice has finished, the sync lists them all; later
+/// runs only fetch invoices added after the subscription's add index and
+/// leave settlements of older invoices to the subscription, which replays
+/// them from the stored settle index.
+///
+/// Invoices are listed `page_size` at a time and each page is upserted in one
+/// statement. A full load stores its own position after every page, so an
+/// interrupted load picks up where it stopped on the next start even when the
+/// subscription has recorded newer invoices meanwhile.
+pub async fn sync_invoices_from_lnd(
+    lnd: &dyn LightningBackend,
+    db_pool: &DbPool,
+    node_id: &str,
+    page_size: u64,
+) {
+    let indices = match db::get_invoice_indices(db_pool, node_id).await {
+        Ok(indices) => indices,
+        Err(e) => {
+            tracing::error!("Failed to load invoice indices: {}", e);
+            return;
+        }
+    };
+
+    let full_load = !indices.full_sync_complete;
+    let start_index = if full_load {
+        indices.sync_add_index
+    } else {
+        indices.add_index
     };
 
-    match lnd_client.lightning().list_invoices(request).await {
-        Ok(response) => {
-            let resp = response.into_inner();
-            let total = resp.invoices.len();
-            let mut changed = 0u32;
-            let mut unchanged = 0u32;
-
-            for inv in &resp.invoices {
-                let status = lnd_state_to_tx_status(inv.state);
-                let payment_hash = hex::encode(&inv.r_hash);
-
-                let expires_at = if inv.expiry > 0 && inv.creation_date > 0 {
-                    DateTime::from_timestamp(inv.creation_date + inv.expiry, 0)
-                        .map(|dt| dt.with_timezone(&Utc))
-                } else {
-                    None
-                };
-
-                let new_tx = NewTransaction::new(
-                    TxType::Invoice,
-                    payment_hash,
-                    inv.payment_request.clone(),
-                    inv.value,
-                    if inv.memo.is_empty() {
-                        None
-                    } else {
-                        Some(inv.memo.clone())
-                    },
-                    status,
-                    expires_at,
-                    node_id.to_string(),
+    if full_load {
+        tracing::info!(
+            "Loading all invoices from LND after add index {}...",
+            start_index
+        );
+    } else {
+        tracing::info!(
+            "Syncing invoices from LND after add index {}...",
+            start_index
+        );
+    }
+
+    let mut index_offset = start_index as u64;
+    let mut last_settle_index = 0u64;
+    let mut total = 0usize;
+    let mut changed = 0usize;
+
+    loop {
+        let request = lnrpc::ListInvoiceRequest {
+            pending_only: false,
+            index_offset,
+            num_max_invoices: page_size,
+            reversed: false,
+        };
+
+        let resp = match lnd.list_invoices(request).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to list invoices from LND after add index {}: {}",
+                    index_offset,
+                    e
                 );
+                return;
+            }
+        };
 
-                match db::upsert_transaction(db_pool, new_tx).await {
-                    Ok(Some(_)) => changed += 1,
-                    Ok(None) => unchanged += 1,
-                    Err(e) => tracing::error!("Failed to upsert invoice: {}", e),
-                }
+        if resp.invoices.is_empty() {
+            break;
+        }
+
+        let new_txs: Vec<NewTransaction> = resp
+            .invoices
+            .iter()
+            .map(|inv| invoice_to_new_transaction(inv, node_id))
+            .collect();
+
+        match db::upsert_transactions(db_pool, &new_txs, TransitionSource::InvoiceSync).await {
+            Ok(rows) => changed += rows.len(),
+            Err(e) => {
+                tracing::error!("Failed to upsert invoice page: {}", e);
+                return;
             }
+        }
 
-            tracing::info!(
-                "LND sync complete: {} total invoices, {} changed/added, {} unchanged",
-                total,
-                changed,
-                unchanged
-            );
+        total += resp.invoices.len();
+        index_offset = resp.last_index_offset;
+        for inv in &resp.invoices {
+            last_settle_index = last_settle_index.max(inv.settle_index);
         }
-        Err(e) => {
-            tracing::error!("Failed to list invoices from LND: {}", e);
+
+        let stored = if full_load {
+            db::advance_invoice_sync(db_pool, node_id, index_offset as i64).await
+        } else {
+            db::advance_invoice_indices(db_pool, node_id, index_offset as i64, 0).await
+        };
+        if let Err(e) = stored {
+            tracing::error!("Failed to store invoice indices: {}", e);
+        }
+
+        tracing::info!(
+            "Invoice sync progress: {} invoices up to add index {}",
+            total,
+            index_offset
+        );
+
+        if (resp.invoices.len() as u64) < page_size {
+            break;
         }
     }
+
+    // Only a load that listed every invoice in one go has seen every
+    // settlement; otherwise older invoices may have settled below the newest
+    // settle index.
+    if full_load {
+        let settle_index = if start_index == 0 {
+            last_settle_index
+        } else {
+            0
+        };
+        if let Err(e) =
+            db::complete_invoice_sync(db_pool, node_id, index_offset as i64, settle_index as i64)
+                .await
+        {
+            tracing::error!("Failed to store invoice indices: {}", e);
+        }
+    }
+
+    tracing::info!(
+        "LND sync complete: {} invoices synced, {} added or changed, {} unchanged",
+        total,
+        changed,
+        total - changed
+    );
 }
 
 /// Subscribe to LND invoice events using a dedicated LND connection.
 /// When a new invoice is created or its state changes, it is upserted into the DB
-/// and broadcast via WebSocket to all connected clients.
+/// and broadcast via WebSocket to all connected clients. Every (re)subscription
+/// resumes from the stored indices, so LND replays whatever was added or
+/// settled while the stream was down.
 pub async fn subscribe_to_invoices(
-    mut lnd_client: LndClient,
+    lnd: Arc<dyn LightningBackend>,
     db_pool: DbPool,
     broadcast_tx: broadcast::Sender<InvoiceEvent>,
     node_id: String,
@@ -85,19 +183,21 @@ pub async fn subscribe_to_invoices(
     tracing::info!("Starting invoice subscription task");
 
     loop {
-        let subscription = lnrpc::InvoiceSubscription {
-            add_index: 0,
-            settle_index: 0,
-        };
+        let indices = db::get_invoice_indices(&db_pool, &node_id)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!(
+                    "Failed to load invoice indices, subscribing from now: {}",
+                    e
+                );
+                Default::default()
+            });
 
-        match lnd_client
-            .lightning()
-            .subscribe_invoices(subscription)
+        match lnd
+            .subscribe_invoices(indices.add_index as u64, indices.settle_index as u64)
             .await
         {
-            Ok(response) => {
-                let mut stream = response.into_inner();
-
+            Ok(mut stream) => {
                 while let Some(invoice_result) = stream.next().await {
                     match invoice_result {
                         Ok(invoice) => {
@@ -132,7 +232,41 @@ async fn handle_invoice_event(
     broadcast_tx: &broadcast::Sender<InvoiceEvent>,
     node_id: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let payment_hash = hex::encode(&invoice.r_hash);
+    let status = lnd_state_to_tx_status(invoice.state);
+
+    // Upsert: creates if new, updates if status changed, skips if same
+    let new_tx = invoice_to_new_transaction(invoice, node_id);
+
+    let result =
+        db::upsert_transaction(db_pool, new_tx, TransitionSource::InvoiceSubscription).await?;
+    db::advance_invoice_indices(
+        db_pool,
+        node_id,
+        invoice.add_index as i64,
+        invoice.settle_index as i64,
+    )
+    .await?;
+
+    // Only broadcast if something actually changed
+    if let Some(tx) = result {
+        let event = match status {
+            TxStatus::Pending => InvoiceEvent::InvoiceCreated { tx },
+            TxStatus::Accepted => InvoiceEvent::InvoiceAccepted { tx },
+            TxStatus::Succeeded => InvoiceEvent::InvoiceSettled { tx },
+            TxStatus::Expired => InvoiceEvent::InvoiceExpired { tx },
+            _ => return Ok(()),
+        };
+
+        let _ = broadcast_tx.send(event);
+    }
+
+    Ok(())
+}
+
+/// Map an LND invoice onto a transaction row. Paid (accepted or settled)
+/// invoices record what was actually paid, which differs from `value` for
+/// amountless invoices and overpayments.
+fn invoice_to_new_transaction(invoice: &lnrpc::Invoice, node_id: &str) -> NewTransaction {
     let status = lnd_state_to_tx_status(invoice.state);
 
     let expires_at = if invoice.expiry > 0 && invoice.creation_date > 0 {
@@ -142,12 +276,21 @@ async fn handle_invoice_event(
         None
     };
 
-    // Upsert: creates if new, updates if status changed, skips if same
-    let new_tx = NewTransaction::new(
+    let amount_msat = if matches!(status, TxStatus::Accepted | TxStatus::Succeeded) {
+        invoice.amt_paid_msat
+    } else {
+        invoice.value_msat
+    };
+
+    let settled_at = Some(invoice.settle_date)
+        .filter(|date| *date > 0 && status == TxStatus::Succeeded)
+        .and_then(|date| DateTime::from_timestamp(date, 0));
+
+    NewTransaction::new(
         TxType::Invoice,
-        payment_hash,
+        hex::encode(&invoice.r_hash),
         invoice.payment_request.clone(),
-        invoice.value,
+        amount_msat,
         if invoice.memo.is_empty() {
             None
         } else {
@@ -156,31 +299,659 @@ async fn handle_invoice_event(
         status,
         expires_at,
         node_id.to_string(),
-    );
+    )
+    .with_settled_at(settled_at)
+}
 
-    let result = db::upsert_transaction(db_pool, new_tx).await?;
+/// Persist one payment update from the router (`SendPaymentV2`) onto its
+/// payment row and broadcast the transition. Returns the updated row, or
+/// `None` if the row's status may not change that way (e.g. it is final).
+pub async fn handle_payment_update(
+    payment: &lnrpc::Payment,
+    db_pool: &DbPool,
+    broadcast_tx: &broadcast::Sender<InvoiceEvent>,
+    source: TransitionSource,
+) -> Result<Option<Transaction>, DbError> {
+    let payment_status = lnd_payment_status(payment.status);
 
-    // Only broadcast if something actually changed
-    if let Some(tx) = result {
-        let event = match status {
-            TxStatus::Pending => InvoiceEvent::InvoiceCreated { tx },
-            TxStatus::Succeeded => InvoiceEvent::InvoiceSettled { tx },
-            TxStatus::Expired => InvoiceEvent::InvoiceExpired { tx },
-            _ => return Ok(()),
+    let update =
+        match payment_status {
+            PaymentStatus::InFlight => {
+                UpdateTransaction::new(Some(TxStatus::Pending), None, None, None)
+                    .with_payment_progress(payment_status, None, payment.htlcs.len() as i32)
+            }
+            PaymentStatus::Succeeded => UpdateTransaction::new(
+                Some(TxStatus::Succeeded),
+                Some(payment.payment_preimage.clone()),
+                Some(payment.fee_msat),
+                None,
+            )
+            .with_payment_progress(payment_status, None, payment.htlcs.len() as i32),
+            PaymentStatus::Failed => {
+                let (code, reason) = payment_failure_reason(payment.failure_reason);
+                UpdateTransaction::new(Some(TxStatus::Failed), None, None, Some(reason.to_string()))
+                    .with_payment_progress(
+                        payment_status,
+                        Some(code.to_string()),
+                        payment.htlcs.len() as i32,
+                    )
+            }
         };
 
-        let _ = broadcast_tx.send(event);
+    let Some(tx) = db::update_transaction_status(
+        db_pool,
+        TxType::Payment,
+        &payment.payment_hash,
+        update,
+        source,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let event = match payment_status {
+        PaymentStatus::InFlight => InvoiceEvent::PaymentInFlight { tx: tx.clone() },
+        PaymentStatus::Succeeded => InvoiceEvent::PaymentSucceeded { tx: tx.clone() },
+        PaymentStatus::Failed => InvoiceEvent::PaymentFailed { tx: tx.clone() },
+    };
+    let _ = broadcast_tx.send(event);
+
+    Ok(Some(tx))
+}
+
+/// Import payments the node sent without THOrs (e.g. with lncli) since the
+/// last imported payment index, settled, failed and in flight alike.
+/// Payments THOrs made already have a row and are left alone; new ones are
+/// recorded as `external` with their fee, preimage or failure reason.
+///
+/// Payments are listed `page_size` at a time and the payment index is stored
+/// after every page, like the invoice sync. Imported payments still in flight
+/// are finished by `reconcile_pending_payments`.
+pub async fn sync_payments_from_lnd(
+    lnd: &dyn LightningBackend,
+    db_pool: &DbPool,
+    broadcast_tx: &broadcast::Sender<InvoiceEvent>,
+    node_id: &str,
+    page_size: u64,
+) {
+    let mut index_offset = match db::get_payment_index(db_pool, node_id).await {
+        Ok(index) => index as u64,
+        Err(e) => {
+            tracing::error!("Failed to load payment index: {}", e);
+            return;
+        }
+    };
+    let mut total = 0usize;
+    let mut imported = 0usize;
+
+    loop {
+        let request = lnrpc::ListPaymentsRequest {
+            include_incomplete: true,
+            index_offset,
+            max_payments: page_size,
+            reversed: false,
+            count_total_payments: false,
+        };
+
+        let resp = match lnd.list_payments(request).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to list payments from LND after payment index {}: {}",
+                    index_offset,
+                    e
+                );
+                return;
+            }
+        };
+
+        if resp.payments.is_empty() {
+            break;
+        }
+
+        let new_txs: Vec<NewTransaction> = resp
+            .payments
+            .iter()
+            .map(|payment| payment_to_new_transaction(payment, node_id))
+            .collect();
+
+        match db::import_transactions(db_pool, &new_txs, TransitionSource::PaymentImport).await {
+            Ok(rows) => {
+                imported += rows.len();
+                for tx in rows {
+                    let event = match tx.status {
+                        TxStatus::Succeeded => InvoiceEvent::PaymentSucceeded { tx },
+                        TxStatus::Failed => InvoiceEvent::PaymentFailed { tx },
+                        _ => InvoiceEvent::PaymentInFlight { tx },
+                    };
+                    let _ = broadcast_tx.send(event);
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to import payment page: {}", e);
+                return;
+            }
+        }
+
+        total += resp.payments.len();
+        index_offset = resp.last_index_offset;
+
+        if let Err(e) = db::advance_payment_index(db_pool, node_id, index_offset as i64).await {
+            tracing::error!("Failed to store payment index: {}", e);
+        }
+
+        if (resp.payments.len() as u64) < page_size {
+            break;
+        }
+    }
+
+    if total > 0 {
+        tracing::info!(
+            "Payment import complete: {} payments listed, {} imported as external",
+            total,
+            imported
+        );
+    }
+}
+
+/// Map a payment LND made without THOrs onto an `external` payment row.
+fn payment_to_new_transaction(payment: &lnrpc::Payment, node_id: &str) -> NewTransaction {
+    let payment_status = lnd_payment_status(payment.status);
+
+    let created_at = Some(payment.creation_time_ns)
+        .filter(|ns| *ns > 0)
+        .map(DateTime::from_timestamp_nanos)
+        .unwrap_or_else(Utc::now);
+
+    // The last hop of any attempt is the recipient
+    let destination = payment
+        .htlcs
+        .iter()
+        .filter_map(|htlc| htlc.route.as_ref())
+        .find_map(|route| route.hops.last())
+        .map(|hop| hop.pub_key.clone());
+
+    let (status, failure_code) = match payment_status {
+        PaymentStatus::InFlight => (TxStatus::Pending, None),
+        PaymentStatus::Succeeded => (TxStatus::Succeeded, None),
+        PaymentStatus::Failed => (TxStatus::Failed, Some(payment.failure_reason)),
+    };
+    let failure = failure_code.map(payment_failure_reason);
+
+    // A payment settles when its last HTLC resolves
+    let settled_at = payment
+        .htlcs
+        .iter()
+        .map(|htlc| htlc.resolve_time_ns)
+        .filter(|ns| *ns > 0 && status == TxStatus::Succeeded)
+        .max()
+        .map(DateTime::from_timestamp_nanos);
+
+    let new_tx = NewTransaction::new(
+        TxType::Payment,
+        payment.payment_hash.clone(),
+        payment.payment_request.clone(),
+        payment.value_msat,
+        None,
+        status,
+        None,
+        node_id.to_string(),
+    )
+    .external(created_at)
+    .with_outcome(
+        (status == TxStatus::Succeeded).then(|| payment.payment_preimage.clone()),
+        (status == TxStatus::Succeeded).then_some(payment.fee_msat),
+        failure.map(|(_, reason)| reason.to_string()),
+    )
+    .with_payment_progress(
+        payment_status,
+        failure.map(|(code, _)| code.to_string()),
+        payment.htlcs.len() as i32,
+    )
+    .with_settled_at(settled_at);
+
+    match destination {
+        Some(destination) => new_tx.with_destination(destination),
+        None => new_tx,
+    }
+}
+
+/// Reconcile every pending payment row of `node_id` with the router's view.
+/// Payments that settled or failed while nobody was streaming their updates
+/// (e.g. the process died mid-payment) are finalized and broadcast.
+pub async fn reconcile_pending_payments(
+    lnd: &dyn LightningBackend,
+    db_pool: &DbPool,
+    broadcast_tx: &broadcast::Sender<InvoiceEvent>,
+    node_id: &str,
+) {
+    let pending = match db::list_pending_payments(db_pool, node_id).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            tracing::error!("Failed to load pending payments: {}", e);
+            return;
+        }
+    };
+
+    if pending.is_empty() {
+        return;
+    }
+
+    tracing::info!("Reconciling {} pending payments with LND...", pending.len());
+    let mut resolved = 0u32;
+
+    for tx in &pending {
+        let payment = match lnd.track_payment(tx.payment_hash.clone()).await {
+            Ok(mut stream) => stream.next().await,
+            Err(LndError::Rpc(status)) if status.code() == tonic_lnd::tonic::Code::NotFound => {
+                let age = Utc::now() - tx.created_at;
+                if age.num_seconds() >= UNDISPATCHED_PAYMENT_GRACE_SECS {
+                    match fail_undispatched_payment(tx, db_pool, broadcast_tx).await {
+                        Ok(()) => resolved += 1,
+                        Err(e) => {
+                            tracing::error!("Failed to update payment {}: {}", tx.payment_hash, e)
+                        }
+                    }
+                }
+                continue;
+            }
+            Err(e) => {
+                tracing::error!("Failed to track payment {}: {}", tx.payment_hash, e);
+                continue;
+            }
+        };
+
+        match payment {
+            Some(Ok(payment)) if lnd_payment_status(payment.status) != PaymentStatus::InFlight => {
+                match handle_payment_update(
+                    &payment,
+                    db_pool,
+                    broadcast_tx,
+                    TransitionSource::PaymentReconciliation,
+                )
+                .await
+                {
+                    Ok(Some(_)) => resolved += 1,
+                    Ok(None) => {}
+                    Err(e) => {
+                        tracing::error!("Failed to update payment {}: {}", tx.payment_hash, e)
+                    }
+                }
+            }
+            // Still in flight: a later pass picks up the final state.
+            Some(Ok(_)) => {}
+            Some(Err(e)) => tracing::error!("Stream error tracking {}: {}", tx.payment_hash, e),
+            None => tracing::warn!(
+                "Payment tracking for {} ended without an update",
+                tx.payment_hash
+            ),
+        }
+    }
+
+    tracing::info!(
+        "Payment reconciliation complete: {} pending, {} resolved",
+        pending.len(),
+        resolved
+    );
+}
+
+/// Periodically import external payments and re-run
+/// `reconcile_pending_payments` for the lifetime of the process.
+pub async fn reconcile_payments(
+    lnd: Arc<dyn LightningBackend>,
+    db_pool: DbPool,
+    broadcast_tx: broadcast::Sender<InvoiceEvent>,
+    node_id: String,
+    interval: Duration,
+    page_size: u64,
+) {
+    tracing::info!(
+        "Starting payment reconciliation task (every {:?})",
+        interval
+    );
+
+    loop {
+        tokio::time::sleep(interval).await;
+        sync_payments_from_lnd(lnd.as_ref(), &db_pool, &broadcast_tx, &node_id, page_size).await;
+        reconcile_pending_payments(lnd.as_ref(), &db_pool, &broadcast_tx, &node_id).await;
+    }
+}
+
+/// The node never saw this payment, so `SendPaymentV2` was never reached.
+async fn fail_undispatched_payment(
+    tx: &Transaction,
+    db_pool: &DbPool,
+    broadcast_tx: &broadcast::Sender<InvoiceEvent>,
+) -> Result<(), DbError> {
+    let update = UpdateTransaction::new(
+        Some(TxStatus::Failed),
+        None,
+        None,
+        Some("payment was never dispatched to LND".to_string()),
+    )
+    .with_payment_progress(PaymentStatus::Failed, Some("not_dispatched".to_string()), 0);
+
+    let updated = db::update_transaction_status(
+        db_pool,
+        TxType::Payment,
+        &tx.payment_hash,
+        update,
+        TransitionSource::PaymentReconciliation,
+    )
+    .await?;
+    if let Some(tx) = updated {
+        let _ = broadcast_tx.send(InvoiceEvent::PaymentFailed { tx });
     }
 
     Ok(())
 }
 
+/// Failure while following the router updates of a payment.
+#[derive(Debug, thiserror::Error)]
+pub enum FollowPaymentError {
+    #[error(transparent)]
+    Lnd(#[from] LndError),
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+/// Persist every update of a freshly started payment until it settles or
+/// fails. Returns the final row, or `None` if the stream ended before that.
+pub async fn follow_payment(
+    mut updates: PaymentStream,
+    db_pool: &DbPool,
+    broadcast_tx: &broadcast::Sender<InvoiceEvent>,
+) -> Result<Option<Transaction>, FollowPaymentError> {
+    while let Some(payment) = updates.next().await {
+        let payment = payment?;
+        let updated = match handle_payment_update(
+            &payment,
+            db_pool,
+            broadcast_tx,
+            TransitionSource::PaymentUpdate,
+        )
+        .await?
+        {
+            Some(updated) => updated,
+            // Reconciliation may have finalized the row first
+            None => {
+                match db::get_transaction_by_hash(db_pool, TxType::Payment, &payment.payment_hash)
+                    .await?
+                {
+                    Some(current) => current,
+                    None => continue,
+                }
+            }
+        };
+        if updated.status.is_final() {
+            return Ok(Some(updated));
+        }
+    }
+
+    Ok(None)
+}
+
+fn lnd_payment_status(status: i32) -> PaymentStatus {
+    match status {
+        s if s == LndPaymentStatus::Succeeded as i32 => PaymentStatus::Succeeded,
+        s if s == LndPaymentStatus::Failed as i32 => PaymentStatus::Failed,
+        _ => PaymentStatus::InFlight,
+    }
+}
+
+/// Map LND's `PaymentFailureReason` to a stored code and a readable message.
+fn payment_failure_reason(reason: i32) -> (&'static str, &'static str) {
+    match reason {
+        r if r == PaymentFailureReason::FailureReasonTimeout as i32 => {
+            ("timeout", "payment timed out")
+        }
+        r if r == PaymentFailureReason::FailureReasonNoRoute as i32 => {
+            (NO_ROUTE_FAILURE_CODE, "no route to destination")
+        }
+        r if r == PaymentFailureReason::FailureReasonIncorrectPaymentDetails as i32 => (
+            "incorrect_payment_details",
+            "payment rejected by destination (unknown hash, wrong amount or expired invoice)",
+        ),
+        r if r == PaymentFailureReason::FailureReasonInsufficientBalance as i32 => {
+            ("insufficient_balance", "insufficient local balance")
+        }
+        _ => ("error", "unexpected payment error"),
+    }
+}
+
 fn lnd_state_to_tx_status(state: i32) -> TxStatus {
     match state {
         s if s == InvoiceState::Open as i32 => TxStatus::Pending,
         s if s == InvoiceState::Settled as i32 => TxStatus::Succeeded,
         s if s == InvoiceState::Canceled as i32 => TxStatus::Expired,
-        s if s == InvoiceState::Accepted as i32 => TxStatus::Pending,
+        s if s == InvoiceState::Accepted as i32 => TxStatus::Accepted,
         _ => TxStatus::Pending,
     }
 }
+
+/// Cancel pending invoices past their expiry on their node, then mark them
+/// expired and broadcast each. LND does not reliably report expired invoices
+/// as canceled, so without this they would stay pending. An invoice the node
+/// could not cancel stays pending until the next sweep, and one it already
+/// settled is left for the invoice subscription to record.
+pub async fn expire_overdue_invoices(
+    nodes: &NodeRegistry,
+    db_pool: &DbPool,
+    broadcast_tx: &broadcast::Sender<InvoiceEvent>,
+) {
+    let cutoff = Utc::now() - chrono::Duration::seconds(EXPIRED_INVOICE_GRACE_SECS);
+
+    let overdue = match db::list_overdue_invoices(db_pool, cutoff).await {
+        Ok(overdue) => overdue,
+        Err(e) => {
+            tracing::error!("Failed to load overdue invoices: {}", e);
+            return;
+        }
+    };
+
+    let mut cancelled = Vec::with_capacity(overdue.len());
+    for tx in &overdue {
+        // Invoices of nodes no longer configured can only be expired here
+        let Some(node) = nodes.get(&tx.node_id) else {
+            cancelled.push(tx.id);
+            continue;
+        };
+        let Ok(hash_bytes) = hex::decode(&tx.payment_hash) else {
+            cancelled.push(tx.id);
+            continue;
+        };
+
+        match node.lnd.cancel_invoice(hash_bytes).await {
+            Ok(()) => cancelled.push(tx.id),
+            Err(LndError::Rpc(status)) if status.code() == tonic_lnd::tonic::Code::NotFound => {
+                cancelled.push(tx.id)
+            }
+            Err(LndError::Rpc(status))
+                if status.code() == tonic_lnd::tonic::Code::FailedPrecondition =>
+            {
+                tracing::info!(
+                    "Overdue invoice {} was settled on {}",
+                    tx.payment_hash,
+                    node.name
+                );
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to cancel overdue invoice {} on {}: {}",
+                    tx.payment_hash,
+                    node.name,
+                    e
+                );
+            }
+        }
+    }
+
+    if cancelled.is_empty() {
+        return;
+    }
+
+    let expired = match db::expire_invoices(db_pool, &cancelled).await {
+        Ok(expired) => expired,
+        Err(e) => {
+            tracing::error!("Failed to expire overdue invoices: {}", e);
+            return;
+        }
+    };
+
+    if !expired.is_empty() {
+        tracing::info!("Expired {} overdue invoices", expired.len());
+    }
+    for tx in expired {
+        let _ = broadcast_tx.send(InvoiceEvent::InvoiceExpired { tx });
+    }
+}
+
+/// Periodically run `expire_overdue_invoices` for the lifetime of the process.
+pub async fn sweep_expired_invoices(
+    nodes: NodeRegistry,
+    db_pool: DbPool,
+    broadcast_tx: broadcast::Sender<InvoiceEvent>,
+    interval: Duration,
+) {
+    tracing::info!("Starting invoice expiry sweep (every {:?})", interval);
+
+    loop {
+        tokio::time::sleep(interval).await;
+        expire_overdue_invoices(&nodes, &db_pool, &broadcast_tx).await;
+    }
+}
+
+/// Delete idempotency keys older than `ttl`; retries after that run the
+/// request again.
+pub async fn prune_idempotency_keys(db_pool: DbPool, ttl: Duration) {
+    tracing::info!("Starting idempotency key pruning (keys kept for {:?})", ttl);
+
+    loop {
+        tokio::time::sleep(IDEMPOTENCY_PRUNE_INTERVAL).await;
+
+        let cutoff = Utc::now() - ttl;
+        match db::prune_idempotency_keys(&db_pool, cutoff).await {
+            Ok(0) => {}
+            Ok(pruned) => tracing::info!("Pruned {pruned} idempotency keys"),
+            Err(e) => tracing::warn!("Failed to prune idempotency keys: {e}"),
+        }
+    }
+}
+
+/// Forward every health change of a node to connected clients.
+pub async fn broadcast_node_health(node: Node, broadcast_tx: broadcast::Sender<InvoiceEvent>) {
+    let mut health = node.lnd.health();
+
+    while health.changed().await.is_ok() {
+        health.borrow_and_update();
+        let status = node.status();
+        tracing::info!(node = %status.name, health = ?status.health, "Node health changed");
+        let _ = broadcast_tx.send(InvoiceEvent::NodeHealthChanged { node: status });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::dto::{CreateInvoiceRequest, InvoiceDefaults};
+    use crate::server::lnd::InvoiceParams;
+    use crate::server::mock::MockNetwork;
+
+    fn invoice_params(amount_sats: i64) -> InvoiceParams {
+        let request = CreateInvoiceRequest {
+            amount_sats: Some(amount_sats),
+            description: Some("in process".into()),
+            ..Default::default()
+        };
+        InvoiceParams::resolve(request, &InvoiceDefaults::default()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn invoice_is_created_paid_and_settled() {
+        let network = MockNetwork::new();
+        let (alice, bob) = (network.node("alice"), network.node("bob"));
+        let mut invoices = alice.subscribe_invoices(0, 0).await.unwrap();
+
+        let invoice = alice.create_invoice(invoice_params(1_000)).await.unwrap();
+        let opened = invoices.next().await.unwrap().unwrap();
+        let created = invoice_to_new_transaction(&opened, alice.pubkey());
+        assert_eq!(created.status, TxStatus::Pending);
+        assert_eq!(created.payment_hash, hex::encode(&invoice.r_hash));
+        assert_eq!(created.amount_msat, 1_000_000);
+        assert_eq!(created.description.as_deref(), Some("in process"));
+        assert!(created.expires_at.is_some());
+
+        let updates: Vec<_> = bob
+            .send_payment(invoice.payment_request, 0, 10_000)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let statuses: Vec<_> = updates
+            .iter()
+            .map(|update| payment_to_new_transaction(update.as_ref().unwrap(), bob.pubkey()))
+            .map(|tx| tx.status)
+            .collect();
+        assert_eq!(statuses, [TxStatus::Pending, TxStatus::Succeeded]);
+
+        let paid = payment_to_new_transaction(updates[1].as_ref().unwrap(), bob.pubkey());
+        assert_eq!(paid.destination.as_deref(), Some(alice.pubkey()));
+        assert_eq!(paid.fee_msat, Some(2_000));
+
+        let settled = invoices.next().await.unwrap().unwrap();
+        let received = invoice_to_new_transaction(&settled, alice.pubkey());
+        assert_eq!(received.status, TxStatus::Succeeded);
+        assert_eq!(received.amount_msat, 1_000_000);
+        assert!(received.settled_at.is_some());
+        assert_eq!(
+            paid.preimage.as_deref(),
+            Some(hex::encode(&settled.r_preimage).as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn hold_invoice_settles_once_released() {
+        let network = MockNetwork::new();
+        let (alice, bob) = (network.node("alice"), network.node("bob"));
+        let mut invoices = alice.subscribe_invoices(0, 0).await.unwrap();
+
+        let preimage = vec![7; 32];
+        let payment_hash = Sha256::digest(&preimage).to_vec();
+        let invoice = alice
+            .create_hold_invoice(invoice_params(500), payment_hash.clone())
+            .await
+            .unwrap();
+        invoices.next().await.unwrap().unwrap();
+
+        let mut updates = bob
+            .send_payment(invoice.payment_request, 0, 10_000)
+            .await
+            .unwrap();
+        let in_flight = updates.next().await.unwrap().unwrap();
+        assert_eq!(
+            payment_to_new_transaction(&in_flight, bob.pubkey()).status,
+            TxStatus::Pending
+        );
+
+        let accepted = invoices.next().await.unwrap().unwrap();
+        let held = invoice_to_new_transaction(&accepted, alice.pubkey());
+        assert_eq!(held.status, TxStatus::Accepted);
+        assert_eq!(held.amount_msat, 500_000);
+
+        alice.settle_invoice(preimage.clone()).await.unwrap();
+
+        let settled = invoices.next().await.unwrap().unwrap();
+        assert_eq!(
+            invoice_to_new_transaction(&settled, alice.pubkey()).status,
+            TxStatus::Succeeded
+        );
+        let succeeded = updates.next().await.unwrap().unwrap();
+        let paid = payment_to_new_transaction(&succeeded, bob.pubkey());
+        assert_eq!(paid.status, TxStatus::Succeeded);
+        assert_eq!(paid.payment_hash, hex::encode(&payment_hash));
+        assert_eq!(paid.preimage, Some(hex::encode(&preimage)));
+    }
+}
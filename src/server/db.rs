@@ -1,26 +1,136 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use diesel::dsl::{max, sql};
+use diesel::pg::Pg;
 use diesel::prelude::*;
-use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use diesel::sql_types::{BigInt, Nullable};
+use diesel::upsert::excluded;
+use diesel_async::scoped_futures::ScopedFutureExt;
 use diesel_async::RunQueryDsl;
 use diesel_async::{
     pooled_connection::{deadpool::Pool, AsyncDieselConnectionManager},
-    AsyncPgConnection,
+    AsyncConnection, AsyncPgConnection,
 };
+use futures::stream::{self, Stream, TryStreamExt};
 
+use crate::dto::{TransactionFilter, TransactionPage};
 use crate::models::*;
-use crate::schema::{balance, transactions};
+use crate::schema::{
+    accounts, idempotency_keys, invoice_sync_state, ledger_entries, nodes, payment_sync_state,
+    transaction_status_history, transactions,
+};
 
 #[derive(Debug, Clone)]
 pub struct BalanceSummary {
-    pub received_sats: i64,
-    pub paid_sats: i64,
-    pub pending_received_sats: i64,
-    pub pending_paid_sats: i64,
+    pub received_msat: i64,
+    pub paid_msat: i64,
+    pub fees_msat: i64,
+    /// Funds on the node's `lightning` account, received less paid and fees.
+    pub balance_msat: i64,
     pub last_updated: DateTime<Utc>,
 }
 
+/// A transaction whose ledger entries do not add up to what it moved.
+#[derive(Debug, Clone, QueryableByName)]
+pub struct LedgerMismatch {
+    #[diesel(sql_type = diesel::sql_types::Varchar)]
+    pub payment_hash: String,
+    #[diesel(sql_type = crate::schema::sql_types::TxType)]
+    pub tx_type: TxType,
+    #[diesel(sql_type = diesel::sql_types::Varchar)]
+    pub node_id: String,
+    /// Change to the node's `lightning` account the transaction calls for.
+    #[diesel(sql_type = BigInt)]
+    pub expected_msat: i64,
+    /// Change its entries actually made.
+    #[diesel(sql_type = BigInt)]
+    pub posted_msat: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LedgerCheck {
+    pub mismatches: Vec<LedgerMismatch>,
+    /// Entries touching another node's accounts, and reversals that do not
+    /// mirror the entry they reverse.
+    pub invalid_entries: i64,
+}
+
+/// Position in a transaction listing, just past `(created_at, id)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: i64,
+}
+
+impl TransactionCursor {
+    pub fn after(tx: &Transaction) -> Self {
+        Self {
+            created_at: tx.created_at,
+            id: tx.id,
+        }
+    }
+
+    /// Opaque form handed to clients.
+    pub fn encode(&self) -> String {
+        hex::encode(format!(
+            "{}:{}",
+            self.created_at.timestamp_micros(),
+            self.id
+        ))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let raw = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+        let (micros, id) = raw.split_once(':')?;
+        Some(Self {
+            created_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionListing {
+    pub transactions: Vec<Transaction>,
+    /// Where the next page starts; `None` on the last page.
+    pub next: Option<TransactionCursor>,
+    pub total_count: i64,
+}
+
+impl TransactionListing {
+    pub fn into_page(self) -> TransactionPage {
+        TransactionPage {
+            transactions: self.transactions,
+            next_cursor: self.next.map(|cursor| cursor.encode()),
+            total_count: self.total_count,
+        }
+    }
+}
+
+/// Page size of a transaction listing that does not ask for one.
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 500;
+
+/// Where an idempotency key stands once a request has tried to claim it.
+#[derive(Debug, Clone)]
+pub enum IdempotencyClaim {
+    /// The key is new; the request runs and its response gets recorded.
+    Acquired,
+    /// The same request already finished with this response.
+    Completed {
+        status: i32,
+        body: serde_json::Value,
+    },
+    /// The same request sent the payment with this hash but recorded no
+    /// response, as it is still running or stopped short of one.
+    Dispatched { payment_hash: String },
+    /// The same request is still running.
+    InProgress,
+    /// The key was used for a different request.
+    Mismatch,
+}
+
 pub type DbPool = Pool<AsyncPgConnection>;
 
 #[derive(Debug, thiserror::Error)]
@@ -39,38 +149,93 @@ pub fn create_pool(database_url: &str) -> DbPool {
         .expect("Failed to create pool")
 }
 
+/// Insert a new transaction and record its first status.
 pub async fn create_transaction(
     pool: &DbPool,
     new_tx: NewTransaction,
+    source: TransitionSource,
 ) -> Result<Transaction, DbError> {
     let mut conn = pool.get().await?;
 
-    let result = diesel::insert_into(transactions::table)
-        .values(&new_tx)
-        .returning(Transaction::as_returning())
-        .get_result(&mut conn)
-        .await?;
+    conn.transaction::<_, DbError, _>(|conn| {
+        async move {
+            let tx = diesel::insert_into(transactions::table)
+                .values(&new_tx)
+                .returning(Transaction::as_returning())
+                .get_result(conn)
+                .await?;
 
-    Ok(result)
+            record_status_changes(
+                conn,
+                &[NewStatusChange::new(tx.id, None, tx.status, source)],
+            )
+            .await?;
+
+            Ok(tx)
+        }
+        .scope_boxed()
+    })
+    .await
 }
 
+/// Apply `update` to a transaction. A status change must be allowed by
+/// `TxStatus::can_transition_to`; otherwise nothing is written, the attempt
+/// is logged and `None` returned. A transaction in a final status that the
+/// update leaves unchanged is not written either and also returns `None`.
 pub async fn update_transaction_status(
     pool: &DbPool,
     tx_type: TxType,
     payment_hash: &str,
     update: UpdateTransaction,
-) -> Result<Transaction, DbError> {
+    source: TransitionSource,
+) -> Result<Option<Transaction>, DbError> {
     let mut conn = pool.get().await?;
 
-    let result = diesel::update(transactions::table)
-        .filter(transactions::payment_hash.eq(payment_hash))
-        .filter(transactions::tx_type.eq(tx_type.as_str()))
-        .set(&update)
-        .returning(Transaction::as_returning())
-        .get_result(&mut conn)
-        .await?;
+    conn.transaction::<_, DbError, _>(|conn| {
+        async move {
+            let current: Transaction = transactions::table
+                .filter(transactions::payment_hash.eq(payment_hash))
+                .filter(transactions::tx_type.eq(tx_type))
+                .select(Transaction::as_select())
+                .for_update()
+                .first(conn)
+                .await?;
 
-    Ok(result)
+            let next = update.status.filter(|next| *next != current.status);
+            match next {
+                Some(next) if !current.status.can_transition_to(next) => {
+                    log_rejected_transition(&current, next, source);
+                    return Ok(None);
+                }
+                // Replayed updates must not rewrite a finished transaction
+                None if current.status.is_final() => return Ok(None),
+                _ => {}
+            }
+
+            let updated: Transaction = diesel::update(transactions::table.find(current.id))
+                .set(&update)
+                .returning(Transaction::as_returning())
+                .get_result(conn)
+                .await?;
+
+            if let Some(next) = next {
+                record_status_changes(
+                    conn,
+                    &[NewStatusChange::new(
+                        updated.id,
+                        Some(current.status),
+                        next,
+                        source,
+                    )],
+                )
+                .await?;
+            }
+
+            Ok(Some(updated))
+        }
+        .scope_boxed()
+    })
+    .await
 }
 
 pub async fn get_transaction_by_hash(
@@ -82,7 +247,7 @@ pub async fn get_transaction_by_hash(
 
     let result = transactions::table
         .filter(transactions::payment_hash.eq(payment_hash))
-        .filter(transactions::tx_type.eq(tx_type.as_str()))
+        .filter(transactions::tx_type.eq(tx_type))
         .select(Transaction::as_select())
         .first(&mut conn)
         .await
@@ -91,17 +256,18 @@ pub async fn get_transaction_by_hash(
     Ok(result)
 }
 
-pub async fn list_transactions(
+/// Outgoing payments from `node_id` that have not reached a final state yet.
+pub async fn list_pending_payments(
     pool: &DbPool,
-    limit: i64,
-    offset: i64,
+    node_id: &str,
 ) -> Result<Vec<Transaction>, DbError> {
     let mut conn = pool.get().await?;
 
     let results = transactions::table
-        .order(transactions::created_at.desc())
-        .limit(limit)
-        .offset(offset)
+        .filter(transactions::node_id.eq(node_id))
+        .filter(transactions::tx_type.eq(TxType::Payment))
+        .filter(transactions::status.eq(TxStatus::Pending))
+        .order(transactions::created_at.asc())
         .select(Transaction::as_select())
         .load(&mut conn)
         .await?;
@@ -109,133 +275,917 @@ pub async fn list_transactions(
     Ok(results)
 }
 
-/// Upsert a transaction: insert if it doesn't exist, update status if it changed.
-/// Returns Some(transaction) if a change was made, None if already up-to-date.
+/// Transactions matching `filter`.
+fn filtered_transactions(filter: &TransactionFilter) -> transactions::BoxedQuery<'_, Pg> {
+    let mut query = transactions::table.into_boxed();
+
+    if let Some(tx_type) = filter.tx_type {
+        query = query.filter(transactions::tx_type.eq(tx_type));
+    }
+    if let Some(status) = filter.status {
+        query = query.filter(transactions::status.eq(status));
+    }
+    if let Some(node_id) = &filter.node_id {
+        query = query.filter(transactions::node_id.eq(node_id));
+    }
+    if let Some(origin) = filter.origin {
+        query = query.filter(transactions::origin.eq(origin));
+    }
+    if let Some(from) = filter.created_from {
+        query = query.filter(transactions::created_at.ge(from));
+    }
+    if let Some(to) = filter.created_to {
+        query = query.filter(transactions::created_at.lt(to));
+    }
+    if let Some(min) = filter.min_amount_msat {
+        query = query.filter(transactions::amount_msat.ge(min));
+    }
+    if let Some(max) = filter.max_amount_msat {
+        query = query.filter(transactions::amount_msat.le(max));
+    }
+    if let Some(search) = filter.search.as_deref().filter(|search| !search.is_empty()) {
+        // Match the text literally, wildcards included
+        let escaped = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        query = query.filter(transactions::description.ilike(format!("%{escaped}%")));
+    }
+
+    query
+}
+
+/// A page of transactions matching `filter`, newest first, starting after
+/// `after`. Keyset paging keeps pages stable while new rows arrive.
+pub async fn list_transactions(
+    pool: &DbPool,
+    filter: &TransactionFilter,
+    after: Option<TransactionCursor>,
+    limit: Option<i64>,
+) -> Result<TransactionListing, DbError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let mut conn = pool.get().await?;
+
+    let total_count = filtered_transactions(filter)
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let (transactions, next) = load_transaction_page(&mut conn, filter, after, limit).await?;
+
+    Ok(TransactionListing {
+        transactions,
+        next,
+        total_count,
+    })
+}
+
+/// Upsert a transaction: insert if it doesn't exist, update status (and the
+/// amount, which settling can change) if the status changed to one the
+/// current status may move to. Rejected changes are logged and skipped.
+/// Returns Some(transaction) if a change was made, None otherwise.
 pub async fn upsert_transaction(
     pool: &DbPool,
     new_tx: NewTransaction,
+    source: TransitionSource,
 ) -> Result<Option<Transaction>, DbError> {
     let mut conn = pool.get().await?;
 
-    let updated = diesel::update(transactions::table)
-        .filter(transactions::payment_hash.eq(&new_tx.payment_hash))
-        .filter(transactions::tx_type.eq(&new_tx.tx_type))
-        .filter(transactions::status.ne(&new_tx.status))
-        .set((
-            transactions::status.eq(&new_tx.status),
-            transactions::updated_at.eq(Utc::now()),
-        ))
-        .returning(Transaction::as_returning())
-        .get_result(&mut conn)
-        .await
-        .optional()?;
+    conn.transaction::<_, DbError, _>(|conn| {
+        async move {
+            let next = new_tx.status;
+
+            let existing: Option<Transaction> = transactions::table
+                .filter(transactions::payment_hash.eq(&new_tx.payment_hash))
+                .filter(transactions::tx_type.eq(new_tx.tx_type))
+                .select(Transaction::as_select())
+                .for_update()
+                .first(conn)
+                .await
+                .optional()?;
+
+            let change = match existing {
+                None => {
+                    let inserted: Option<Transaction> = diesel::insert_into(transactions::table)
+                        .values(&new_tx)
+                        .on_conflict_do_nothing()
+                        .returning(Transaction::as_returning())
+                        .get_result(conn)
+                        .await
+                        .optional()?;
+                    inserted.map(|tx| (None, tx))
+                }
+                Some(current) if current.status == next => None,
+                Some(current) if !current.status.can_transition_to(next) => {
+                    log_rejected_transition(&current, next, source);
+                    None
+                }
+                Some(current) => {
+                    let updated = diesel::update(transactions::table.find(current.id))
+                        .set((
+                            transactions::status.eq(&new_tx.status),
+                            transactions::amount_msat.eq(new_tx.amount_msat),
+                            transactions::settled_at.eq(new_tx.settled_at),
+                            transactions::updated_at.eq(Utc::now()),
+                        ))
+                        .returning(Transaction::as_returning())
+                        .get_result(conn)
+                        .await?;
+                    Some((Some(current.status), updated))
+                }
+            };
+
+            let Some((from, tx)) = change else {
+                return Ok(None);
+            };
+            record_status_changes(conn, &[NewStatusChange::new(tx.id, from, next, source)]).await?;
 
-    if updated.is_some() {
-        return Ok(updated);
+            Ok(Some(tx))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Upsert a batch of transactions with one insert and one update statement.
+///
+/// Status changes are held to `TxStatus::can_transition_to` like single
+/// upserts, so a stale page from a long startup sync cannot roll back a
+/// settlement the live subscription recorded in the meantime. Returns the
+/// rows that were inserted or changed.
+pub async fn upsert_transactions(
+    pool: &DbPool,
+    new_txs: &[NewTransaction],
+    source: TransitionSource,
+) -> Result<Vec<Transaction>, DbError> {
+    if new_txs.is_empty() {
+        return Ok(Vec::new());
     }
 
-    let existing: Option<Transaction> = transactions::table
-        .filter(transactions::payment_hash.eq(&new_tx.payment_hash))
-        .filter(transactions::tx_type.eq(&new_tx.tx_type))
+    let mut conn = pool.get().await?;
+
+    conn.transaction::<_, DbError, _>(|conn| {
+        async move {
+            let hashes: Vec<&str> = new_txs.iter().map(|tx| tx.payment_hash.as_str()).collect();
+            let existing: HashMap<(TxType, String), Transaction> = transactions::table
+                .filter(transactions::payment_hash.eq_any(&hashes))
+                .select(Transaction::as_select())
+                .for_update()
+                .load::<Transaction>(conn)
+                .await?
+                .into_iter()
+                .map(|tx| ((tx.tx_type, tx.payment_hash.clone()), tx))
+                .collect();
+
+            let mut to_insert = Vec::new();
+            let mut to_update = Vec::new();
+            for new_tx in new_txs {
+                let next = new_tx.status;
+                match existing.get(&(new_tx.tx_type, new_tx.payment_hash.clone())) {
+                    None => to_insert.push(new_tx),
+                    Some(current) if current.status == next => {}
+                    Some(current) if !current.status.can_transition_to(next) => {
+                        log_rejected_transition(current, next, source);
+                    }
+                    Some(_) => to_update.push(new_tx),
+                }
+            }
+
+            let mut changes = Vec::new();
+            let mut results = Vec::new();
+
+            // Rows the live subscription inserted meanwhile are left to it
+            if !to_insert.is_empty() {
+                let inserted: Vec<Transaction> = diesel::insert_into(transactions::table)
+                    .values(to_insert)
+                    .on_conflict_do_nothing()
+                    .returning(Transaction::as_returning())
+                    .get_results(conn)
+                    .await?;
+                for tx in inserted {
+                    changes.push(NewStatusChange::new(tx.id, None, tx.status, source));
+                    results.push(tx);
+                }
+            }
+
+            // Every row to update exists and is locked, so each one conflicts
+            if !to_update.is_empty() {
+                let updated: Vec<Transaction> = diesel::insert_into(transactions::table)
+                    .values(to_update)
+                    .on_conflict((transactions::tx_type, transactions::payment_hash))
+                    .do_update()
+                    .set((
+                        transactions::status.eq(excluded(transactions::status)),
+                        transactions::amount_msat.eq(excluded(transactions::amount_msat)),
+                        transactions::settled_at.eq(excluded(transactions::settled_at)),
+                        transactions::updated_at.eq(Utc::now()),
+                    ))
+                    .returning(Transaction::as_returning())
+                    .get_results(conn)
+                    .await?;
+                for tx in updated {
+                    let from = existing
+                        .get(&(tx.tx_type, tx.payment_hash.clone()))
+                        .map(|current| current.status);
+                    changes.push(NewStatusChange::new(tx.id, from, tx.status, source));
+                    results.push(tx);
+                }
+            }
+
+            record_status_changes(conn, &changes).await?;
+
+            Ok(results)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+async fn record_status_changes(
+    conn: &mut AsyncPgConnection,
+    changes: &[NewStatusChange],
+) -> Result<(), DbError> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    diesel::insert_into(transaction_status_history::table)
+        .values(changes)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+fn log_rejected_transition(current: &Transaction, next: TxStatus, source: TransitionSource) {
+    tracing::warn!(
+        "Rejected {} {} status change {} -> {} from {}",
+        current.tx_type.as_str(),
+        current.payment_hash,
+        current.status.as_str(),
+        next.as_str(),
+        source.as_str()
+    );
+}
+
+/// Pending invoices whose expiry passed before `cutoff`.
+pub async fn list_overdue_invoices(
+    pool: &DbPool,
+    cutoff: DateTime<Utc>,
+) -> Result<Vec<Transaction>, DbError> {
+    let mut conn = pool.get().await?;
+
+    let overdue = transactions::table
+        .filter(transactions::tx_type.eq(TxType::Invoice))
+        .filter(transactions::status.eq(TxStatus::Pending))
+        .filter(transactions::expires_at.lt(cutoff))
+        .order(transactions::id.asc())
         .select(Transaction::as_select())
+        .load(&mut conn)
+        .await?;
+
+    Ok(overdue)
+}
+
+/// Expire the invoices with the given ids that are still pending, in one
+/// statement so a settlement recorded meanwhile is never overwritten.
+/// Returns the expired invoices.
+pub async fn expire_invoices(pool: &DbPool, ids: &[i64]) -> Result<Vec<Transaction>, DbError> {
+    let mut conn = pool.get().await?;
+
+    conn.transaction::<_, DbError, _>(|conn| {
+        async move {
+            let expired: Vec<Transaction> = diesel::update(
+                transactions::table
+                    .filter(transactions::id.eq_any(ids))
+                    .filter(transactions::tx_type.eq(TxType::Invoice))
+                    .filter(transactions::status.eq(TxStatus::Pending)),
+            )
+            .set((
+                transactions::status.eq(TxStatus::Expired),
+                transactions::updated_at.eq(Utc::now()),
+            ))
+            .returning(Transaction::as_returning())
+            .get_results(conn)
+            .await?;
+
+            let changes: Vec<NewStatusChange> = expired
+                .iter()
+                .map(|tx| {
+                    NewStatusChange::new(
+                        tx.id,
+                        Some(TxStatus::Pending),
+                        tx.status,
+                        TransitionSource::ExpirySweep,
+                    )
+                })
+                .collect();
+            record_status_changes(conn, &changes).await?;
+
+            Ok(expired)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Insert transactions found on a node. Rows already recorded, e.g. payments
+/// THOrs made itself, are left as they are. Returns the rows inserted.
+pub async fn import_transactions(
+    pool: &DbPool,
+    new_txs: &[NewTransaction],
+    source: TransitionSource,
+) -> Result<Vec<Transaction>, DbError> {
+    if new_txs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut conn = pool.get().await?;
+
+    conn.transaction::<_, DbError, _>(|conn| {
+        async move {
+            let inserted: Vec<Transaction> = diesel::insert_into(transactions::table)
+                .values(new_txs)
+                .on_conflict_do_nothing()
+                .returning(Transaction::as_returning())
+                .get_results(conn)
+                .await?;
+
+            let changes: Vec<NewStatusChange> = inserted
+                .iter()
+                .map(|tx| NewStatusChange::new(tx.id, None, tx.status, source))
+                .collect();
+            record_status_changes(conn, &changes).await?;
+
+            Ok(inserted)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Status transitions of every transaction with `payment_hash`, oldest
+/// first. An invoice and the payment of it share a hash, so both may appear.
+pub async fn get_status_timeline(
+    pool: &DbPool,
+    payment_hash: &str,
+) -> Result<Vec<(TxType, StatusChange)>, DbError> {
+    let mut conn = pool.get().await?;
+
+    let results = transaction_status_history::table
+        .inner_join(transactions::table)
+        .filter(transactions::payment_hash.eq(payment_hash))
+        .order((
+            transaction_status_history::created_at.asc(),
+            transaction_status_history::id.asc(),
+        ))
+        .select((transactions::tx_type, StatusChange::as_select()))
+        .load(&mut conn)
+        .await?;
+
+    Ok(results)
+}
+
+/// Last add/settle indices recorded for `node_id`, or zeros if none yet.
+pub async fn get_invoice_indices(pool: &DbPool, node_id: &str) -> Result<InvoiceIndices, DbError> {
+    let mut conn = pool.get().await?;
+
+    let result = invoice_sync_state::table
+        .find(node_id)
+        .select(InvoiceIndices::as_select())
         .first(&mut conn)
         .await
         .optional()?;
 
-    if existing.is_some() {
-        return Ok(None);
-    }
+    Ok(result.unwrap_or_default())
+}
 
-    let insert_result = diesel::insert_into(transactions::table)
-        .values(&new_tx)
-        .returning(Transaction::as_returning())
-        .get_result(&mut conn)
-        .await;
+/// Move the stored indices of `node_id` forward. Each index only ever grows,
+/// so stale or zero values (e.g. an unsettled invoice) leave it unchanged.
+pub async fn advance_invoice_indices(
+    pool: &DbPool,
+    node_id: &str,
+    add_index: i64,
+    settle_index: i64,
+) -> Result<(), DbError> {
+    let mut conn = pool.get().await?;
 
-    match insert_result {
-        Ok(tx) => Ok(Some(tx)),
-        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(None),
-        Err(err) => Err(err.into()),
-    }
+    diesel::insert_into(invoice_sync_state::table)
+        .values((
+            invoice_sync_state::node_id.eq(node_id),
+            invoice_sync_state::add_index.eq(add_index),
+            invoice_sync_state::settle_index.eq(settle_index),
+        ))
+        .on_conflict(invoice_sync_state::node_id)
+        .do_update()
+        .set((
+            invoice_sync_state::add_index.eq(sql::<BigInt>(
+                "GREATEST(invoice_sync_state.add_index, excluded.add_index)",
+            )),
+            invoice_sync_state::settle_index.eq(sql::<BigInt>(
+                "GREATEST(invoice_sync_state.settle_index, excluded.settle_index)",
+            )),
+            invoice_sync_state::updated_at.eq(Utc::now()),
+        ))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(())
 }
 
-pub async fn get_balance(pool: &DbPool) -> Result<Balance, DbError> {
+/// Record that a full invoice load of `node_id` has listed every invoice up
+/// to `sync_add_index`. Like the other indices it only grows.
+pub async fn advance_invoice_sync(
+    pool: &DbPool,
+    node_id: &str,
+    sync_add_index: i64,
+) -> Result<(), DbError> {
     let mut conn = pool.get().await?;
 
-    let result = balance::table
-        .find(1)
-        .select(Balance::as_select())
+    diesel::insert_into(invoice_sync_state::table)
+        .values((
+            invoice_sync_state::node_id.eq(node_id),
+            invoice_sync_state::sync_add_index.eq(sync_add_index),
+        ))
+        .on_conflict(invoice_sync_state::node_id)
+        .do_update()
+        .set((
+            invoice_sync_state::sync_add_index.eq(sql::<BigInt>(
+                "GREATEST(invoice_sync_state.sync_add_index, excluded.sync_add_index)",
+            )),
+            invoice_sync_state::updated_at.eq(Utc::now()),
+        ))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Mark the full invoice load of `node_id` finished at `add_index`, and move
+/// the subscription's indices forward to what it saw.
+pub async fn complete_invoice_sync(
+    pool: &DbPool,
+    node_id: &str,
+    add_index: i64,
+    settle_index: i64,
+) -> Result<(), DbError> {
+    let mut conn = pool.get().await?;
+
+    diesel::insert_into(invoice_sync_state::table)
+        .values((
+            invoice_sync_state::node_id.eq(node_id),
+            invoice_sync_state::add_index.eq(add_index),
+            invoice_sync_state::settle_index.eq(settle_index),
+            invoice_sync_state::sync_add_index.eq(add_index),
+            invoice_sync_state::full_sync_complete.eq(true),
+        ))
+        .on_conflict(invoice_sync_state::node_id)
+        .do_update()
+        .set((
+            invoice_sync_state::add_index.eq(sql::<BigInt>(
+                "GREATEST(invoice_sync_state.add_index, excluded.add_index)",
+            )),
+            invoice_sync_state::settle_index.eq(sql::<BigInt>(
+                "GREATEST(invoice_sync_state.settle_index, excluded.settle_index)",
+            )),
+            invoice_sync_state::sync_add_index.eq(sql::<BigInt>(
+                "GREATEST(invoice_sync_state.sync_add_index, excluded.sync_add_index)",
+            )),
+            invoice_sync_state::full_sync_complete.eq(true),
+            invoice_sync_state::updated_at.eq(Utc::now()),
+        ))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Last payment index imported from `node_id`, or zero if none yet.
+pub async fn get_payment_index(pool: &DbPool, node_id: &str) -> Result<i64, DbError> {
+    let mut conn = pool.get().await?;
+
+    let result = payment_sync_state::table
+        .find(node_id)
+        .select(payment_sync_state::payment_index)
         .first(&mut conn)
+        .await
+        .optional()?;
+
+    Ok(result.unwrap_or_default())
+}
+
+/// Move the stored payment index of `node_id` forward; it never goes back.
+pub async fn advance_payment_index(
+    pool: &DbPool,
+    node_id: &str,
+    payment_index: i64,
+) -> Result<(), DbError> {
+    let mut conn = pool.get().await?;
+
+    diesel::insert_into(payment_sync_state::table)
+        .values((
+            payment_sync_state::node_id.eq(node_id),
+            payment_sync_state::payment_index.eq(payment_index),
+        ))
+        .on_conflict(payment_sync_state::node_id)
+        .do_update()
+        .set((
+            payment_sync_state::payment_index.eq(sql::<BigInt>(
+                "GREATEST(payment_sync_state.payment_index, excluded.payment_index)",
+            )),
+            payment_sync_state::updated_at.eq(Utc::now()),
+        ))
+        .execute(&mut conn)
         .await?;
 
-    Ok(result)
+    Ok(())
+}
+
+/// Record a configured node, updating its name and role if it is known.
+pub async fn register_node(pool: &DbPool, node: &NewNode) -> Result<(), DbError> {
+    let mut conn = pool.get().await?;
+
+    diesel::insert_into(nodes::table)
+        .values(node)
+        .on_conflict(nodes::node_id)
+        .do_update()
+        .set((
+            nodes::name.eq(&node.name),
+            nodes::role.eq(&node.role),
+            nodes::updated_at.eq(Utc::now()),
+        ))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(())
 }
 
+/// Transactions of `node_id`, or of every node.
+fn node_transactions(node_id: Option<&str>) -> transactions::BoxedQuery<'_, Pg> {
+    match node_id {
+        Some(node_id) => transactions::table
+            .filter(transactions::node_id.eq(node_id))
+            .into_boxed(),
+        None => transactions::table.into_boxed(),
+    }
+}
+
+async fn load_transaction_page(
+    conn: &mut AsyncPgConnection,
+    filter: &TransactionFilter,
+    after: Option<TransactionCursor>,
+    limit: i64,
+) -> Result<(Vec<Transaction>, Option<TransactionCursor>), DbError> {
+    let mut query = filtered_transactions(filter);
+    if let Some(after) = after {
+        query = query.filter(
+            transactions::created_at
+                .lt(after.created_at)
+                .or(transactions::created_at
+                    .eq(after.created_at)
+                    .and(transactions::id.lt(after.id))),
+        );
+    }
+
+    // One row more than asked tells whether another page follows
+    let mut transactions: Vec<Transaction> = query
+        .order((transactions::created_at.desc(), transactions::id.desc()))
+        .limit(limit + 1)
+        .select(Transaction::as_select())
+        .load(conn)
+        .await?;
+
+    let next = if transactions.len() as i64 > limit {
+        transactions.truncate(limit as usize);
+        transactions.last().map(TransactionCursor::after)
+    } else {
+        None
+    };
+
+    Ok((transactions, next))
+}
+
+/// Every transaction matching `filter`, newest first, loaded a page at a
+/// time so exports of any size stay out of memory.
+pub fn stream_transactions(
+    pool: DbPool,
+    filter: TransactionFilter,
+) -> impl Stream<Item = Result<Transaction, DbError>> + Send + 'static {
+    // `None` once the last page is out; `Some(None)` before the first one
+    let pages = stream::try_unfold(
+        Some(None),
+        move |after: Option<Option<TransactionCursor>>| {
+            let (pool, filter) = (pool.clone(), filter.clone());
+            async move {
+                let Some(after) = after else {
+                    return Ok::<_, DbError>(None);
+                };
+                let mut conn = pool.get().await?;
+                let (page, next) =
+                    load_transaction_page(&mut conn, &filter, after, MAX_PAGE_SIZE).await?;
+                let rows = stream::iter(page.into_iter().map(Ok::<_, DbError>));
+                Ok(Some((rows, next.map(Some))))
+            }
+        },
+    );
+
+    pages.try_flatten()
+}
+
+/// Balance of `node_id`, or summed over every node, from the ledger.
 pub async fn get_balance_summary(
     pool: &DbPool,
-    receive_node_id: &str,
-    send_node_id: &str,
+    node_id: Option<&str>,
 ) -> Result<BalanceSummary, DbError> {
     let mut conn = pool.get().await?;
 
-    let received: Option<i64> = transactions::table
-        .filter(transactions::node_id.eq(receive_node_id))
-        .filter(transactions::tx_type.eq(TxType::Invoice.as_str()))
-        .filter(
-            transactions::status.eq_any([TxStatus::Pending.as_str(), TxStatus::Succeeded.as_str()]),
-        )
-        .select(sql::<Nullable<BigInt>>("SUM(amount_sats)::BIGINT"))
-        .first(&mut conn)
+    // Few accounts exist, so sum them per node and keep the wanted ones
+    let debits: Vec<(String, String, Option<i64>)> = ledger_entries::table
+        .inner_join(accounts::table.on(accounts::id.eq(ledger_entries::debit_account_id)))
+        .group_by((accounts::node_id, accounts::kind))
+        .select((
+            accounts::node_id,
+            accounts::kind,
+            sql::<Nullable<BigInt>>("SUM(ledger_entries.amount_msat)::BIGINT"),
+        ))
+        .load(&mut conn)
         .await?;
 
-    let paid_amount: Option<i64> = transactions::table
-        .filter(transactions::node_id.eq(send_node_id))
-        .filter(transactions::tx_type.eq(TxType::Payment.as_str()))
-        .filter(transactions::status.eq(TxStatus::Succeeded.as_str()))
-        .select(sql::<Nullable<BigInt>>("SUM(amount_sats)::BIGINT"))
-        .first(&mut conn)
+    let credits: Vec<(String, String, Option<i64>)> = ledger_entries::table
+        .inner_join(accounts::table.on(accounts::id.eq(ledger_entries::credit_account_id)))
+        .group_by((accounts::node_id, accounts::kind))
+        .select((
+            accounts::node_id,
+            accounts::kind,
+            sql::<Nullable<BigInt>>("SUM(ledger_entries.amount_msat)::BIGINT"),
+        ))
+        .load(&mut conn)
         .await?;
 
-    let pending_received: Option<i64> = transactions::table
-        .filter(transactions::node_id.eq(receive_node_id))
-        .filter(transactions::tx_type.eq(TxType::Invoice.as_str()))
-        .filter(transactions::status.eq(TxStatus::Pending.as_str()))
-        .select(sql::<Nullable<BigInt>>("SUM(amount_sats)::BIGINT"))
+    let mut summary = BalanceSummary {
+        received_msat: 0,
+        paid_msat: 0,
+        fees_msat: 0,
+        balance_msat: 0,
+        last_updated: Utc::now(),
+    };
+
+    let postings = debits
+        .into_iter()
+        .map(|(node, kind, msat)| (node, kind, msat.unwrap_or(0)))
+        .chain(
+            credits
+                .into_iter()
+                .map(|(node, kind, msat)| (node, kind, -msat.unwrap_or(0))),
+        );
+    for (node, kind, debit_msat) in postings {
+        if node_id.is_some_and(|node_id| node_id != node) {
+            continue;
+        }
+        match AccountKind::parse(&kind) {
+            Some(AccountKind::Lightning) => summary.balance_msat += debit_msat,
+            // Income grows with credits
+            Some(AccountKind::Received) => summary.received_msat -= debit_msat,
+            Some(AccountKind::Sent) => summary.paid_msat += debit_msat,
+            Some(AccountKind::Fees) => summary.fees_msat += debit_msat,
+            None => tracing::warn!("Unknown ledger account kind {}", kind),
+        }
+    }
+
+    let last_updated: Option<DateTime<Utc>> = node_transactions(node_id)
+        .select(max(transactions::updated_at))
         .first(&mut conn)
         .await?;
+    if let Some(last_updated) = last_updated {
+        summary.last_updated = last_updated;
+    }
 
-    let pending_paid: Option<i64> = transactions::table
-        .filter(transactions::node_id.eq(send_node_id))
-        .filter(transactions::tx_type.eq(TxType::Payment.as_str()))
-        .filter(transactions::status.eq(TxStatus::Pending.as_str()))
-        .select(sql::<Nullable<BigInt>>("SUM(amount_sats)::BIGINT"))
-        .first(&mut conn)
+    Ok(summary)
+}
+
+/// Check the ledger against the transactions it was posted for.
+///
+/// Each succeeded invoice must have added its amount to its node's
+/// `lightning` account, each succeeded payment taken its amount and fee,
+/// and every other transaction must have left it unchanged.
+pub async fn check_ledger(pool: &DbPool) -> Result<LedgerCheck, DbError> {
+    let mut conn = pool.get().await?;
+
+    let mismatches = diesel::sql_query(
+        "SELECT payment_hash, tx_type, node_id, expected_msat, posted_msat FROM (
+            SELECT t.payment_hash, t.tx_type, t.node_id,
+                CASE
+                    WHEN t.status <> 'succeeded' THEN 0
+                    WHEN t.tx_type = 'invoice' THEN t.amount_msat
+                    ELSE -(t.amount_msat + COALESCE(t.fee_msat, 0))
+                END::BIGINT AS expected_msat,
+                COALESCE((
+                    SELECT SUM(
+                        CASE WHEN d.kind = 'lightning' THEN e.amount_msat ELSE 0 END
+                        - CASE WHEN c.kind = 'lightning' THEN e.amount_msat ELSE 0 END)
+                    FROM ledger_entries e
+                    JOIN accounts d ON d.id = e.debit_account_id
+                    JOIN accounts c ON c.id = e.credit_account_id
+                    WHERE e.transaction_id = t.id
+                ), 0)::BIGINT AS posted_msat
+            FROM transactions t
+        ) checked
+        WHERE expected_msat <> posted_msat
+        ORDER BY payment_hash",
+    )
+    .load::<LedgerMismatch>(&mut conn)
+    .await?;
+
+    #[derive(QueryableByName)]
+    struct Count {
+        #[diesel(sql_type = BigInt)]
+        count: i64,
+    }
+
+    let invalid_entries = diesel::sql_query(
+        "SELECT COUNT(*) AS count FROM ledger_entries e
+        JOIN transactions t ON t.id = e.transaction_id
+        JOIN accounts d ON d.id = e.debit_account_id
+        JOIN accounts c ON c.id = e.credit_account_id
+        LEFT JOIN ledger_entries o ON o.id = e.reverses_entry_id
+        WHERE d.node_id <> t.node_id
+            OR c.node_id <> t.node_id
+            OR (o.id IS NOT NULL AND (
+                o.transaction_id <> e.transaction_id
+                OR o.debit_account_id <> e.credit_account_id
+                OR o.credit_account_id <> e.debit_account_id
+                OR o.amount_msat <> e.amount_msat))",
+    )
+    .get_result::<Count>(&mut conn)
+    .await?
+    .count;
+
+    Ok(LedgerCheck {
+        mismatches,
+        invalid_entries,
+    })
+}
+
+/// Claim `key` for a request with body hash `request_hash`, or find out what
+/// the request that claimed it first did.
+pub async fn claim_idempotency_key(
+    pool: &DbPool,
+    endpoint: IdempotentEndpoint,
+    key: &str,
+    request_hash: &str,
+) -> Result<IdempotencyClaim, DbError> {
+    let mut conn = pool.get().await?;
+
+    let inserted = diesel::insert_into(idempotency_keys::table)
+        .values(NewIdempotencyKey::new(
+            endpoint,
+            key.to_string(),
+            request_hash.to_string(),
+        ))
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
         .await?;
+    if inserted > 0 {
+        return Ok(IdempotencyClaim::Acquired);
+    }
 
-    let last_updated_receive: Option<DateTime<Utc>> = transactions::table
-        .filter(transactions::node_id.eq(receive_node_id))
-        .select(max(transactions::updated_at))
+    let record = idempotency_keys::table
+        .find((endpoint.as_str(), key))
+        .select(IdempotencyRecord::as_select())
         .first(&mut conn)
+        .await
+        .optional()?;
+
+    Ok(match record {
+        Some(record) if record.request_hash != request_hash => IdempotencyClaim::Mismatch,
+        Some(IdempotencyRecord {
+            response_status: Some(status),
+            response_body: Some(body),
+            ..
+        }) => IdempotencyClaim::Completed { status, body },
+        Some(IdempotencyRecord {
+            payment_hash: Some(payment_hash),
+            ..
+        }) => IdempotencyClaim::Dispatched { payment_hash },
+        // Also a key released by a failed request since the insert; the
+        // caller retries either way
+        _ => IdempotencyClaim::InProgress,
+    })
+}
+
+/// Record the response of the request holding `key`.
+pub async fn complete_idempotency_key(
+    pool: &DbPool,
+    endpoint: IdempotentEndpoint,
+    key: &str,
+    status: i32,
+    body: &serde_json::Value,
+) -> Result<(), DbError> {
+    let mut conn = pool.get().await?;
+
+    diesel::update(idempotency_keys::table.find((endpoint.as_str(), key)))
+        .set((
+            idempotency_keys::response_status.eq(status),
+            idempotency_keys::response_body.eq(body),
+            idempotency_keys::completed_at.eq(Utc::now()),
+        ))
+        .execute(&mut conn)
         .await?;
 
-    let last_updated_send: Option<DateTime<Utc>> = transactions::table
-        .filter(transactions::node_id.eq(send_node_id))
-        .select(max(transactions::updated_at))
-        .first(&mut conn)
+    Ok(())
+}
+
+/// Record that the request holding `key` is about to send the payment with
+/// `payment_hash`, so the key outlives any failure to record a response.
+pub async fn bind_idempotency_key(
+    pool: &DbPool,
+    endpoint: IdempotentEndpoint,
+    key: &str,
+    payment_hash: &str,
+) -> Result<(), DbError> {
+    let mut conn = pool.get().await?;
+
+    diesel::update(idempotency_keys::table.find((endpoint.as_str(), key)))
+        .set(idempotency_keys::payment_hash.eq(payment_hash))
+        .execute(&mut conn)
         .await?;
 
-    let last_updated = match (last_updated_receive, last_updated_send) {
-        (Some(a), Some(b)) => Some(a.max(b)),
-        (Some(a), None) => Some(a),
-        (None, Some(b)) => Some(b),
-        (None, None) => None,
-    };
+    Ok(())
+}
 
-    Ok(BalanceSummary {
-        received_sats: received.unwrap_or(0),
-        paid_sats: paid_amount.unwrap_or(0),
-        pending_received_sats: pending_received.unwrap_or(0),
-        pending_paid_sats: pending_paid.unwrap_or(0),
-        last_updated: last_updated.unwrap_or_else(Utc::now),
-    })
+/// Give up `key` without a response, so a retry runs the request again.
+/// Keys bound to a payment are kept, as running the request again could send
+/// a second one.
+pub async fn release_idempotency_key(
+    pool: &DbPool,
+    endpoint: IdempotentEndpoint,
+    key: &str,
+) -> Result<(), DbError> {
+    let mut conn = pool.get().await?;
+
+    diesel::delete(
+        idempotency_keys::table
+            .find((endpoint.as_str(), key))
+            .filter(idempotency_keys::response_status.is_null())
+            .filter(idempotency_keys::payment_hash.is_null()),
+    )
+    .execute(&mut conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Release the keys of requests a previous run never finished, except those
+/// bound to a payment. Returns how many there were.
+pub async fn release_unfinished_idempotency_keys(pool: &DbPool) -> Result<usize, DbError> {
+    let mut conn = pool.get().await?;
+
+    let released = diesel::delete(
+        idempotency_keys::table
+            .filter(idempotency_keys::response_status.is_null())
+            .filter(idempotency_keys::payment_hash.is_null()),
+    )
+    .execute(&mut conn)
+    .await?;
+
+    Ok(released)
+}
+
+/// Delete every key claimed before `cutoff`. Returns how many there were.
+pub async fn prune_idempotency_keys(
+    pool: &DbPool,
+    cutoff: DateTime<Utc>,
+) -> Result<usize, DbError> {
+    let mut conn = pool.get().await?;
+
+    let pruned =
+        diesel::delete(idempotency_keys::table.filter(idempotency_keys::created_at.lt(cutoff)))
+            .execute(&mut conn)
+            .await?;
+
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_cursor_round_trips() {
+        let cursor = TransactionCursor {
+            created_at: DateTime::from_timestamp_micros(1_767_225_600_123_456).unwrap(),
+            id: 42,
+        };
+        assert_eq!(TransactionCursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn transaction_cursor_rejects_garbage() {
+        assert_eq!(TransactionCursor::decode(""), None);
+        assert_eq!(TransactionCursor::decode("zz"), None);
+        // Valid hex, but not "<micros>:<id>"
+        assert_eq!(TransactionCursor::decode(&hex::encode("42")), None);
+        assert_eq!(TransactionCursor::decode(&hex::encode("x:42")), None);
+        assert_eq!(TransactionCursor::decode(&hex::encode("1:x")), None);
+        assert_eq!(TransactionCursor::decode("ff"), None);
+    }
 }
@@ -0,0 +1,287 @@
+//! Transaction history exports for bookkeeping.
+//!
+//! Each transaction becomes one self-contained chunk of output, so exports
+//! stream row by row however long the history is.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::models::{Transaction, TxStatus, TxType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Beancount,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Beancount => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "transactions.csv",
+            ExportFormat::Jsonl => "transactions.jsonl",
+            ExportFormat::Beancount => "transactions.beancount",
+        }
+    }
+
+    /// Output ahead of the first transaction.
+    pub fn header(&self, accounts: &BeancountAccounts) -> String {
+        match self {
+            ExportFormat::Csv => format!("{}\n", CSV_COLUMNS.join(",")),
+            ExportFormat::Jsonl => String::new(),
+            ExportFormat::Beancount => beancount_open_directives(accounts),
+        }
+    }
+
+    /// One transaction in this format, line break included.
+    pub fn row(&self, tx: &Transaction, accounts: &BeancountAccounts) -> String {
+        match self {
+            ExportFormat::Csv => csv_row(tx),
+            ExportFormat::Jsonl => {
+                let mut line = serde_json::to_string(tx).unwrap_or_default();
+                line.push('\n');
+                line
+            }
+            ExportFormat::Beancount => beancount_entry(tx, accounts),
+        }
+    }
+}
+
+/// Accounts a Beancount export posts to. The node's own funds sit on
+/// `lightning`; received invoices are income, sent payments and their
+/// routing fees are expenses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BeancountAccounts {
+    pub lightning: String,
+    pub income: String,
+    pub payments: String,
+    pub fees: String,
+}
+
+impl Default for BeancountAccounts {
+    fn default() -> Self {
+        Self {
+            lightning: "Assets:Lightning".to_string(),
+            income: "Income:Lightning".to_string(),
+            payments: "Expenses:Lightning:Payments".to_string(),
+            fees: "Expenses:Lightning:Fees".to_string(),
+        }
+    }
+}
+
+/// Whether Beancount accepts `name` as an account: a root type followed by
+/// colon-separated components that start with a capital letter or digit.
+pub fn is_beancount_account(name: &str) -> bool {
+    let mut components = name.split(':');
+    let root = components.next().unwrap_or_default();
+    let mut rest = components.peekable();
+
+    ["Assets", "Liabilities", "Equity", "Income", "Expenses"].contains(&root)
+        && rest.peek().is_some()
+        && rest.all(|component| {
+            let mut chars = component.chars();
+            chars
+                .next()
+                .is_some_and(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+const CSV_COLUMNS: [&str; 16] = [
+    "id",
+    "tx_type",
+    "status",
+    "origin",
+    "payment_hash",
+    "node_id",
+    "destination",
+    "amount_msat",
+    "fee_msat",
+    "description",
+    "preimage",
+    "failure_reason",
+    "created_at",
+    "updated_at",
+    "settled_at",
+    "expires_at",
+];
+
+fn csv_row(tx: &Transaction) -> String {
+    let optional = |value: &Option<String>| value.as_deref().map(csv_field).unwrap_or_default();
+    let timestamp = |time: DateTime<Utc>| time.to_rfc3339();
+
+    let fields = [
+        tx.id.to_string(),
+        tx.tx_type.as_str().to_string(),
+        tx.status.as_str().to_string(),
+        tx.origin.as_str().to_string(),
+        tx.payment_hash.clone(),
+        tx.node_id.clone(),
+        optional(&tx.destination),
+        tx.amount_msat.to_string(),
+        tx.fee_msat.map(|fee| fee.to_string()).unwrap_or_default(),
+        optional(&tx.description),
+        optional(&tx.preimage),
+        optional(&tx.failure_reason),
+        timestamp(tx.created_at),
+        timestamp(tx.updated_at),
+        tx.settled_at.map(timestamp).unwrap_or_default(),
+        tx.expires_at.map(timestamp).unwrap_or_default(),
+    ];
+
+    format!("{}\n", fields.join(","))
+}
+
+/// Quote a free-text field when it needs it. Text a spreadsheet would read
+/// as a formula gets a leading apostrophe.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Date the accounts are opened on, before any entry an export can hold.
+const BEANCOUNT_OPEN_DATE: &str = "1970-01-01";
+
+/// `open` directives for every account the entries post to, so an export
+/// passes `bean-check` on its own. Accounts configured twice open once.
+fn beancount_open_directives(accounts: &BeancountAccounts) -> String {
+    let mut opened: Vec<&str> = Vec::new();
+    for account in [
+        &accounts.lightning,
+        &accounts.income,
+        &accounts.payments,
+        &accounts.fees,
+    ] {
+        if !opened.contains(&account.as_str()) {
+            opened.push(account);
+        }
+    }
+
+    let mut header: String = opened
+        .iter()
+        .map(|account| format!("{BEANCOUNT_OPEN_DATE} open {account} BTC\n"))
+        .collect();
+    header.push('\n');
+    header
+}
+
+/// A balanced entry for a settled transaction. Transactions that never
+/// settled moved no funds and are left out.
+fn beancount_entry(tx: &Transaction, accounts: &BeancountAccounts) -> String {
+    if tx.status != TxStatus::Succeeded {
+        return String::new();
+    }
+
+    // Rows settle with `settled_at` set, which later updates leave alone
+    let date = tx.settled_at.unwrap_or(tx.updated_at).format("%Y-%m-%d");
+    let narration = beancount_string(tx.description.as_deref().unwrap_or_default());
+    let fee_msat = tx.fee_msat.unwrap_or_default();
+
+    let (payee, postings) = match tx.tx_type {
+        TxType::Invoice => (
+            "Invoice settled",
+            vec![
+                (&accounts.lightning, tx.amount_msat),
+                (&accounts.income, -tx.amount_msat),
+            ],
+        ),
+        TxType::Payment => {
+            let mut postings = vec![(&accounts.payments, tx.amount_msat)];
+            if fee_msat > 0 {
+                postings.push((&accounts.fees, fee_msat));
+            }
+            postings.push((&accounts.lightning, -(tx.amount_msat + fee_msat)));
+            ("Payment sent", postings)
+        }
+    };
+
+    let mut entry = format!("{date} * \"{payee}\" \"{narration}\"\n");
+    entry.push_str(&format!("  payment_hash: \"{}\"\n", tx.payment_hash));
+    entry.push_str(&format!("  node_id: \"{}\"\n", tx.node_id));
+    for (account, msat) in postings {
+        entry.push_str(&format!("  {account}  {} BTC\n", format_btc(msat)));
+    }
+    entry.push('\n');
+
+    entry
+}
+
+fn beancount_string(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(['\n', '\r'], " ")
+}
+
+/// Millisatoshis as BTC, exact to the millisatoshi and with at least the
+/// eight decimals of a satoshi.
+fn format_btc(msat: i64) -> String {
+    const MSAT_PER_BTC: u64 = 100_000_000_000;
+
+    let sign = if msat < 0 { "-" } else { "" };
+    let msat = msat.unsigned_abs();
+    let fraction = format!("{:011}", msat % MSAT_PER_BTC);
+    let fraction = fraction.trim_end_matches('0');
+    let fraction = format!("{fraction:0<8}");
+
+    format!("{sign}{}.{fraction}", msat / MSAT_PER_BTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_field_quotes_and_defuses() {
+        assert_eq!(csv_field("coffee"), "coffee");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("=SUM(A1:A2)"), "'=SUM(A1:A2)");
+        assert_eq!(csv_field("-1"), "'-1");
+        assert_eq!(csv_field("@x,y"), "\"'@x,y\"");
+    }
+
+    #[test]
+    fn beancount_header_opens_each_account_once() {
+        let accounts = BeancountAccounts {
+            fees: "Expenses:Lightning:Payments".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            ExportFormat::Beancount.header(&accounts),
+            "1970-01-01 open Assets:Lightning BTC\n\
+             1970-01-01 open Income:Lightning BTC\n\
+             1970-01-01 open Expenses:Lightning:Payments BTC\n\n"
+        );
+    }
+
+    #[test]
+    fn format_btc_is_exact() {
+        assert_eq!(format_btc(0), "0.00000000");
+        assert_eq!(format_btc(1_000), "0.00000001");
+        assert_eq!(format_btc(1), "0.00000000001");
+        assert_eq!(format_btc(2_500), "0.000000025");
+        assert_eq!(format_btc(100_000_000_000), "1.00000000");
+        assert_eq!(format_btc(-1_502_500), "-0.000015025");
+        assert_eq!(format_btc(i64::MIN), "-92233720.36854775808");
+    }
+}
@@ -1,11 +1,31 @@
 use leptos::prelude::*;
 
 #[cfg(feature = "ssr")]
-use crate::models::{NewTransaction, TxStatus, TxType};
+use crate::models::{IdempotentEndpoint, NodeRole, TxStatus, TxType};
 #[cfg(feature = "ssr")]
-use crate::server::db::{create_transaction, get_balance_summary, list_transactions, DbPool};
+use crate::server::api::ApiError;
 #[cfg(feature = "ssr")]
-use crate::server::lnd::LightningClients;
+use crate::server::db::{
+    get_balance_summary, get_transaction_by_hash, list_transactions, DbPool, TransactionCursor,
+};
+#[cfg(feature = "ssr")]
+use crate::server::export::BeancountAccounts;
+#[cfg(feature = "ssr")]
+use crate::server::idempotency::{self, Execution, Outcome};
+#[cfg(feature = "ssr")]
+use crate::server::lnd::{random_preimage, InvoiceParams};
+#[cfg(feature = "ssr")]
+use crate::server::nodes::NodeRegistry;
+#[cfg(feature = "ssr")]
+use crate::server::payments;
+#[cfg(feature = "ssr")]
+use futures::TryFutureExt;
+#[cfg(feature = "ssr")]
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "ssr")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "ssr")]
+use std::future::Future;
 #[cfg(feature = "ssr")]
 use tokio::sync::broadcast;
 
@@ -23,6 +43,13 @@ impl std::fmt::Display for AppError {
 
 impl std::error::Error for AppError {}
 
+#[cfg(feature = "ssr")]
+impl From<ApiError> for AppError {
+    fn from(err: ApiError) -> Self {
+        AppError(err.to_string())
+    }
+}
+
 impl std::str::FromStr for AppError {
     type Err = std::convert::Infallible;
 
@@ -36,139 +63,294 @@ impl std::str::FromStr for AppError {
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: DbPool,
-    pub lnd_receive: LightningClients,
-    pub lnd_send: LightningClients,
+    pub nodes: NodeRegistry,
     pub broadcast_tx: broadcast::Sender<InvoiceEvent>,
-    pub receive_node_id: String,
-    pub send_node_id: String,
+    /// Fee cap for payments that do not specify their own.
+    pub fee_limit: FeeLimit,
+    pub invoice_defaults: InvoiceDefaults,
+    /// Accounts Beancount exports post to.
+    pub beancount_accounts: BeancountAccounts,
+}
+
+/// Refuse a server function when no configured node has `role`.
+#[cfg(feature = "ssr")]
+fn require_role(app_state: &AppState, role: NodeRole) -> Result<(), AppError> {
+    if app_state.nodes.supports(role) {
+        Ok(())
+    } else {
+        Err(AppError(ApiError::RoleDisabled(role).to_string()))
+    }
+}
+
+/// Run a server function under `idempotency_key`, if the caller gave one.
+/// Keys are shared with the matching REST endpoint. Only successes are kept:
+/// errors reach here as plain messages, so a retry runs a failed call again.
+#[cfg(feature = "ssr")]
+async fn idempotent<R, Fut>(
+    app_state: &AppState,
+    idempotency_key: Option<String>,
+    endpoint: IdempotentEndpoint,
+    request_hash: String,
+    call: Fut,
+) -> Result<R, ServerFnError>
+where
+    R: Serialize + DeserializeOwned + Send + 'static,
+    Fut: Future<Output = Result<R, ServerFnError>> + Send + 'static,
+{
+    let Some(key) = idempotency_key else {
+        return call.await;
+    };
+
+    // Record the status the REST endpoint answers with
+    let status = match endpoint {
+        IdempotentEndpoint::Invoice => axum::http::StatusCode::CREATED,
+        IdempotentEndpoint::Payment | IdempotentEndpoint::Keysend => axum::http::StatusCode::OK,
+    };
+    let keep = move |result: &Result<R, ServerFnError>| {
+        let body = serde_json::to_value(result.as_ref().ok()?).ok()?;
+        Some(Outcome { status, body })
+    };
+
+    let execution =
+        idempotency::execute(&app_state.db_pool, endpoint, key, request_hash, call, keep)
+            .await
+            .map_err(|e| AppError(e.to_string()))?;
+
+    match execution {
+        Execution::Ran(result) => result,
+        Execution::Replayed(outcome) if outcome.status.is_success() => {
+            Ok(serde_json::from_value(outcome.body).map_err(|e| AppError(e.to_string()))?)
+        }
+        Execution::Replayed(outcome) => {
+            let error = outcome.body["error"].as_str().unwrap_or_default();
+            Err(AppError(error.to_string()).into())
+        }
+        Execution::Dispatched(payment_hash) => {
+            let payment = payments::replay(&app_state.db_pool, &payment_hash)
+                .await
+                .map_err(AppError::from)?;
+            let payment = serde_json::to_value(payment).and_then(serde_json::from_value);
+            Ok(payment.map_err(|e| AppError(e.to_string()))?)
+        }
+    }
 }
 
 #[server]
 pub async fn create_invoice_fn(
-    amount_sats: i64,
-    description: Option<String>,
+    request: CreateInvoiceRequest,
+    idempotency_key: Option<String>,
 ) -> Result<InvoiceResponse, ServerFnError> {
     let app_state = expect_context::<AppState>();
-    let lnd = app_state.lnd_receive.clone();
+    require_role(&app_state, NodeRole::Receive)?;
 
-    // Validate amount
-    if amount_sats <= 0 {
-        return Err(AppError("Invalid amount".to_string()).into());
-    }
+    let request_hash = idempotency::fingerprint(&request);
+    let call = create_invoice(app_state.clone(), request);
+    idempotent(
+        &app_state,
+        idempotency_key,
+        IdempotentEndpoint::Invoice,
+        request_hash,
+        call,
+    )
+    .await
+}
 
-    // Create invoice in LND
-    let lnd_invoice = lnd
-        .create_invoice(amount_sats, description.clone())
-        .await
-        .map_err(|e| AppError(e.to_string()))?;
+#[cfg(feature = "ssr")]
+async fn create_invoice(
+    app_state: AppState,
+    request: CreateInvoiceRequest,
+) -> Result<InvoiceResponse, ServerFnError> {
+    let node = app_state
+        .nodes
+        .receiver(request.node_id.as_deref())
+        .map_err(AppError)?;
+    let lnd = node.lnd.clone();
+
+    // Validate and apply server defaults
+    let hold = request.hold;
+    let params = InvoiceParams::resolve(request, &app_state.invoice_defaults).map_err(AppError)?;
+    let (amount_msat, expiry_secs) = (params.amount_msat, params.expiry_secs);
+
+    // Create invoice in LND; hold invoices use a preimage only the caller sees
+    let (lnd_invoice, preimage) = if hold {
+        let preimage = random_preimage();
+        let payment_hash = Sha256::digest(&preimage).to_vec();
+        let invoice = lnd
+            .create_hold_invoice(params, payment_hash)
+            .await
+            .map_err(|e| AppError(e.to_string()))?;
+        (invoice, Some(hex::encode(preimage)))
+    } else {
+        let invoice = lnd
+            .create_invoice(params)
+            .await
+            .map_err(|e| AppError(e.to_string()))?;
+        (invoice, None)
+    };
 
     // No DB insert here; background invoice subscription handles persistence.
 
     Ok(InvoiceResponse {
         payment_request: lnd_invoice.payment_request,
         payment_hash: hex::encode(&lnd_invoice.r_hash),
-        amount_sats,
+        amount_sats: amount_msat / 1000,
+        amount_msat,
+        expiry_secs,
+        preimage,
     })
 }
 
+/// Withdraw an invoice that has not been settled. The invoice subscription
+/// marks it expired and broadcasts `InvoiceExpired`.
 #[server]
-pub async fn pay_invoice_fn(payment_request: String) -> Result<PaymentResponse, ServerFnError> {
+pub async fn cancel_invoice_fn(payment_hash: String) -> Result<(), ServerFnError> {
     let app_state = expect_context::<AppState>();
-    let lnd = app_state.lnd_send.clone();
+    require_role(&app_state, NodeRole::Receive)?;
 
-    // Decode invoice
-    let decoded = lnd
-        .decode_payment_request(payment_request.clone())
+    let tx = get_transaction_by_hash(&app_state.db_pool, TxType::Invoice, &payment_hash)
         .await
-        .map_err(|e| AppError(e.to_string()))?;
+        .map_err(|e| AppError(e.to_string()))?
+        .ok_or_else(|| AppError("Invoice not found".to_string()))?;
+    if tx.status == TxStatus::Succeeded {
+        return Err(
+            AppError("Invoice is already settled and cannot be cancelled".to_string()).into(),
+        );
+    }
 
-    // Save as pending
-    let new_tx = NewTransaction::new(
-        TxType::Payment,
-        decoded.payment_hash.clone(),
-        payment_request.clone(),
-        decoded.num_satoshis,
-        Some(decoded.description.clone()),
-        TxStatus::Pending,
-        None,
-        app_state.send_node_id.clone(),
-    );
-
-    create_transaction(&app_state.db_pool, new_tx)
-        .await
-        .map_err(|e| AppError(e.to_string()))?;
+    let node = app_state
+        .nodes
+        .get(&tx.node_id)
+        .ok_or_else(|| AppError("Invoice belongs to a node that is not configured".to_string()))?;
 
-    // Send payment
-    let payment = lnd
-        .send_payment(payment_request)
+    let hash_bytes =
+        hex::decode(&payment_hash).map_err(|_| AppError("Invalid payment hash".to_string()))?;
+    node.lnd
+        .cancel_invoice(hash_bytes)
         .await
         .map_err(|e| AppError(e.to_string()))?;
 
-    if !payment.payment_error.is_empty() {
-        return Err(AppError(payment.payment_error).into());
-    }
+    Ok(())
+}
 
-    // Update status to succeeded (trigger will update balance)
-    let update = crate::models::UpdateTransaction::new(
-        Some(TxStatus::Succeeded),
-        Some(hex::encode(&payment.payment_preimage)),
-        payment
-            .payment_route
-            .as_ref()
-            .map(|r| r.total_fees_msat / 1000),
-        None,
-    );
-
-    let tx = crate::server::db::update_transaction_status(
-        &app_state.db_pool,
-        TxType::Payment,
-        &decoded.payment_hash,
-        update,
+/// The server's defaults for the optional invoice settings.
+#[server]
+pub async fn get_invoice_defaults_fn() -> Result<InvoiceDefaults, ServerFnError> {
+    let app_state = expect_context::<AppState>();
+    Ok(app_state.invoice_defaults.clone())
+}
+
+#[server]
+pub async fn pay_invoice_fn(
+    payment_request: String,
+    amount_sats: Option<i64>,
+    amount_msat: Option<i64>,
+    max_fee_sats: Option<i64>,
+    max_fee_percent: Option<f64>,
+    node_id: Option<String>,
+    idempotency_key: Option<String>,
+) -> Result<PaymentResponse, ServerFnError> {
+    let app_state = expect_context::<AppState>();
+    require_role(&app_state, NodeRole::Send)?;
+
+    // Fingerprinted as the REST body, so both share keys
+    let request = PayInvoiceRequest {
+        payment_request,
+        amount_sats,
+        amount_msat,
+        max_fee_sats,
+        max_fee_percent,
+        node_id,
+    };
+    let request_hash = idempotency::fingerprint(&request);
+    let call =
+        payments::pay_invoice(app_state.clone(), request).map_err(|e| AppError::from(e).into());
+    idempotent(
+        &app_state,
+        idempotency_key,
+        IdempotentEndpoint::Payment,
+        request_hash,
+        call,
     )
     .await
-    .map_err(|e| AppError(e.to_string()))?;
+}
 
-    let _ = app_state
-        .broadcast_tx
-        .send(InvoiceEvent::PaymentSucceeded { tx });
+#[server]
+pub async fn keysend_fn(
+    request: KeysendRequest,
+    idempotency_key: Option<String>,
+) -> Result<PaymentResponse, ServerFnError> {
+    let app_state = expect_context::<AppState>();
+    require_role(&app_state, NodeRole::Send)?;
 
-    Ok(PaymentResponse {
-        payment_hash: decoded.payment_hash,
-        preimage: hex::encode(&payment.payment_preimage),
-        amount_sats: decoded.num_satoshis,
-    })
+    let request_hash = idempotency::fingerprint(&request);
+    let call = payments::keysend(app_state.clone(), request).map_err(|e| AppError::from(e).into());
+    idempotent(
+        &app_state,
+        idempotency_key,
+        IdempotentEndpoint::Keysend,
+        request_hash,
+        call,
+    )
+    .await
+}
+
+/// The server's default routing fee cap, shown in the send form.
+#[server]
+pub async fn get_fee_limit_fn() -> Result<FeeLimit, ServerFnError> {
+    let app_state = expect_context::<AppState>();
+    Ok(app_state.fee_limit)
 }
 
+/// A page of transactions matching `filter`, starting at `cursor` (the
+/// `next_cursor` of the previous page) or at the newest one.
 #[server]
 pub async fn get_transactions_fn(
+    filter: TransactionFilter,
+    cursor: Option<String>,
     limit: Option<i64>,
-    offset: Option<i64>,
-) -> Result<Vec<crate::models::Transaction>, ServerFnError> {
+) -> Result<TransactionPage, ServerFnError> {
     let app_state = expect_context::<AppState>();
 
-    let txs = list_transactions(&app_state.db_pool, limit.unwrap_or(50), offset.unwrap_or(0))
+    let after = match cursor {
+        Some(cursor) => Some(
+            TransactionCursor::decode(&cursor)
+                .ok_or_else(|| AppError("Invalid cursor".to_string()))?,
+        ),
+        None => None,
+    };
+    let listing = list_transactions(&app_state.db_pool, &filter, after, limit)
         .await
         .map_err(|e| AppError(e.to_string()))?;
 
-    Ok(txs)
+    Ok(listing.into_page())
 }
 
+/// Balance of one node, or of all nodes together when `node_id` is unset.
 #[server]
-pub async fn get_balance_fn() -> Result<crate::dto::BalanceDto, ServerFnError> {
+pub async fn get_balance_fn(
+    node_id: Option<String>,
+) -> Result<crate::dto::BalanceDto, ServerFnError> {
     let app_state = expect_context::<AppState>();
 
-    let balance = get_balance_summary(
-        &app_state.db_pool,
-        &app_state.receive_node_id,
-        &app_state.send_node_id,
-    )
-    .await
-    .map_err(|e| AppError(e.to_string()))?;
+    let balance = get_balance_summary(&app_state.db_pool, node_id.as_deref())
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
 
     Ok(BalanceDto {
-        received_sats: balance.received_sats,
-        paid_sats: balance.paid_sats,
-        total_balance: balance.pending_received_sats - balance.pending_paid_sats,
+        received_sats: balance.received_msat / 1000,
+        received_msat: balance.received_msat,
+        paid_sats: balance.paid_msat / 1000,
+        paid_msat: balance.paid_msat,
+        fee_sats: balance.fees_msat / 1000,
+        fee_msat: balance.fees_msat,
+        total_balance: balance.balance_msat / 1000,
+        total_balance_msat: balance.balance_msat,
         last_updated: balance.last_updated.to_rfc3339(),
     })
 }
+
+#[server]
+pub async fn get_node_status_fn() -> Result<Vec<NodeStatus>, ServerFnError> {
+    let app_state = expect_context::<AppState>();
+    Ok(app_state.nodes.statuses())
+}
@@ -0,0 +1,194 @@
+//! Idempotency keys for the endpoints that create invoices and send payments.
+//!
+//! The first request with a key runs on its own task, so it still finishes and
+//! records its response when the client stops waiting. Retries with the same
+//! key and body get that response back; a different body is refused.
+//!
+//! A payment request binds its key to the payment hash before sending. From
+//! then on the key is never released, even when no response gets recorded, and
+//! retries answer from the payment's current state.
+
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+
+use futures::FutureExt;
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::models::IdempotentEndpoint;
+use crate::server::db::{self, DbError, DbPool, IdempotencyClaim};
+
+/// Header carrying the key on REST requests.
+pub const HEADER: &str = "idempotency-key";
+
+/// Header set on a response replayed from an earlier request.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Longest key accepted, as stored in `idempotency_keys`.
+const MAX_KEY_LEN: usize = 255;
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdempotencyError {
+    #[error("Idempotency-Key must be 1 to {MAX_KEY_LEN} printable ASCII characters")]
+    InvalidKey,
+
+    #[error("Idempotency-Key was already used for a different request")]
+    Mismatch,
+
+    #[error("A request with this Idempotency-Key is still in progress")]
+    InProgress,
+
+    #[error(transparent)]
+    Database(#[from] DbError),
+}
+
+/// A response kept for retries.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub status: StatusCode,
+    pub body: serde_json::Value,
+}
+
+/// What answered a request made under a key.
+pub enum Execution<T> {
+    /// The request ran now.
+    Ran(T),
+    /// An earlier request with the key already answered this.
+    Replayed(Outcome),
+    /// An earlier request with the key sent the payment with this hash, but
+    /// recorded no response; the payment's current state answers instead.
+    Dispatched(String),
+}
+
+tokio::task_local! {
+    /// The key the request running on this task holds.
+    static CLAIMED: (IdempotentEndpoint, String);
+}
+
+/// Hash of a request body, to notice a key reused for another request.
+pub fn fingerprint(request: &impl Serialize) -> String {
+    let json = serde_json::to_vec(request).unwrap_or_default();
+    hex::encode(Sha256::digest(json))
+}
+
+fn validate_key(key: &str) -> Result<(), IdempotencyError> {
+    let printable = key.bytes().all(|b| b.is_ascii_graphic() || b == b' ');
+    if key.is_empty() || key.len() > MAX_KEY_LEN || !printable {
+        return Err(IdempotencyError::InvalidKey);
+    }
+
+    Ok(())
+}
+
+/// Run `request` under `key`, or answer with what it gave the first time.
+/// `keep` picks the outcome to record for retries; a result it returns None
+/// for releases the key instead, so a retry runs the request again, unless
+/// the request already bound the key to a payment.
+pub async fn execute<T, Fut, Keep>(
+    pool: &DbPool,
+    endpoint: IdempotentEndpoint,
+    key: String,
+    request_hash: String,
+    request: Fut,
+    keep: Keep,
+) -> Result<Execution<T>, IdempotencyError>
+where
+    T: Send + 'static,
+    Fut: Future<Output = T> + Send + 'static,
+    Keep: FnOnce(&T) -> Option<Outcome> + Send + 'static,
+{
+    validate_key(&key)?;
+
+    match db::claim_idempotency_key(pool, endpoint, &key, &request_hash).await? {
+        IdempotencyClaim::Acquired => {}
+        IdempotencyClaim::Completed { status, body } => {
+            let status = u16::try_from(status)
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .unwrap_or(StatusCode::OK);
+            return Ok(Execution::Replayed(Outcome { status, body }));
+        }
+        IdempotencyClaim::Dispatched { payment_hash } => {
+            return Ok(Execution::Dispatched(payment_hash))
+        }
+        IdempotencyClaim::InProgress => return Err(IdempotencyError::InProgress),
+        IdempotencyClaim::Mismatch => return Err(IdempotencyError::Mismatch),
+    }
+
+    let pool = pool.clone();
+    let task = tokio::spawn(async move {
+        let claimed = (endpoint, key.clone());
+        let result = CLAIMED
+            .scope(claimed, AssertUnwindSafe(request).catch_unwind())
+            .await;
+
+        // A panicking request records nothing, so it must not hold the key
+        let result = match result {
+            Ok(result) => result,
+            Err(panic) => {
+                if let Err(e) = db::release_idempotency_key(&pool, endpoint, &key).await {
+                    tracing::error!("Failed to release idempotency key {key} after a panic: {e}");
+                }
+                std::panic::resume_unwind(panic);
+            }
+        };
+
+        let recorded = match keep(&result) {
+            Some(outcome) => {
+                let status = i32::from(outcome.status.as_u16());
+                db::complete_idempotency_key(&pool, endpoint, &key, status, &outcome.body).await
+            }
+            None => db::release_idempotency_key(&pool, endpoint, &key).await,
+        };
+        if let Err(e) = recorded {
+            tracing::error!(
+                "Failed to record the {} response for idempotency key {key}: {e}",
+                endpoint.as_str()
+            );
+        }
+        result
+    });
+
+    let result = task
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+
+    Ok(Execution::Ran(result))
+}
+
+/// Bind the key the current request holds, if any, to the payment it is about
+/// to send. Call once the payment is recorded and before it goes out.
+pub async fn bind_payment(pool: &DbPool, payment_hash: &str) -> Result<(), DbError> {
+    let Ok((endpoint, key)) = CLAIMED.try_with(|(endpoint, key)| (*endpoint, key.clone())) else {
+        return Ok(());
+    };
+
+    db::bind_idempotency_key(pool, endpoint, &key, payment_hash).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::KeysendRequest;
+
+    #[test]
+    fn keysend_fingerprint_ignores_record_order() {
+        let request = |types: Vec<u64>| KeysendRequest {
+            destination: format!("02{}", "ab".repeat(32)),
+            amount_sats: Some(5),
+            custom_records: types
+                .into_iter()
+                .map(|i| (65_536 + i * 7919, format!("{i:02x}")))
+                .collect(),
+            ..Default::default()
+        };
+
+        let first = fingerprint(&request((0..8).collect()));
+        for _ in 0..50 {
+            assert_eq!(fingerprint(&request((0..8).collect())), first);
+            assert_eq!(fingerprint(&request((0..8).rev().collect())), first);
+        }
+    }
+}
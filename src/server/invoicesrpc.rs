@@ -0,0 +1,120 @@
+//! Bindings for the parts of LND's `invoicesrpc.Invoices` service used by THOrs.
+//!
+//! Like `routerrpc`, these mirror `invoicesrpc/invoices.proto` by field tag
+//! because `tonic_lnd` 0.5 only ships `lnrpc`. Fields THOrs never sets are
+//! left out.
+
+use tonic_lnd::tonic;
+use tonic_lnd::tonic::codegen::{http, Body, StdError};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AddHoldInvoiceRequest {
+    #[prost(string, tag = "1")]
+    pub memo: String,
+    /// The hash of the preimage; LND never learns the preimage until settlement.
+    #[prost(bytes = "vec", tag = "2")]
+    pub hash: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub description_hash: Vec<u8>,
+    #[prost(int64, tag = "5")]
+    pub expiry: i64,
+    #[prost(string, tag = "6")]
+    pub fallback_addr: String,
+    #[prost(uint64, tag = "7")]
+    pub cltv_expiry: u64,
+    #[prost(bool, tag = "9")]
+    pub private: bool,
+    #[prost(int64, tag = "10")]
+    pub value_msat: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AddHoldInvoiceResp {
+    #[prost(string, tag = "1")]
+    pub payment_request: String,
+    #[prost(uint64, tag = "2")]
+    pub add_index: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub payment_addr: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SettleInvoiceMsg {
+    /// Preimage of the accepted hold invoice to settle.
+    #[prost(bytes = "vec", tag = "1")]
+    pub preimage: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SettleInvoiceResp {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CancelInvoiceMsg {
+    /// Hash of the invoice to cancel.
+    #[prost(bytes = "vec", tag = "1")]
+    pub payment_hash: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CancelInvoiceResp {}
+
+#[derive(Clone)]
+pub struct InvoicesClient<T> {
+    inner: tonic::client::Grpc<T>,
+}
+
+impl<T> InvoicesClient<T>
+where
+    T: tonic::client::GrpcService<tonic::body::BoxBody>,
+    T::ResponseBody: Body + Send + Sync + 'static,
+    T::Error: Into<StdError>,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: tonic::client::Grpc::new(inner),
+        }
+    }
+
+    /// Create an invoice whose incoming HTLCs are held until settled or cancelled.
+    pub async fn add_hold_invoice(
+        &mut self,
+        request: impl tonic::IntoRequest<AddHoldInvoiceRequest>,
+    ) -> Result<tonic::Response<AddHoldInvoiceResp>, tonic::Status> {
+        self.ready().await?;
+        let codec = tonic::codec::ProstCodec::default();
+        let path = http::uri::PathAndQuery::from_static("/invoicesrpc.Invoices/AddHoldInvoice");
+        self.inner.unary(request.into_request(), path, codec).await
+    }
+
+    /// Release the held HTLCs of an accepted hold invoice.
+    pub async fn settle_invoice(
+        &mut self,
+        request: impl tonic::IntoRequest<SettleInvoiceMsg>,
+    ) -> Result<tonic::Response<SettleInvoiceResp>, tonic::Status> {
+        self.ready().await?;
+        let codec = tonic::codec::ProstCodec::default();
+        let path = http::uri::PathAndQuery::from_static("/invoicesrpc.Invoices/SettleInvoice");
+        self.inner.unary(request.into_request(), path, codec).await
+    }
+
+    /// Cancel an open or accepted invoice, failing any held HTLCs back.
+    pub async fn cancel_invoice(
+        &mut self,
+        request: impl tonic::IntoRequest<CancelInvoiceMsg>,
+    ) -> Result<tonic::Response<CancelInvoiceResp>, tonic::Status> {
+        self.ready().await?;
+        let codec = tonic::codec::ProstCodec::default();
+        let path = http::uri::PathAndQuery::from_static("/invoicesrpc.Invoices/CancelInvoice");
+        self.inner.unary(request.into_request(), path, codec).await
+    }
+
+    async fn ready(&mut self) -> Result<(), tonic::Status> {
+        self.inner.ready().await.map_err(|e| {
+            tonic::Status::new(
+                tonic::Code::Unknown,
+                format!("Service was not ready: {}", e.into()),
+            )
+        })
+    }
+}
@@ -0,0 +1,663 @@
+//! In-memory Lightning network used in place of LND.
+//!
+//! A `MockNetwork` keeps every invoice created by its nodes. Paying an invoice
+//! from one `MockNode` settles it on the node that issued it and pushes the
+//! update to that node's invoice subscribers, the same way LND would. Hold
+//! invoices stop at `ACCEPTED` and keep the payer's stream in flight until the
+//! issuer settles or cancels them.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, oneshot};
+use tokio_stream::wrappers::BroadcastStream;
+use tonic_lnd::lnrpc::invoice::InvoiceState;
+use tonic_lnd::lnrpc::payment::PaymentStatus;
+use tonic_lnd::lnrpc::PaymentFailureReason;
+use tonic_lnd::{lnrpc, tonic};
+
+use super::lnd::{
+    InvoiceParams, InvoiceStream, KeysendParams, LightningBackend, LndError, PaymentStream,
+};
+
+/// Every mock route charges a 1 sat base fee plus 1000 ppm of the amount.
+const ROUTING_FEE_BASE_MSAT: i64 = 1_000;
+const ROUTING_FEE_PPM: i64 = 1_000;
+
+#[derive(Clone, Default)]
+pub struct MockNetwork {
+    state: Arc<Mutex<NetworkState>>,
+}
+
+#[derive(Default)]
+struct NetworkState {
+    subscribers: HashMap<String, broadcast::Sender<lnrpc::Invoice>>,
+    invoices: Vec<StoredInvoice>,
+    payments: Vec<StoredPayment>,
+    /// Payers waiting on accepted hold invoices, keyed by payment hash. They
+    /// receive the preimage on settle and `None` on cancel.
+    held: HashMap<Vec<u8>, oneshot::Sender<Option<Vec<u8>>>>,
+    preimage_counter: u64,
+}
+
+struct StoredInvoice {
+    node: String,
+    invoice: lnrpc::Invoice,
+    hold: bool,
+}
+
+/// Latest state of a payment, kept on the sending node for `track_payment`.
+struct StoredPayment {
+    node: String,
+    payment: lnrpc::Payment,
+}
+
+/// A simulated node attached to a `MockNetwork`.
+#[derive(Clone)]
+pub struct MockNode {
+    pubkey: String,
+    network: MockNetwork,
+}
+
+impl MockNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node to the network. The pubkey is derived from the alias, so
+    /// calling this twice with the same alias returns the same node.
+    pub fn node(&self, alias: &str) -> MockNode {
+        let pubkey = format!("02{}", hex::encode(sha256(alias.as_bytes())));
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .subscribers
+            .entry(pubkey.clone())
+            .or_insert_with(|| broadcast::channel(100).0);
+
+        MockNode {
+            pubkey,
+            network: self.clone(),
+        }
+    }
+}
+
+impl MockNode {
+    pub fn pubkey(&self) -> &str {
+        &self.pubkey
+    }
+
+    /// Record a failed payment and return its `IN_FLIGHT`, `FAILED` updates.
+    fn fail_payment(
+        &self,
+        state: &mut NetworkState,
+        in_flight: lnrpc::Payment,
+        reason: PaymentFailureReason,
+    ) -> PaymentStream {
+        let failed = failed_payment(&in_flight, reason);
+        self.record_payment(state, failed.clone());
+
+        Box::pin(futures::stream::iter([Ok(in_flight), Ok(failed)]))
+    }
+
+    /// Record a successful payment and return its `IN_FLIGHT`, `SUCCEEDED` updates.
+    fn succeed_payment(
+        &self,
+        state: &mut NetworkState,
+        in_flight: lnrpc::Payment,
+        preimage: &[u8],
+    ) -> PaymentStream {
+        let succeeded = succeeded_payment(&in_flight, preimage);
+        self.record_payment(state, succeeded.clone());
+
+        Box::pin(futures::stream::iter([Ok(in_flight), Ok(succeeded)]))
+    }
+
+    /// Record a payment to an accepted hold invoice. The returned stream stays
+    /// `IN_FLIGHT` until the issuer settles or cancels the invoice.
+    fn hold_payment(
+        &self,
+        state: &mut NetworkState,
+        in_flight: lnrpc::Payment,
+        r_hash: Vec<u8>,
+    ) -> PaymentStream {
+        let (resolve_tx, resolve_rx) = oneshot::channel();
+        state.held.insert(r_hash, resolve_tx);
+        self.record_payment(state, in_flight.clone());
+
+        let node = self.clone();
+        let pending = in_flight.clone();
+        let resolved = async move {
+            let payment = match resolve_rx.await.ok().flatten() {
+                Some(preimage) => succeeded_payment(&pending, &preimage),
+                None => failed_payment(
+                    &pending,
+                    PaymentFailureReason::FailureReasonIncorrectPaymentDetails,
+                ),
+            };
+            let mut state = node.network.state.lock().unwrap();
+            node.record_payment(&mut state, payment.clone());
+            Ok(payment)
+        };
+
+        Box::pin(futures::stream::iter([Ok(in_flight)]).chain(futures::stream::once(resolved)))
+    }
+
+    /// Store the latest state of a payment for `track_payment`. A new payment
+    /// gets the node's next payment index, an update keeps the one it has.
+    fn record_payment(&self, state: &mut NetworkState, mut payment: lnrpc::Payment) {
+        if let Some(stored) = state.payments.iter_mut().find(|stored| {
+            stored.node == self.pubkey && stored.payment.payment_hash == payment.payment_hash
+        }) {
+            payment.payment_index = stored.payment.payment_index;
+            stored.payment = payment;
+            return;
+        }
+
+        payment.payment_index = state.node_payments(&self.pubkey).count() as u64 + 1;
+        state.payments.push(StoredPayment {
+            node: self.pubkey.clone(),
+            payment,
+        });
+    }
+
+    /// Store a new invoice on this node and announce it to subscribers.
+    fn add_invoice(
+        &self,
+        state: &mut NetworkState,
+        params: InvoiceParams,
+        r_preimage: Vec<u8>,
+        r_hash: Vec<u8>,
+        hold: bool,
+    ) -> lnrpc::AddInvoiceResponse {
+        let add_index = state.node_invoices(&self.pubkey).count() as u64 + 1;
+
+        let invoice = lnrpc::Invoice {
+            memo: params.memo.unwrap_or_default(),
+            r_preimage,
+            r_hash: r_hash.clone(),
+            value: params.amount_msat / 1000,
+            value_msat: params.amount_msat,
+            creation_date: Utc::now().timestamp(),
+            payment_request: format!("lnmock1{}", hex::encode(&r_hash)),
+            description_hash: params.description_hash.unwrap_or_default(),
+            expiry: params.expiry_secs,
+            fallback_addr: params.fallback_addr.unwrap_or_default(),
+            cltv_expiry: params.cltv_expiry,
+            private: params.private,
+            add_index,
+            state: InvoiceState::Open as i32,
+            ..Default::default()
+        };
+
+        state.notify(&self.pubkey, &invoice);
+        let response = lnrpc::AddInvoiceResponse {
+            r_hash,
+            payment_request: invoice.payment_request.clone(),
+            add_index,
+            ..Default::default()
+        };
+        state.invoices.push(StoredInvoice {
+            node: self.pubkey.clone(),
+            invoice,
+            hold,
+        });
+
+        response
+    }
+
+    /// Find one of this node's invoices by payment hash.
+    fn own_invoice<'a>(
+        &self,
+        state: &'a mut NetworkState,
+        r_hash: &[u8],
+    ) -> Option<&'a mut lnrpc::Invoice> {
+        state
+            .invoices
+            .iter_mut()
+            .find(|stored| stored.node == self.pubkey && stored.invoice.r_hash == r_hash)
+            .map(|stored| &mut stored.invoice)
+    }
+}
+
+impl NetworkState {
+    fn notify(&self, node: &str, invoice: &lnrpc::Invoice) {
+        if let Some(tx) = self.subscribers.get(node) {
+            let _ = tx.send(invoice.clone());
+        }
+    }
+
+    fn next_settle_index(&self, node: &str) -> u64 {
+        self.node_invoices(node)
+            .filter(|invoice| invoice.state == InvoiceState::Settled as i32)
+            .count() as u64
+            + 1
+    }
+
+    fn node_payments<'a>(&'a self, node: &'a str) -> impl Iterator<Item = &'a lnrpc::Payment> {
+        self.payments
+            .iter()
+            .filter(move |stored| stored.node == node)
+            .map(|stored| &stored.payment)
+    }
+
+    fn node_invoices<'a>(&'a self, node: &'a str) -> impl Iterator<Item = &'a lnrpc::Invoice> {
+        self.invoices
+            .iter()
+            .filter(move |stored| stored.node == node)
+            .map(|stored| &stored.invoice)
+    }
+}
+
+#[async_trait]
+impl LightningBackend for MockNode {
+    async fn get_node_pubkey(&self) -> Result<String, LndError> {
+        Ok(self.pubkey.clone())
+    }
+
+    async fn create_invoice(
+        &self,
+        params: InvoiceParams,
+    ) -> Result<lnrpc::AddInvoiceResponse, LndError> {
+        let mut state = self.network.state.lock().unwrap();
+
+        state.preimage_counter += 1;
+        let preimage = sha256(format!("{}:{}", self.pubkey, state.preimage_counter).as_bytes());
+        let r_hash = sha256(&preimage);
+
+        Ok(self.add_invoice(&mut state, params, preimage, r_hash, false))
+    }
+
+    async fn create_hold_invoice(
+        &self,
+        params: InvoiceParams,
+        payment_hash: Vec<u8>,
+    ) -> Result<lnrpc::AddInvoiceResponse, LndError> {
+        let mut state = self.network.state.lock().unwrap();
+
+        if state
+            .node_invoices(&self.pubkey)
+            .any(|invoice| invoice.r_hash == payment_hash)
+        {
+            return Err(
+                tonic::Status::already_exists("invoice with payment hash already exists").into(),
+            );
+        }
+
+        Ok(self.add_invoice(&mut state, params, Vec::new(), payment_hash, true))
+    }
+
+    async fn settle_invoice(&self, preimage: Vec<u8>) -> Result<(), LndError> {
+        let mut state = self.network.state.lock().unwrap();
+        let r_hash = sha256(&preimage);
+        let settle_index = state.next_settle_index(&self.pubkey);
+
+        let invoice = self
+            .own_invoice(&mut state, &r_hash)
+            .ok_or_else(|| tonic::Status::not_found("unable to locate invoice"))?;
+        if invoice.state != InvoiceState::Accepted as i32 {
+            return Err(tonic::Status::failed_precondition("invoice is not accepted").into());
+        }
+        invoice.state = InvoiceState::Settled as i32;
+        invoice.r_preimage = preimage.clone();
+        invoice.settle_date = Utc::now().timestamp();
+        invoice.settle_index = settle_index;
+        let invoice = invoice.clone();
+
+        state.notify(&self.pubkey, &invoice);
+        if let Some(payer) = state.held.remove(&r_hash) {
+            let _ = payer.send(Some(preimage));
+        }
+
+        Ok(())
+    }
+
+    async fn cancel_invoice(&self, payment_hash: Vec<u8>) -> Result<(), LndError> {
+        let mut state = self.network.state.lock().unwrap();
+
+        let invoice = self
+            .own_invoice(&mut state, &payment_hash)
+            .ok_or_else(|| tonic::Status::not_found("unable to locate invoice"))?;
+        if invoice.state == InvoiceState::Settled as i32 {
+            return Err(tonic::Status::failed_precondition("invoice already settled").into());
+        }
+        invoice.state = InvoiceState::Canceled as i32;
+        let invoice = invoice.clone();
+
+        state.notify(&self.pubkey, &invoice);
+        if let Some(payer) = state.held.remove(&payment_hash) {
+            let _ = payer.send(None);
+        }
+
+        Ok(())
+    }
+
+    async fn decode_payment_request(
+        &self,
+        payment_request: String,
+    ) -> Result<lnrpc::PayReq, LndError> {
+        let state = self.network.state.lock().unwrap();
+        let stored = state
+            .invoices
+            .iter()
+            .find(|stored| stored.invoice.payment_request == payment_request)
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid payment request"))?;
+
+        Ok(lnrpc::PayReq {
+            destination: stored.node.clone(),
+            payment_hash: hex::encode(&stored.invoice.r_hash),
+            num_satoshis: stored.invoice.value,
+            timestamp: stored.invoice.creation_date,
+            expiry: stored.invoice.expiry,
+            description: stored.invoice.memo.clone(),
+            description_hash: hex::encode(&stored.invoice.description_hash),
+            fallback_addr: stored.invoice.fallback_addr.clone(),
+            cltv_expiry: stored.invoice.cltv_expiry as i64,
+            num_msat: stored.invoice.value_msat,
+            ..Default::default()
+        })
+    }
+
+    async fn estimate_route_fee(&self, invoice: &lnrpc::PayReq) -> Result<Option<i64>, LndError> {
+        let state = self.network.state.lock().unwrap();
+        let known = state.subscribers.contains_key(&invoice.destination);
+
+        Ok((known && invoice.destination != self.pubkey)
+            .then(|| routing_fee_msat(invoice.num_msat)))
+    }
+
+    async fn send_payment(
+        &self,
+        payment_request: String,
+        amount_msat: i64,
+        fee_limit_msat: i64,
+    ) -> Result<PaymentStream, LndError> {
+        let mut state = self.network.state.lock().unwrap();
+
+        let position = state
+            .invoices
+            .iter()
+            .position(|stored| stored.invoice.payment_request == payment_request)
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid payment request"))?;
+
+        let node = state.invoices[position].node.clone();
+        if node == self.pubkey {
+            return Err(tonic::Status::invalid_argument("no self-payments allowed").into());
+        }
+
+        let invoice = &state.invoices[position].invoice;
+        if invoice.state == InvoiceState::Settled as i32
+            || invoice.state == InvoiceState::Accepted as i32
+        {
+            return Err(tonic::Status::already_exists("invoice is already paid").into());
+        }
+
+        // Same amount rules as LND's router
+        let amount_msat = match (invoice.value_msat, amount_msat) {
+            (0, 0) => {
+                return Err(tonic::Status::invalid_argument(
+                    "amount must be specified when paying a zero amount invoice",
+                )
+                .into());
+            }
+            (0, amount) => amount,
+            (value, 0) => value,
+            _ => {
+                return Err(tonic::Status::invalid_argument(
+                    "amount must not be specified when paying a non-zero amount invoice",
+                )
+                .into());
+            }
+        };
+
+        let in_flight = lnrpc::Payment {
+            payment_hash: hex::encode(&invoice.r_hash),
+            value_sat: amount_msat / 1000,
+            value_msat: amount_msat,
+            payment_request: payment_request.clone(),
+            status: PaymentStatus::InFlight as i32,
+            creation_time_ns: Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            htlcs: vec![htlc_to(&node)],
+            ..Default::default()
+        };
+
+        let failure_reason = if invoice.state == InvoiceState::Canceled as i32 {
+            Some(PaymentFailureReason::FailureReasonIncorrectPaymentDetails)
+        } else if routing_fee_msat(amount_msat) > fee_limit_msat {
+            // LND reports routes that are too expensive as having no route.
+            Some(PaymentFailureReason::FailureReasonNoRoute)
+        } else {
+            None
+        };
+
+        if let Some(reason) = failure_reason {
+            return Ok(self.fail_payment(&mut state, in_flight, reason));
+        }
+
+        // Hold invoices only lock the funds; the issuer decides what happens next
+        if state.invoices[position].hold {
+            let invoice = &mut state.invoices[position].invoice;
+            invoice.state = InvoiceState::Accepted as i32;
+            invoice.amt_paid_sat = amount_msat / 1000;
+            invoice.amt_paid_msat = amount_msat;
+            let invoice = invoice.clone();
+
+            state.notify(&node, &invoice);
+            return Ok(self.hold_payment(&mut state, in_flight, invoice.r_hash));
+        }
+
+        let settle_index = state.next_settle_index(&node);
+
+        let invoice = &mut state.invoices[position].invoice;
+        invoice.state = InvoiceState::Settled as i32;
+        invoice.settle_date = Utc::now().timestamp();
+        invoice.settle_index = settle_index;
+        invoice.amt_paid_sat = amount_msat / 1000;
+        invoice.amt_paid_msat = amount_msat;
+        let invoice = invoice.clone();
+
+        state.notify(&node, &invoice);
+
+        Ok(self.succeed_payment(&mut state, in_flight, &invoice.r_preimage))
+    }
+
+    async fn send_keysend(
+        &self,
+        params: KeysendParams,
+        fee_limit_msat: i64,
+    ) -> Result<PaymentStream, LndError> {
+        let mut state = self.network.state.lock().unwrap();
+        let payment_hash = sha256(&params.preimage);
+
+        let in_flight = lnrpc::Payment {
+            payment_hash: hex::encode(&payment_hash),
+            value_sat: params.amount_msat / 1000,
+            value_msat: params.amount_msat,
+            status: PaymentStatus::InFlight as i32,
+            creation_time_ns: Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            htlcs: vec![htlc_to(&params.destination)],
+            ..Default::default()
+        };
+
+        let reachable = params.destination != self.pubkey
+            && state.subscribers.contains_key(&params.destination);
+        if !reachable || routing_fee_msat(params.amount_msat) > fee_limit_msat {
+            return Ok(self.fail_payment(
+                &mut state,
+                in_flight,
+                PaymentFailureReason::FailureReasonNoRoute,
+            ));
+        }
+
+        // The recipient records a keysend as an invoice that settles on arrival
+        let now = Utc::now().timestamp();
+        let invoice = lnrpc::Invoice {
+            r_preimage: params.preimage.clone(),
+            r_hash: payment_hash,
+            value: params.amount_msat / 1000,
+            value_msat: params.amount_msat,
+            creation_date: now,
+            settle_date: now,
+            add_index: state.node_invoices(&params.destination).count() as u64 + 1,
+            settle_index: state.next_settle_index(&params.destination),
+            amt_paid_sat: params.amount_msat / 1000,
+            amt_paid_msat: params.amount_msat,
+            state: InvoiceState::Settled as i32,
+            is_keysend: true,
+            ..Default::default()
+        };
+
+        state.notify(&params.destination, &invoice);
+        state.invoices.push(StoredInvoice {
+            node: params.destination,
+            invoice,
+            hold: false,
+        });
+
+        Ok(self.succeed_payment(&mut state, in_flight, &params.preimage))
+    }
+
+    async fn track_payment(&self, payment_hash: String) -> Result<PaymentStream, LndError> {
+        let state = self.network.state.lock().unwrap();
+
+        // Mock payments finish synchronously, so the stored state is already final.
+        let payment = state
+            .payments
+            .iter()
+            .rev()
+            .find(|stored| {
+                stored.node == self.pubkey && stored.payment.payment_hash == payment_hash
+            })
+            .map(|stored| stored.payment.clone())
+            .ok_or_else(|| tonic::Status::not_found("payment isn't initiated"))?;
+
+        Ok(Box::pin(futures::stream::iter([Ok(payment)])))
+    }
+
+    async fn list_invoices(
+        &self,
+        request: lnrpc::ListInvoiceRequest,
+    ) -> Result<lnrpc::ListInvoiceResponse, LndError> {
+        let state = self.network.state.lock().unwrap();
+
+        let mut invoices: Vec<lnrpc::Invoice> = state
+            .node_invoices(&self.pubkey)
+            .filter(|invoice| invoice.add_index > request.index_offset)
+            .filter(|invoice| !request.pending_only || invoice.state == InvoiceState::Open as i32)
+            .cloned()
+            .collect();
+
+        if request.reversed {
+            invoices.reverse();
+        }
+        invoices.truncate(usize::try_from(request.num_max_invoices).unwrap_or(usize::MAX));
+
+        Ok(lnrpc::ListInvoiceResponse {
+            first_index_offset: invoices.first().map_or(0, |invoice| invoice.add_index),
+            last_index_offset: invoices.last().map_or(0, |invoice| invoice.add_index),
+            invoices,
+        })
+    }
+
+    async fn list_payments(
+        &self,
+        request: lnrpc::ListPaymentsRequest,
+    ) -> Result<lnrpc::ListPaymentsResponse, LndError> {
+        let state = self.network.state.lock().unwrap();
+
+        let mut payments: Vec<lnrpc::Payment> = state
+            .node_payments(&self.pubkey)
+            .filter(|payment| payment.payment_index > request.index_offset)
+            .filter(|payment| {
+                request.include_incomplete || payment.status == PaymentStatus::Succeeded as i32
+            })
+            .cloned()
+            .collect();
+
+        if request.reversed {
+            payments.reverse();
+        }
+        payments.truncate(usize::try_from(request.max_payments).unwrap_or(usize::MAX));
+
+        Ok(lnrpc::ListPaymentsResponse {
+            first_index_offset: payments.first().map_or(0, |payment| payment.payment_index),
+            last_index_offset: payments.last().map_or(0, |payment| payment.payment_index),
+            total_num_payments: 0,
+            payments,
+        })
+    }
+
+    async fn subscribe_invoices(
+        &self,
+        add_index: u64,
+        settle_index: u64,
+    ) -> Result<InvoiceStream, LndError> {
+        let state = self.network.state.lock().unwrap();
+
+        // Like LND, replay everything past the given indices before going live.
+        let backlog: Vec<Result<lnrpc::Invoice, LndError>> = state
+            .node_invoices(&self.pubkey)
+            .filter(|invoice| {
+                (add_index > 0 && invoice.add_index > add_index)
+                    || (settle_index > 0 && invoice.settle_index > settle_index)
+            })
+            .cloned()
+            .map(Ok)
+            .collect();
+
+        let live = state
+            .subscribers
+            .get(&self.pubkey)
+            .map(|tx| tx.subscribe())
+            .ok_or_else(|| LndError::Connection("mock node is not registered".into()))?;
+
+        let live = BroadcastStream::new(live).filter_map(|item| async move { item.ok().map(Ok) });
+
+        Ok(Box::pin(futures::stream::iter(backlog).chain(live)))
+    }
+}
+
+fn failed_payment(in_flight: &lnrpc::Payment, reason: PaymentFailureReason) -> lnrpc::Payment {
+    lnrpc::Payment {
+        status: PaymentStatus::Failed as i32,
+        failure_reason: reason as i32,
+        ..in_flight.clone()
+    }
+}
+
+fn succeeded_payment(in_flight: &lnrpc::Payment, preimage: &[u8]) -> lnrpc::Payment {
+    let fee_msat = routing_fee_msat(in_flight.value_msat);
+    lnrpc::Payment {
+        status: PaymentStatus::Succeeded as i32,
+        payment_preimage: hex::encode(preimage),
+        fee_sat: fee_msat / 1000,
+        fee_msat,
+        ..in_flight.clone()
+    }
+}
+
+/// A single-hop HTLC attempt, enough to tell where a payment went.
+fn htlc_to(destination: &str) -> lnrpc::HtlcAttempt {
+    lnrpc::HtlcAttempt {
+        route: Some(lnrpc::Route {
+            hops: vec![lnrpc::Hop {
+                pub_key: destination.to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn routing_fee_msat(amount_msat: i64) -> i64 {
+    ROUTING_FEE_BASE_MSAT + amount_msat * ROUTING_FEE_PPM / 1_000_000
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
@@ -9,8 +9,22 @@ pub mod background;
 #[cfg(feature = "ssr")]
 pub mod db;
 #[cfg(feature = "ssr")]
+pub mod export;
+#[cfg(feature = "ssr")]
+pub mod idempotency;
+#[cfg(feature = "ssr")]
+pub mod invoicesrpc;
+#[cfg(feature = "ssr")]
 pub mod lnd;
 #[cfg(feature = "ssr")]
+pub mod mock;
+#[cfg(feature = "ssr")]
+pub mod nodes;
+#[cfg(feature = "ssr")]
+pub mod payments;
+#[cfg(feature = "ssr")]
+pub mod routerrpc;
+#[cfg(feature = "ssr")]
 pub mod sse;
 
 // Re-export commonly used types (SSR only)
@@ -20,4 +34,6 @@ pub use db::{create_pool, DbPool};
 #[cfg(feature = "ssr")]
 pub use functions::AppState;
 #[cfg(feature = "ssr")]
-pub use lnd::{LightningClients, LndError};
+pub use lnd::{LightningBackend, LightningClients, LndError};
+#[cfg(feature = "ssr")]
+pub use nodes::{Node, NodeRegistry};
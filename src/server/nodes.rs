@@ -0,0 +1,132 @@
+//! The Lightning nodes this instance works with.
+//!
+//! Every configured node has a role: `receive` nodes issue invoices, `send`
+//! nodes pay them and `both` do either. Requests may name a node by pubkey;
+//! otherwise the first node with the needed role is used.
+
+use std::sync::Arc;
+
+use super::lnd::LightningBackend;
+use crate::dto::NodeStatus;
+use crate::models::NodeRole;
+
+/// A connected node and what it may be used for.
+#[derive(Clone)]
+pub struct Node {
+    pub name: String,
+    /// Identity pubkey, as recorded on its transactions.
+    pub node_id: String,
+    pub role: NodeRole,
+    pub lnd: Arc<dyn LightningBackend>,
+}
+
+impl Node {
+    pub fn status(&self) -> NodeStatus {
+        NodeStatus {
+            name: self.name.clone(),
+            node_id: self.node_id.clone(),
+            role: self.role,
+            health: *self.lnd.health().borrow(),
+        }
+    }
+}
+
+/// All configured nodes, in configuration order.
+#[derive(Clone)]
+pub struct NodeRegistry {
+    nodes: Arc<[Node]>,
+}
+
+impl NodeRegistry {
+    /// Fails if two nodes share a name or a pubkey.
+    pub fn new(nodes: Vec<Node>) -> Result<Self, String> {
+        for (i, node) in nodes.iter().enumerate() {
+            for other in &nodes[..i] {
+                if other.name == node.name {
+                    return Err(format!("Node name {} is configured twice", node.name));
+                }
+                if other.node_id == node.node_id {
+                    return Err(format!(
+                        "Nodes {} and {} are the same node ({})",
+                        other.name, node.name, node.node_id
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            nodes: nodes.into(),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter()
+    }
+
+    pub fn receivers(&self) -> impl Iterator<Item = &Node> {
+        self.iter().filter(|node| node.role.can_receive())
+    }
+
+    pub fn senders(&self) -> impl Iterator<Item = &Node> {
+        self.iter().filter(|node| node.role.can_send())
+    }
+
+    /// Whether any node can issue invoices.
+    pub fn can_receive(&self) -> bool {
+        self.receivers().next().is_some()
+    }
+
+    /// Whether any node can make payments.
+    pub fn can_send(&self) -> bool {
+        self.senders().next().is_some()
+    }
+
+    /// Whether any node can act in `role`.
+    pub fn supports(&self, role: NodeRole) -> bool {
+        match role {
+            NodeRole::Receive => self.can_receive(),
+            NodeRole::Send => self.can_send(),
+            NodeRole::Both => self.can_receive() && self.can_send(),
+        }
+    }
+
+    pub fn get(&self, node_id: &str) -> Option<&Node> {
+        self.iter().find(|node| node.node_id == node_id)
+    }
+
+    /// The node to create an invoice on.
+    pub fn receiver(&self, node_id: Option<&str>) -> Result<&Node, String> {
+        self.pick(node_id, self.receivers(), "receive")
+    }
+
+    /// The node to pay from.
+    pub fn sender(&self, node_id: Option<&str>) -> Result<&Node, String> {
+        self.pick(node_id, self.senders(), "send")
+    }
+
+    fn pick<'a>(
+        &'a self,
+        node_id: Option<&str>,
+        mut candidates: impl Iterator<Item = &'a Node>,
+        role: &str,
+    ) -> Result<&'a Node, String> {
+        match node_id {
+            Some(node_id) => {
+                let node = self
+                    .get(node_id)
+                    .ok_or_else(|| format!("Unknown node {}", node_id))?;
+                candidates
+                    .find(|candidate| candidate.node_id == node.node_id)
+                    .ok_or_else(|| format!("Node {} is not configured to {}", node.name, role))
+            }
+            None => candidates
+                .next()
+                .ok_or_else(|| format!("No node is configured to {}", role)),
+        }
+    }
+
+    /// Current health of every node.
+    pub fn statuses(&self) -> Vec<NodeStatus> {
+        self.iter().map(Node::status).collect()
+    }
+}
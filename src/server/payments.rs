@@ -0,0 +1,204 @@
+//! Sending payments, shared by the REST endpoints and the server functions.
+
+use tonic_lnd::lnrpc;
+
+use crate::dto::{FeeLimit, KeysendRequest, PayInvoiceRequest, PaymentResponse};
+use crate::models::{NewTransaction, Transaction, TransitionSource, TxStatus, TxType};
+use crate::server::api::ApiError;
+use crate::server::db::DbPool;
+use crate::server::idempotency::{self, IdempotencyError};
+use crate::server::lnd::{apply_payment_amount, KeysendParams};
+use crate::server::{background, db, AppState, Node};
+
+/// How to hand a recorded payment to LND.
+enum Dispatch {
+    Invoice {
+        payment_request: String,
+        /// Zero unless the invoice leaves the amount open.
+        amount_msat: i64,
+    },
+    Keysend(KeysendParams),
+}
+
+/// Pay a BOLT 11 invoice from the requested send node.
+pub async fn pay_invoice(
+    state: AppState,
+    request: PayInvoiceRequest,
+) -> Result<PaymentResponse, ApiError> {
+    if request.payment_request.is_empty() {
+        return Err(ApiError::BadRequest("payment_request is required".into()));
+    }
+
+    let node = state
+        .nodes
+        .sender(request.node_id.as_deref())
+        .map_err(ApiError::BadRequest)?;
+    let fee_limit = FeeLimit::from_parts(request.max_fee_sats, request.max_fee_percent)
+        .map_err(ApiError::BadRequest)?
+        .unwrap_or(state.fee_limit);
+
+    // Amountless invoices take the amount from the caller
+    let mut decoded = node
+        .lnd
+        .decode_payment_request(request.payment_request.clone())
+        .await?;
+    let amount_msat = apply_payment_amount(&mut decoded, request.amount_sats, request.amount_msat)
+        .map_err(ApiError::BadRequest)?;
+
+    let new_tx = NewTransaction::new(
+        TxType::Payment,
+        decoded.payment_hash.clone(),
+        request.payment_request.clone(),
+        decoded.num_msat,
+        Some(decoded.description.clone()),
+        TxStatus::Pending,
+        None,
+        node.node_id.clone(),
+    )
+    .with_destination(decoded.destination.clone());
+
+    let dispatch = Dispatch::Invoice {
+        payment_request: request.payment_request,
+        amount_msat,
+    };
+    send(&state, node, fee_limit, &decoded, new_tx, dispatch).await
+}
+
+/// Send a keysend payment straight to a node pubkey.
+pub async fn keysend(
+    state: AppState,
+    request: KeysendRequest,
+) -> Result<PaymentResponse, ApiError> {
+    let node = state
+        .nodes
+        .sender(request.node_id.as_deref())
+        .map_err(ApiError::BadRequest)?;
+    let params = KeysendParams::resolve(&request).map_err(ApiError::BadRequest)?;
+    let fee_limit = FeeLimit::from_parts(request.max_fee_sats, request.max_fee_percent)
+        .map_err(ApiError::BadRequest)?
+        .unwrap_or(state.fee_limit);
+
+    // There is no invoice, so the payment request stays empty
+    let new_tx = NewTransaction::new(
+        TxType::Payment,
+        params.payment_hash(),
+        String::new(),
+        params.amount_msat,
+        None,
+        TxStatus::Pending,
+        None,
+        node.node_id.clone(),
+    )
+    .with_destination(params.destination.clone());
+
+    let estimate = params.as_pay_req();
+    send(
+        &state,
+        node,
+        fee_limit,
+        &estimate,
+        new_tx,
+        Dispatch::Keysend(params),
+    )
+    .await
+}
+
+/// Record `new_tx` as pending, send it and follow it until it settles or fails.
+/// `payment` describes it the way a decoded invoice would.
+async fn send(
+    state: &AppState,
+    node: &Node,
+    fee_limit: FeeLimit,
+    payment: &lnrpc::PayReq,
+    new_tx: NewTransaction,
+    dispatch: Dispatch,
+) -> Result<PaymentResponse, ApiError> {
+    // Avoid duplicate payment records for the same hash
+    let existing =
+        db::get_transaction_by_hash(&state.db_pool, TxType::Payment, &payment.payment_hash).await?;
+    if existing.is_some() {
+        return Err(ApiError::DuplicatePayment);
+    }
+
+    // Refuse up front if even the cheapest known route is over the limit
+    let limit_msat = fee_limit.limit_msat(payment.num_msat);
+    if let Some(fee_msat) = node.lnd.estimate_route_fee(payment).await? {
+        if fee_msat > limit_msat {
+            return Err(fee_limit_exceeded(fee_msat, limit_msat));
+        }
+    }
+
+    db::create_transaction(&state.db_pool, new_tx, TransitionSource::Api).await?;
+    idempotency::bind_payment(&state.db_pool, &payment.payment_hash).await?;
+
+    // Every router update is recorded and broadcast until the payment settles or fails
+    let updates = match dispatch {
+        Dispatch::Invoice {
+            payment_request,
+            amount_msat,
+        } => {
+            node.lnd
+                .send_payment(payment_request, amount_msat, limit_msat)
+                .await?
+        }
+        Dispatch::Keysend(params) => node.lnd.send_keysend(params, limit_msat).await?,
+    };
+
+    let tx = background::follow_payment(updates, &state.db_pool, &state.broadcast_tx).await?;
+    let Some(tx) = tx else {
+        return Err(ApiError::PaymentFailed(
+            "payment stream ended before a final state".into(),
+        ));
+    };
+
+    if tx.status == TxStatus::Failed {
+        // LND reports a payment it could only route over the fee limit as
+        // having no route, so tell the two apart by asking for a route again
+        if tx.failure_code.as_deref() == Some(background::NO_ROUTE_FAILURE_CODE) {
+            if let Ok(Some(fee_msat)) = node.lnd.estimate_route_fee(payment).await {
+                if fee_msat > limit_msat {
+                    return Err(fee_limit_exceeded(fee_msat, limit_msat));
+                }
+            }
+        }
+
+        return Err(ApiError::PaymentFailed(
+            tx.failure_reason.unwrap_or_default(),
+        ));
+    }
+
+    Ok(payment_response(tx))
+}
+
+/// Answer a retry of a request that sent the payment with `payment_hash` but
+/// recorded no response, from the payment's current state.
+pub async fn replay(pool: &DbPool, payment_hash: &str) -> Result<PaymentResponse, ApiError> {
+    let tx = db::get_transaction_by_hash(pool, TxType::Payment, payment_hash).await?;
+
+    match tx {
+        Some(tx) if tx.status == TxStatus::Succeeded => Ok(payment_response(tx)),
+        Some(tx) if tx.status == TxStatus::Failed => Err(ApiError::PaymentFailed(
+            tx.failure_reason.unwrap_or_default(),
+        )),
+        // Still in flight; reconciliation settles it eventually
+        _ => Err(IdempotencyError::InProgress.into()),
+    }
+}
+
+fn fee_limit_exceeded(fee_msat: i64, limit_msat: i64) -> ApiError {
+    ApiError::FeeLimitExceeded {
+        fee_sats: (fee_msat + 999) / 1000,
+        limit_sats: limit_msat / 1000,
+    }
+}
+
+fn payment_response(tx: Transaction) -> PaymentResponse {
+    PaymentResponse {
+        payment_hash: tx.payment_hash,
+        preimage: tx.preimage.unwrap_or_default(),
+        amount_sats: tx.amount_sats,
+        amount_msat: tx.amount_msat,
+        fee_sats: tx.fee_sats.unwrap_or_default(),
+        fee_msat: tx.fee_msat.unwrap_or_default(),
+    }
+}
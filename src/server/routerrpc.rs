@@ -0,0 +1,103 @@
+//! Bindings for the parts of LND's `routerrpc.Router` service used by THOrs.
+//!
+//! `tonic_lnd` 0.5 only ships `lnrpc`, so the messages below mirror
+//! `routerrpc/router.proto` by field tag. Fields THOrs never sets are left out;
+//! protobuf encoding skips them either way.
+
+use std::collections::HashMap;
+
+use tonic_lnd::lnrpc;
+use tonic_lnd::tonic;
+use tonic_lnd::tonic::codegen::{http, Body, StdError};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SendPaymentRequest {
+    /// Identity pubkey of the payment recipient, for payments without an invoice.
+    #[prost(bytes = "vec", tag = "1")]
+    pub dest: Vec<u8>,
+    /// Hash to use within the HTLCs, for payments without an invoice.
+    #[prost(bytes = "vec", tag = "3")]
+    pub payment_hash: Vec<u8>,
+    /// Custom TLV records for the final hop, e.g. the keysend preimage.
+    #[prost(map = "uint64, bytes", tag = "11")]
+    pub dest_custom_records: HashMap<u64, Vec<u8>>,
+    /// Amount to send in millisatoshis. Only allowed for amountless invoices.
+    #[prost(int64, tag = "12")]
+    pub amt_msat: i64,
+    /// A bare-bones invoice for a payment within the Lightning Network.
+    #[prost(string, tag = "5")]
+    pub payment_request: String,
+    /// Upper limit on the time a payment may be attempted, in seconds.
+    #[prost(int32, tag = "6")]
+    pub timeout_seconds: i32,
+    /// Maximum fee in millisatoshis. Zero means only zero-fee routes.
+    #[prost(int64, tag = "13")]
+    pub fee_limit_msat: i64,
+    /// Skip `IN_FLIGHT` updates and only report the final state.
+    #[prost(bool, tag = "18")]
+    pub no_inflight_updates: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TrackPaymentRequest {
+    /// The hash of the payment to look up.
+    #[prost(bytes = "vec", tag = "1")]
+    pub payment_hash: Vec<u8>,
+    /// Skip `IN_FLIGHT` updates and only report the final state.
+    #[prost(bool, tag = "2")]
+    pub no_inflight_updates: bool,
+}
+
+#[derive(Clone)]
+pub struct RouterClient<T> {
+    inner: tonic::client::Grpc<T>,
+}
+
+impl<T> RouterClient<T>
+where
+    T: tonic::client::GrpcService<tonic::body::BoxBody>,
+    T::ResponseBody: Body + Send + Sync + 'static,
+    T::Error: Into<StdError>,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: tonic::client::Grpc::new(inner),
+        }
+    }
+
+    /// Attempt a payment and stream its status until it reaches a final state.
+    pub async fn send_payment_v2(
+        &mut self,
+        request: impl tonic::IntoRequest<SendPaymentRequest>,
+    ) -> Result<tonic::Response<tonic::codec::Streaming<lnrpc::Payment>>, tonic::Status> {
+        self.ready().await?;
+        let codec = tonic::codec::ProstCodec::default();
+        let path = http::uri::PathAndQuery::from_static("/routerrpc.Router/SendPaymentV2");
+        self.inner
+            .server_streaming(request.into_request(), path, codec)
+            .await
+    }
+
+    /// Stream the state of a payment sent earlier, starting with its current one.
+    pub async fn track_payment_v2(
+        &mut self,
+        request: impl tonic::IntoRequest<TrackPaymentRequest>,
+    ) -> Result<tonic::Response<tonic::codec::Streaming<lnrpc::Payment>>, tonic::Status> {
+        self.ready().await?;
+        let codec = tonic::codec::ProstCodec::default();
+        let path = http::uri::PathAndQuery::from_static("/routerrpc.Router/TrackPaymentV2");
+        self.inner
+            .server_streaming(request.into_request(), path, codec)
+            .await
+    }
+
+    async fn ready(&mut self) -> Result<(), tonic::Status> {
+        self.inner.ready().await.map_err(|e| {
+            tonic::Status::new(
+                tonic::Code::Unknown,
+                format!("Service was not ready: {}", e.into()),
+            )
+        })
+    }
+}
@@ -177,15 +177,15 @@ INVOICE_PR=$(json_field payment_request)
 INVOICE_HASH=$(json_field payment_hash)
 
 # ==================================================================
-# 4. POST /api/invoice — validation (amount <= 0)
+# 4. POST /api/invoice — validation (amount < 0, amountless)
 # ==================================================================
 echo -e "${CYAN}--- 4. POST /api/invoice (validation) ---${NC}"
-http POST /api/invoice '{"amount_sats": 0}'
-assert_status 400 "Invoice with amount 0 returns 400"
-assert_json_field "error" "Error response has error field"
-
 http POST /api/invoice '{"amount_sats": -5}'
 assert_status 400 "Invoice with negative amount returns 400"
+assert_json_field "error" "Error response has error field"
+
+http POST /api/invoice '{"amount_sats": 0}'
+assert_status 201 "Amountless invoice (amount 0) returns 201"
 
 # ==================================================================
 # 5. GET /api/invoice/{hash} — lookup pending invoice
@@ -216,6 +216,7 @@ assert_json_field "payment_hash" "Payment has payment_hash"
 assert_json_field "preimage" "Payment has preimage"
 assert_json_field "amount_sats" "Payment has amount_sats"
 assert_json_field_equals "amount_sats" "1000" "Payment amount matches invoice"
+assert_json_field "fee_sats" "Payment has fee_sats"
 PAYMENT_HASH=$(json_field payment_hash)
 
 # ==================================================================
@@ -226,6 +227,9 @@ http POST /api/payment '{"payment_request": ""}'
 assert_status 400 "Payment with empty request returns 400"
 assert_json_field "error" "Error response has error field"
 
+http POST /api/payment "{\"payment_request\": \"${INVOICE_PR}\", \"max_fee_sats\": 1, \"max_fee_percent\": 1}"
+assert_status 400 "Payment with both fee caps returns 400"
+
 # ==================================================================
 # 8. POST /api/payment — duplicate payment guard
 # ==================================================================
@@ -313,6 +317,51 @@ http GET /api/payment/0000000000000000000000000000000000000000000000000000000000
 assert_status 404 "Nonexistent payment returns 404"
 assert_json_field "error" "404 response has error field"
 
+# ==================================================================
+# 16. Hold invoice — create, refuse early settle, cancel
+# ==================================================================
+echo -e "${CYAN}--- 16. Hold invoice (settle/cancel) ---${NC}"
+http POST /api/invoice '{"amount_sats": 500, "hold": true}'
+assert_status 201 "Create hold invoice returns 201"
+assert_json_field "preimage" "Hold invoice has preimage"
+HOLD_HASH=$(json_field payment_hash)
+HOLD_PREIMAGE=$(json_field preimage)
+
+echo -e "  ${YELLOW}(waiting 2s for background invoice sync)${NC}"
+sleep 2
+
+http POST "/api/invoice/${HOLD_HASH}/settle" "{\"preimage\": \"${HOLD_PREIMAGE}\"}"
+assert_status 400 "Settling an unpaid hold invoice returns 400"
+
+http DELETE "/api/invoice/${HOLD_HASH}"
+assert_status 204 "Cancel hold invoice returns 204"
+
+echo -e "  ${YELLOW}(waiting 2s for cancellation sync)${NC}"
+sleep 2
+
+http GET "/api/invoice/${HOLD_HASH}"
+assert_json_field_equals "status" "expired" "Cancelled hold invoice is expired"
+
+# ==================================================================
+# 17. DELETE /api/invoice/{hash} — settled invoices cannot be cancelled
+# ==================================================================
+echo -e "${CYAN}--- 17. DELETE /api/invoice/{hash} (settled) ---${NC}"
+http DELETE "/api/invoice/${INVOICE_HASH}"
+assert_status 400 "Cancelling a settled invoice returns 400"
+
+http DELETE /api/invoice/0000000000000000000000000000000000000000000000000000000000000000
+assert_status 404 "Cancelling a nonexistent invoice returns 404"
+
+# ==================================================================
+# 18. GET /api/status — both nodes reachable
+# ==================================================================
+echo -e "${CYAN}--- 18. GET /api/status ---${NC}"
+http GET /api/status
+assert_status 200 "Get node status"
+assert_json_array "Node status list"
+assert_json_field_equals "[0].health" "connected" "Receiver node is connected"
+assert_json_field_equals "[1].health" "connected" "Sender node is connected"
+
 # ==================================================================
 # Summary
 # ==================================================================
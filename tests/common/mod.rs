@@ -0,0 +1,402 @@
+//! A `MockNode` served over gRPC, so the real `LightningClients` can be
+//! exercised end-to-end without LND.
+//!
+//! `MockLnd` speaks the parts of `lnrpc.Lightning`, `routerrpc.Router` and
+//! `invoicesrpc.Invoices` THOrs calls, over TLS with the self-signed
+//! certificate in `tests/fixtures` and only for requests carrying the fixture
+//! macaroon. Everything else answers `Unimplemented`.
+
+#![allow(dead_code)]
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::task::{Context, Poll};
+
+use futures::{Stream, StreamExt, TryStreamExt};
+use thors::initialize::NodeConfig;
+use thors::models::NodeRole;
+use thors::server::lnd::{InvoiceParams, KeysendParams, LightningBackend, LndError};
+use thors::server::mock::MockNode;
+use thors::server::{invoicesrpc, routerrpc};
+use tokio::net::TcpListener;
+use tonic_lnd::lnrpc;
+use tonic_lnd::tonic::body::BoxBody;
+use tonic_lnd::tonic::codec::ProstCodec;
+use tonic_lnd::tonic::codegen::{http, BoxFuture, BoxStream, Service};
+use tonic_lnd::tonic::server::Grpc;
+use tonic_lnd::tonic::transport::{Body, Identity, NamedService, Server, ServerTlsConfig};
+use tonic_lnd::tonic::{self, Status};
+
+pub const CERT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls.cert");
+pub const KEY_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls.key");
+pub const MACAROON_PATH: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/admin.macaroon");
+
+/// A mock node listening for gRPC connections on a local port.
+pub struct MockLndServer {
+    pub addr: SocketAddr,
+    pub node: MockNode,
+}
+
+impl MockLndServer {
+    /// Serve `node` on a free local port until the test process exits.
+    pub async fn start(node: MockNode) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let lnd = MockLnd {
+            node: node.clone(),
+            macaroon: hex::encode(std::fs::read(MACAROON_PATH).unwrap()),
+        };
+        let identity = Identity::from_pem(
+            std::fs::read(CERT_PATH).unwrap(),
+            std::fs::read(KEY_PATH).unwrap(),
+        );
+        let router = Server::builder()
+            .tls_config(ServerTlsConfig::new().identity(identity))
+            .unwrap()
+            .add_service(Lightning(lnd.clone()))
+            .add_service(Router(lnd.clone()))
+            .add_service(Invoices(lnd));
+
+        let incoming = futures::stream::unfold(listener, |listener| async move {
+            let accepted = listener.accept().await.map(|(stream, _)| stream);
+            Some((accepted, listener))
+        });
+        tokio::spawn(router.serve_with_incoming(incoming));
+
+        Self { addr, node }
+    }
+
+    /// The certificate is issued for `localhost`, not the IP address.
+    pub fn endpoint(&self) -> String {
+        format!("https://localhost:{}", self.addr.port())
+    }
+
+    /// Connection settings for THOrs to reach this node.
+    pub fn node_config(&self, name: &str, role: NodeRole) -> NodeConfig {
+        NodeConfig {
+            name: name.to_string(),
+            endpoint: self.endpoint(),
+            cert_path: CERT_PATH.to_string(),
+            macaroon_path: MACAROON_PATH.to_string(),
+            role,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MockLnd {
+    node: MockNode,
+    /// Hex-encoded macaroon every request must carry.
+    macaroon: String,
+}
+
+impl MockLnd {
+    async fn handle(self, request: http::Request<Body>) -> http::Response<BoxBody> {
+        let authorized = request
+            .headers()
+            .get("macaroon")
+            .is_some_and(|macaroon| macaroon.as_bytes() == self.macaroon.as_bytes());
+        if !authorized {
+            return Status::unauthenticated("invalid macaroon").to_http();
+        }
+
+        let node = self.node;
+        match request.uri().path() {
+            "/lnrpc.Lightning/GetInfo" => {
+                unary(request, |_: lnrpc::GetInfoRequest| async move {
+                    Ok(lnrpc::GetInfoResponse {
+                        identity_pubkey: node.get_node_pubkey().await?,
+                        ..Default::default()
+                    })
+                })
+                .await
+            }
+            "/lnrpc.Lightning/AddInvoice" => {
+                unary(request, |invoice: lnrpc::Invoice| async move {
+                    Ok(node.create_invoice(invoice_params(invoice)).await?)
+                })
+                .await
+            }
+            "/lnrpc.Lightning/DecodePayReq" => {
+                unary(request, |request: lnrpc::PayReqString| async move {
+                    Ok(node.decode_payment_request(request.pay_req).await?)
+                })
+                .await
+            }
+            "/lnrpc.Lightning/QueryRoutes" => {
+                unary(request, |request: lnrpc::QueryRoutesRequest| async move {
+                    let invoice = lnrpc::PayReq {
+                        destination: request.pub_key,
+                        num_msat: request.amt_msat,
+                        ..Default::default()
+                    };
+                    let fee_msat = node
+                        .estimate_route_fee(&invoice)
+                        .await?
+                        .ok_or_else(|| Status::not_found("unable to find a path to destination"))?;
+
+                    Ok(lnrpc::QueryRoutesResponse {
+                        routes: vec![lnrpc::Route {
+                            total_fees_msat: fee_msat,
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    })
+                })
+                .await
+            }
+            "/lnrpc.Lightning/ListInvoices" => {
+                unary(request, |request: lnrpc::ListInvoiceRequest| async move {
+                    Ok(node.list_invoices(request).await?)
+                })
+                .await
+            }
+            "/lnrpc.Lightning/ListPayments" => {
+                unary(request, |request: lnrpc::ListPaymentsRequest| async move {
+                    Ok(node.list_payments(request).await?)
+                })
+                .await
+            }
+            "/lnrpc.Lightning/SubscribeInvoices" => {
+                server_streaming(request, |request: lnrpc::InvoiceSubscription| async move {
+                    let stream = node
+                        .subscribe_invoices(request.add_index, request.settle_index)
+                        .await?;
+                    Ok(into_status_stream(stream))
+                })
+                .await
+            }
+            "/routerrpc.Router/SendPaymentV2" => {
+                server_streaming(
+                    request,
+                    |request: routerrpc::SendPaymentRequest| async move {
+                        let stream = if request.payment_request.is_empty() {
+                            node.send_keysend(
+                                keysend_params(request.clone()).ok_or_else(|| {
+                                    Status::invalid_argument("missing keysend preimage record")
+                                })?,
+                                request.fee_limit_msat,
+                            )
+                            .await?
+                        } else {
+                            node.send_payment(
+                                request.payment_request,
+                                request.amt_msat,
+                                request.fee_limit_msat,
+                            )
+                            .await?
+                        };
+                        Ok(into_status_stream(stream))
+                    },
+                )
+                .await
+            }
+            "/routerrpc.Router/TrackPaymentV2" => {
+                server_streaming(
+                    request,
+                    |request: routerrpc::TrackPaymentRequest| async move {
+                        let stream = node
+                            .track_payment(hex::encode(request.payment_hash))
+                            .await?;
+                        Ok(into_status_stream(stream))
+                    },
+                )
+                .await
+            }
+            "/invoicesrpc.Invoices/AddHoldInvoice" => {
+                unary(
+                    request,
+                    |request: invoicesrpc::AddHoldInvoiceRequest| async move {
+                        let hash = request.hash.clone();
+                        let invoice = lnrpc::Invoice {
+                            memo: request.memo,
+                            value_msat: request.value_msat,
+                            description_hash: request.description_hash,
+                            expiry: request.expiry,
+                            fallback_addr: request.fallback_addr,
+                            cltv_expiry: request.cltv_expiry,
+                            private: request.private,
+                            ..Default::default()
+                        };
+                        let response = node
+                            .create_hold_invoice(invoice_params(invoice), hash)
+                            .await?;
+
+                        Ok(invoicesrpc::AddHoldInvoiceResp {
+                            payment_request: response.payment_request,
+                            add_index: response.add_index,
+                            payment_addr: response.payment_addr,
+                        })
+                    },
+                )
+                .await
+            }
+            "/invoicesrpc.Invoices/SettleInvoice" => {
+                unary(
+                    request,
+                    |request: invoicesrpc::SettleInvoiceMsg| async move {
+                        node.settle_invoice(request.preimage).await?;
+                        Ok(invoicesrpc::SettleInvoiceResp {})
+                    },
+                )
+                .await
+            }
+            "/invoicesrpc.Invoices/CancelInvoice" => {
+                unary(
+                    request,
+                    |request: invoicesrpc::CancelInvoiceMsg| async move {
+                        node.cancel_invoice(request.payment_hash).await?;
+                        Ok(invoicesrpc::CancelInvoiceResp {})
+                    },
+                )
+                .await
+            }
+            path => Status::unimplemented(format!("{} is not mocked", path)).to_http(),
+        }
+    }
+}
+
+/// Decode a single request message, run `handler` on it and encode its answer.
+async fn unary<Req, Resp, F, Fut>(
+    request: http::Request<Body>,
+    handler: F,
+) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+    F: FnOnce(Req) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Resp, MockError>> + Send + 'static,
+{
+    let mut handler = Some(handler);
+    let service = tower::service_fn(move |request: tonic::Request<Req>| {
+        let handler = handler.take().expect("unary handler called twice");
+        async move {
+            handler(request.into_inner())
+                .await
+                .map(tonic::Response::new)
+                .map_err(Status::from)
+        }
+    });
+
+    Grpc::new(ProstCodec::<Resp, Req>::default())
+        .unary(service, request)
+        .await
+}
+
+/// Like `unary`, but `handler` answers with a stream of messages.
+async fn server_streaming<Req, Resp, F, Fut>(
+    request: http::Request<Body>,
+    handler: F,
+) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+    F: FnOnce(Req) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<BoxStream<Resp>, MockError>> + Send + 'static,
+{
+    let mut handler = Some(handler);
+    let service = tower::service_fn(move |request: tonic::Request<Req>| {
+        let handler = handler.take().expect("streaming handler called twice");
+        async move {
+            handler(request.into_inner())
+                .await
+                .map(tonic::Response::new)
+                .map_err(Status::from)
+        }
+    });
+
+    Grpc::new(ProstCodec::<Resp, Req>::default())
+        .server_streaming(service, request)
+        .await
+}
+
+fn into_status_stream<T: Send + 'static>(
+    stream: impl Stream<Item = Result<T, LndError>> + Send + 'static,
+) -> BoxStream<T> {
+    stream.map_err(|e| Status::from(MockError(e))).boxed()
+}
+
+/// Rebuild the params THOrs sent from the invoice LND received.
+fn invoice_params(invoice: lnrpc::Invoice) -> InvoiceParams {
+    InvoiceParams {
+        amount_msat: invoice.value_msat,
+        memo: Some(invoice.memo).filter(|memo| !memo.is_empty()),
+        description_hash: Some(invoice.description_hash).filter(|hash| !hash.is_empty()),
+        expiry_secs: invoice.expiry,
+        private: invoice.private,
+        cltv_expiry: invoice.cltv_expiry,
+        fallback_addr: Some(invoice.fallback_addr).filter(|addr| !addr.is_empty()),
+    }
+}
+
+/// Recover a keysend payment from its `SendPaymentV2` request, or `None` if
+/// it does not carry a keysend preimage.
+fn keysend_params(mut request: routerrpc::SendPaymentRequest) -> Option<KeysendParams> {
+    let preimage = request
+        .dest_custom_records
+        .remove(&thors::server::lnd::KEYSEND_RECORD_TYPE)?;
+
+    Some(KeysendParams {
+        destination: hex::encode(request.dest),
+        amount_msat: request.amt_msat,
+        preimage,
+        custom_records: request.dest_custom_records,
+    })
+}
+
+/// A `MockNode` failure, sent to the client as the status LND would answer.
+struct MockError(LndError);
+
+impl From<LndError> for MockError {
+    fn from(e: LndError) -> Self {
+        Self(e)
+    }
+}
+
+impl From<Status> for MockError {
+    fn from(status: Status) -> Self {
+        Self(LndError::Rpc(status))
+    }
+}
+
+impl From<MockError> for Status {
+    fn from(e: MockError) -> Self {
+        match e.0 {
+            LndError::Rpc(status) => status,
+            e => Status::internal(e.to_string()),
+        }
+    }
+}
+
+macro_rules! grpc_service {
+    ($name:ident, $service:literal) => {
+        #[derive(Clone)]
+        struct $name(MockLnd);
+
+        impl NamedService for $name {
+            const NAME: &'static str = $service;
+        }
+
+        impl Service<http::Request<Body>> for $name {
+            type Response = http::Response<BoxBody>;
+            type Error = Infallible;
+            type Future = BoxFuture<Self::Response, Self::Error>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+                let lnd = self.0.clone();
+                Box::pin(async move { Ok(lnd.handle(request).await) })
+            }
+        }
+    };
+}
+
+grpc_service!(Lightning, "lnrpc.Lightning");
+grpc_service!(Router, "routerrpc.Router");
+grpc_service!(Invoices, "invoicesrpc.Invoices");
@@ -0,0 +1,1120 @@
+//! THOrs against mock LND nodes served over gRPC.
+//!
+//! The end-to-end tests also need a Postgres database they may migrate and
+//! write to, given as `TEST_DATABASE_URL`; they are skipped when it is unset.
+
+mod common;
+
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::body::{to_bytes, Body};
+use axum::http::{Method, Request, StatusCode};
+use axum::Router;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use leptos::prelude::LeptosOptions;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tonic_lnd::lnrpc;
+use tonic_lnd::lnrpc::payment::PaymentStatus;
+use tonic_lnd::tonic;
+use tower::ServiceExt;
+
+use common::{MockLndServer, CERT_PATH, MACAROON_PATH};
+use thors::dto::{CreateInvoiceRequest, FeeLimit, InvoiceDefaults, PayInvoiceRequest};
+use thors::initialize::{
+    build_router, run_migrations, setup_nodes, spawn_background_tasks, Config,
+};
+use thors::models::{
+    IdempotentEndpoint, NewTransaction, NodeRole, Transaction, TransitionSource, TxStatus, TxType,
+    UpdateTransaction,
+};
+use thors::server::lnd::{
+    InvoiceParams, InvoiceStream, KeysendParams, LndConnection, LndError, PaymentStream,
+};
+use thors::server::mock::{MockNetwork, MockNode};
+use thors::server::{
+    background, db, idempotency, AppState, DbPool, InvoiceEvent, LightningBackend,
+    LightningClients, NodeRegistry,
+};
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A mock node serving on its own port, and the client THOrs would use for it.
+async fn connected_node(network: &MockNetwork, alias: &str) -> (MockLndServer, LightningClients) {
+    let server = MockLndServer::start(network.node(alias)).await;
+    let connection = LndConnection {
+        endpoint: server.endpoint(),
+        cert_path: CERT_PATH.to_string(),
+        macaroon_path: MACAROON_PATH.to_string(),
+        pool_size: 2,
+    };
+    let clients = LightningClients::connect(connection, CALL_TIMEOUT)
+        .await
+        .unwrap();
+
+    (server, clients)
+}
+
+fn invoice_params(amount_sats: i64) -> InvoiceParams {
+    let request = CreateInvoiceRequest {
+        amount_sats: Some(amount_sats),
+        ..Default::default()
+    };
+    InvoiceParams::resolve(request, &InvoiceDefaults::default()).unwrap()
+}
+
+#[tokio::test]
+async fn lnd_clients_pay_through_mock_server() {
+    let network = MockNetwork::new();
+    let (alice_server, alice) = connected_node(&network, "alice").await;
+    let (_bob_server, bob) = connected_node(&network, "bob").await;
+
+    assert_eq!(
+        alice.get_node_pubkey().await.unwrap(),
+        alice_server.node.pubkey()
+    );
+
+    let mut invoices = alice.subscribe_invoices(0, 0).await.unwrap();
+    let invoice = alice.create_invoice(invoice_params(1_000)).await.unwrap();
+
+    let decoded = bob
+        .decode_payment_request(invoice.payment_request.clone())
+        .await
+        .unwrap();
+    assert_eq!(decoded.destination, alice_server.node.pubkey());
+    assert_eq!(decoded.num_satoshis, 1_000);
+    assert!(bob.estimate_route_fee(&decoded).await.unwrap().is_some());
+
+    let updates: Vec<_> = bob
+        .send_payment(invoice.payment_request, 0, 10_000)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    let last = updates.last().unwrap().as_ref().unwrap();
+    assert_eq!(last.status, PaymentStatus::Succeeded as i32);
+
+    // The subscription sees the invoice open first, then settled
+    let opened = invoices.next().await.unwrap().unwrap();
+    let settled = invoices.next().await.unwrap().unwrap();
+    assert_eq!(opened.r_hash, invoice.r_hash);
+    assert_eq!(settled.r_hash, invoice.r_hash);
+    assert!(settled.settle_index > 0);
+
+    let listed = alice
+        .list_invoices(lnrpc::ListInvoiceRequest {
+            num_max_invoices: 10,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(listed.invoices.len(), 1);
+
+    let payments = bob
+        .list_payments(lnrpc::ListPaymentsRequest {
+            include_incomplete: true,
+            max_payments: 10,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(payments.payments.len(), 1);
+    assert_eq!(payments.last_index_offset, 1);
+}
+
+#[tokio::test]
+async fn mock_server_rejects_unknown_macaroon() {
+    let network = MockNetwork::new();
+    let server = MockLndServer::start(network.node("alice")).await;
+    let connection = LndConnection {
+        endpoint: server.endpoint(),
+        cert_path: CERT_PATH.to_string(),
+        // Any file other than the fixture macaroon is a wrong macaroon
+        macaroon_path: CERT_PATH.to_string(),
+        pool_size: 1,
+    };
+    let clients = LightningClients::connect(connection, CALL_TIMEOUT)
+        .await
+        .unwrap();
+
+    match clients.get_node_pubkey().await {
+        Err(LndError::Rpc(status)) => assert_eq!(status.code(), tonic::Code::Unauthenticated),
+        other => panic!("expected Unauthenticated, got {:?}", other.map(|_| ())),
+    }
+}
+
+/// Send a JSON request through the router and decode its JSON answer.
+async fn call(
+    router: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(match body {
+            Some(body) => Body::from(body.to_string()),
+            None => Body::empty(),
+        })
+        .unwrap();
+
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let value = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes).unwrap()
+    };
+
+    (status, value)
+}
+
+/// POST `body` under an `Idempotency-Key`; also tells whether the answer was
+/// replayed from an earlier request.
+async fn post_idempotent(
+    router: &Router,
+    uri: &str,
+    key: &str,
+    body: Value,
+) -> (StatusCode, bool, Value) {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .header("idempotency-key", key)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let replayed = response.headers().contains_key("idempotent-replayed");
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    (status, replayed, serde_json::from_slice(&bytes).unwrap())
+}
+
+/// GET a non-JSON response as text.
+async fn download(router: &Router, uri: &str) -> (StatusCode, String) {
+    let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+async fn balance(router: &Router, node_id: &str) -> Value {
+    let uri = format!("/api/balance?node_id={node_id}");
+    let (status, balance) = call(router, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    balance
+}
+
+/// THOrs wired to its own receive node (Alice) and send node (Bob).
+struct TestApp {
+    router: Router,
+    pool: DbPool,
+    events_tx: broadcast::Sender<InvoiceEvent>,
+    nodes: NodeRegistry,
+    alice: MockLndServer,
+    bob: MockLndServer,
+    started: DateTime<Utc>,
+    /// Unique per app, for keys and hashes that must not clash across runs.
+    run: i64,
+}
+
+static APPS_STARTED: AtomicI64 = AtomicI64::new(0);
+
+/// Held while migrating; tests run in parallel.
+static MIGRATIONS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Start THOrs against two fresh mock nodes, or `None` when
+/// `TEST_DATABASE_URL` is unset.
+async fn setup() -> Option<TestApp> {
+    let Ok(database_url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set, skipping");
+        return None;
+    };
+
+    // Mock pubkeys and payment hashes follow from the node alias, so fresh
+    // aliases keep other tests' and earlier runs' rows out of the way
+    let started = Utc::now();
+    let run = started.timestamp_micros() * 1000 + APPS_STARTED.fetch_add(1, Ordering::Relaxed);
+    let (alice_name, bob_name) = (format!("alice-{run}"), format!("bob-{run}"));
+
+    let network = MockNetwork::new();
+    let alice = MockLndServer::start(network.node(&alice_name)).await;
+    let bob = MockLndServer::start(network.node(&bob_name)).await;
+
+    let config = Config {
+        database_url,
+        run_migrations: true,
+        nodes: vec![
+            alice.node_config(&alice_name, NodeRole::Receive),
+            bob.node_config(&bob_name, NodeRole::Send),
+        ],
+        lnd_pool_size: 2,
+        lnd_call_timeout: CALL_TIMEOUT,
+        payment_sync_interval: Duration::from_secs(60),
+        payment_sync_page_size: 100,
+        invoice_expiry_sweep_interval: Duration::from_secs(60),
+        idempotency_key_ttl: Duration::from_secs(86_400),
+        fee_limit: FeeLimit::Percent(5.0),
+        invoice_defaults: InvoiceDefaults::default(),
+        invoice_sync_page_size: 100,
+        invoice_sync_background: false,
+        beancount_accounts: Default::default(),
+    };
+
+    {
+        let _guard = MIGRATIONS.lock().await;
+        run_migrations(&config.database_url).unwrap();
+    }
+    let pool = db::create_pool(&config.database_url);
+    let nodes = setup_nodes(&config, &pool).await.unwrap();
+    let (events_tx, _) = broadcast::channel::<InvoiceEvent>(100);
+    spawn_background_tasks(
+        &nodes,
+        pool.clone(),
+        events_tx.clone(),
+        config.payment_sync_interval,
+        config.payment_sync_page_size,
+        config.invoice_expiry_sweep_interval,
+        config.idempotency_key_ttl,
+    );
+
+    let app_state = AppState {
+        db_pool: pool.clone(),
+        nodes: nodes.clone(),
+        broadcast_tx: events_tx.clone(),
+        fee_limit: config.fee_limit,
+        invoice_defaults: config.invoice_defaults,
+        beancount_accounts: config.beancount_accounts,
+    };
+    let router = build_router(
+        app_state,
+        LeptosOptions::builder().output_name("thors").build(),
+    );
+
+    Some(TestApp {
+        router,
+        pool,
+        events_tx,
+        nodes,
+        alice,
+        bob,
+        started,
+        run,
+    })
+}
+
+/// Wait until the invoice subscription records the invoice for
+/// `payment_hash` as settled.
+async fn wait_for_settlement(
+    events: &mut broadcast::Receiver<InvoiceEvent>,
+    payment_hash: &str,
+) -> Transaction {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Ok(InvoiceEvent::InvoiceSettled { tx }) = events.recv().await {
+                if tx.payment_hash == payment_hash {
+                    return tx;
+                }
+            }
+        }
+    })
+    .await
+    .expect("invoice was not settled")
+}
+
+/// Have Alice invoice `amount_sats` and Bob pay it through the API, once the
+/// invoice is settled. Returns the payment.
+async fn pay_invoice(app: &TestApp, amount_sats: i64, description: &str) -> Value {
+    let mut events = app.events_tx.subscribe();
+    let (status, invoice) = call(
+        &app.router,
+        Method::POST,
+        "/api/invoice",
+        Some(json!({ "amount_sats": amount_sats, "description": description })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{invoice}");
+
+    let (status, payment) = call(
+        &app.router,
+        Method::POST,
+        "/api/payment",
+        Some(json!({ "payment_request": invoice["payment_request"] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{payment}");
+    wait_for_settlement(&mut events, payment["payment_hash"].as_str().unwrap()).await;
+
+    payment
+}
+
+/// A mock node whose invoice listing fails after `pages` pages, as if the
+/// process stopped partway through a sync.
+struct InterruptedListing {
+    node: MockNode,
+    pages: AtomicUsize,
+}
+
+#[async_trait]
+impl LightningBackend for InterruptedListing {
+    async fn get_node_pubkey(&self) -> Result<String, LndError> {
+        self.node.get_node_pubkey().await
+    }
+
+    async fn create_invoice(
+        &self,
+        params: InvoiceParams,
+    ) -> Result<lnrpc::AddInvoiceResponse, LndError> {
+        self.node.create_invoice(params).await
+    }
+
+    async fn create_hold_invoice(
+        &self,
+        params: InvoiceParams,
+        payment_hash: Vec<u8>,
+    ) -> Result<lnrpc::AddInvoiceResponse, LndError> {
+        self.node.create_hold_invoice(params, payment_hash).await
+    }
+
+    async fn settle_invoice(&self, preimage: Vec<u8>) -> Result<(), LndError> {
+        self.node.settle_invoice(preimage).await
+    }
+
+    async fn cancel_invoice(&self, payment_hash: Vec<u8>) -> Result<(), LndError> {
+        self.node.cancel_invoice(payment_hash).await
+    }
+
+    async fn decode_payment_request(
+        &self,
+        payment_request: String,
+    ) -> Result<lnrpc::PayReq, LndError> {
+        self.node.decode_payment_request(payment_request).await
+    }
+
+    async fn estimate_route_fee(&self, invoice: &lnrpc::PayReq) -> Result<Option<i64>, LndError> {
+        self.node.estimate_route_fee(invoice).await
+    }
+
+    async fn send_payment(
+        &self,
+        payment_request: String,
+        amount_msat: i64,
+        fee_limit_msat: i64,
+    ) -> Result<PaymentStream, LndError> {
+        self.node
+            .send_payment(payment_request, amount_msat, fee_limit_msat)
+            .await
+    }
+
+    async fn track_payment(&self, payment_hash: String) -> Result<PaymentStream, LndError> {
+        self.node.track_payment(payment_hash).await
+    }
+
+    async fn send_keysend(
+        &self,
+        params: KeysendParams,
+        fee_limit_msat: i64,
+    ) -> Result<PaymentStream, LndError> {
+        self.node.send_keysend(params, fee_limit_msat).await
+    }
+
+    async fn list_invoices(
+        &self,
+        request: lnrpc::ListInvoiceRequest,
+    ) -> Result<lnrpc::ListInvoiceResponse, LndError> {
+        let left = self.pages.load(Ordering::Relaxed);
+        if left == 0 {
+            return Err(LndError::Connection("process stopped".into()));
+        }
+        self.pages.store(left - 1, Ordering::Relaxed);
+        self.node.list_invoices(request).await
+    }
+
+    async fn list_payments(
+        &self,
+        request: lnrpc::ListPaymentsRequest,
+    ) -> Result<lnrpc::ListPaymentsResponse, LndError> {
+        self.node.list_payments(request).await
+    }
+
+    async fn subscribe_invoices(
+        &self,
+        add_index: u64,
+        settle_index: u64,
+    ) -> Result<InvoiceStream, LndError> {
+        self.node.subscribe_invoices(add_index, settle_index).await
+    }
+}
+
+#[tokio::test]
+async fn invoices_are_created_idempotently() {
+    let Some(app) = setup().await else {
+        return;
+    };
+    let router = &app.router;
+
+    let (status, nodes) = call(router, Method::GET, "/api/status", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(nodes.as_array().unwrap().len(), 2);
+
+    let invoice_request = json!({ "amount_sats": 1500, "description": "idempotent" });
+    let invoice_key = format!("invoice-{}", app.run);
+    let (status, replayed, invoice) = post_idempotent(
+        router,
+        "/api/invoice",
+        &invoice_key,
+        invoice_request.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(!replayed);
+
+    // A retry gets the same invoice back instead of a second one
+    let (status, replayed, retried) =
+        post_idempotent(router, "/api/invoice", &invoice_key, invoice_request).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(replayed);
+    assert_eq!(retried, invoice);
+
+    // The key cannot be reused for another request
+    let (status, _, _) = post_idempotent(
+        router,
+        "/api/invoice",
+        &invoice_key,
+        json!({ "amount_sats": 2000 }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Amounts that do not fit in msat are refused rather than wrapped
+    let (status, _) = call(
+        router,
+        Method::POST,
+        "/api/invoice",
+        Some(json!({ "amount_sats": i64::MAX })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Amounts can be given in msat instead, but not both ways at once
+    let (status, msat_invoice) = call(
+        router,
+        Method::POST,
+        "/api/invoice",
+        Some(json!({ "amount_msat": 1_000_500 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(msat_invoice["amount_msat"], 1_000_500);
+    assert_eq!(msat_invoice["amount_sats"], 1000);
+    let (status, _) = call(
+        router,
+        Method::POST,
+        "/api/invoice",
+        Some(json!({ "amount_sats": 1, "amount_msat": 1000 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn invoice_paid_end_to_end() {
+    let Some(app) = setup().await else {
+        return;
+    };
+    let (router, pool) = (&app.router, &app.pool);
+    let mut events = app.events_tx.subscribe();
+
+    let (status, invoice) = call(
+        router,
+        Method::POST,
+        "/api/invoice",
+        Some(json!({ "amount_sats": 1500, "description": "paid" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let payment_hash = invoice["payment_hash"].as_str().unwrap().to_string();
+
+    let payment_request = json!({ "payment_request": invoice["payment_request"] });
+    let payment_key = format!("payment-{}", app.run);
+    let (status, _, payment) = post_idempotent(
+        router,
+        "/api/payment",
+        &payment_key,
+        payment_request.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{payment}");
+    assert_eq!(payment["payment_hash"], payment_hash.as_str());
+    assert_eq!(payment["amount_sats"], 1500);
+    assert_eq!(payment["amount_msat"], 1_500_000);
+
+    // Retrying with the key returns the payment; without it, it is a duplicate
+    let (status, replayed, retried) = post_idempotent(
+        router,
+        "/api/payment",
+        &payment_key,
+        payment_request.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(replayed);
+    assert_eq!(retried, payment);
+    let (status, _) = call(router, Method::POST, "/api/payment", Some(payment_request)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // A key bound to a sent payment survives a lost response and a restart,
+    // and retries answer from the payment instead of paying again
+    let lost_key = format!("lost-{}", app.run);
+    let lost_body = json!({ "payment_request": invoice["payment_request"] });
+    let lost_request: PayInvoiceRequest = serde_json::from_value(lost_body.clone()).unwrap();
+    let request_hash = idempotency::fingerprint(&lost_request);
+    let endpoint = IdempotentEndpoint::Payment;
+    db::claim_idempotency_key(pool, endpoint, &lost_key, &request_hash)
+        .await
+        .unwrap();
+    db::bind_idempotency_key(pool, endpoint, &lost_key, &payment_hash)
+        .await
+        .unwrap();
+    db::release_unfinished_idempotency_keys(pool).await.unwrap();
+    let (status, replayed, retried) =
+        post_idempotent(router, "/api/payment", &lost_key, lost_body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(replayed);
+    assert_eq!(retried, payment);
+
+    // The invoice subscription records the settlement
+    let settled = wait_for_settlement(&mut events, &payment_hash).await;
+    assert!(settled.settled_at.is_some());
+
+    let (status, tx) = call(
+        router,
+        Method::GET,
+        &format!("/api/invoice/{payment_hash}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(tx["status"], "succeeded");
+    assert_eq!(tx["node_id"], app.alice.node.pubkey());
+
+    // The mock charges 1 sat plus 1000 ppm, so 2.5 sats here
+    assert_eq!(payment["fee_msat"], 2_500);
+    assert_eq!(payment["fee_sats"], 2);
+
+    let received = balance(router, app.alice.node.pubkey()).await;
+    assert_eq!(received["received_sats"], 1500);
+    assert_eq!(received["total_balance_msat"], 1_500_000);
+
+    let paid = balance(router, app.bob.node.pubkey()).await;
+    assert_eq!(paid["paid_sats"], 1500);
+    assert_eq!(paid["fee_msat"], 2_500);
+    assert_eq!(paid["total_balance_msat"], -1_502_500);
+
+    // Marking the settled invoice settled again changes and posts nothing
+    let replayed = db::update_transaction_status(
+        pool,
+        TxType::Invoice,
+        &payment_hash,
+        UpdateTransaction::new(Some(TxStatus::Succeeded), None, None, None),
+        TransitionSource::InvoiceSubscription,
+    )
+    .await
+    .unwrap();
+    assert!(replayed.is_none());
+    assert_eq!(
+        balance(router, app.alice.node.pubkey()).await["total_balance"],
+        1500
+    );
+
+    // A settled invoice never goes back to pending
+    let rolled_back = db::update_transaction_status(
+        pool,
+        TxType::Invoice,
+        &payment_hash,
+        UpdateTransaction::new(Some(TxStatus::Pending), None, None, None),
+        TransitionSource::InvoiceSubscription,
+    )
+    .await
+    .unwrap();
+    assert!(rolled_back.is_none());
+
+    let (status, timeline) = call(
+        router,
+        Method::GET,
+        &format!("/api/transactions/{payment_hash}/timeline"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let steps: Vec<_> = timeline
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|step| {
+            (
+                step["tx_type"].as_str().unwrap(),
+                step["from_status"].as_str(),
+                step["to_status"].as_str().unwrap(),
+                step["source"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert!(steps.contains(&("payment", None, "pending", "api")));
+    assert!(steps.contains(&("payment", Some("pending"), "succeeded", "payment_update")));
+    // The subscription may first see the invoice open or already settled
+    let invoice_steps: Vec<_> = steps.iter().filter(|step| step.0 == "invoice").collect();
+    let last = invoice_steps.last().unwrap();
+    assert_eq!((last.2, last.3), ("succeeded", "invoice_subscription"));
+
+    let (status, check) = call(router, Method::GET, "/api/ledger/check", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(check["ok"], true, "{check}");
+}
+
+#[tokio::test]
+async fn transactions_are_listed_and_exported() {
+    let Some(app) = setup().await else {
+        return;
+    };
+    let router = &app.router;
+    let payment = pay_invoice(&app, 1500, "end to end").await;
+    let payment_hash = payment["payment_hash"].as_str().unwrap();
+
+    let uri = format!(
+        "/api/transactions?node_id={}&tx_type=payment&status=succeeded",
+        app.bob.node.pubkey()
+    );
+    let (status, page) = call(router, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["total_count"], 1);
+    assert_eq!(page["transactions"][0]["payment_hash"], payment_hash);
+    assert!(page["next_cursor"].is_null());
+
+    let uri = format!(
+        "/api/transactions?node_id={}&min_amount_msat=1500001",
+        app.alice.node.pubkey()
+    );
+    let (_, page) = call(router, Method::GET, &uri, None).await;
+    assert_eq!(page["total_count"], 0);
+
+    // The invoice and its payment, one per page
+    let since = app
+        .started
+        .to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+    let uri = format!("/api/transactions?search=END%20TO&created_from={since}&limit=1");
+    let (status, first) = call(router, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::OK, "{first}");
+    assert_eq!(first["total_count"], 2);
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let (_, second) = call(router, Method::GET, &format!("{uri}&cursor={cursor}"), None).await;
+    assert_eq!(second["transactions"].as_array().unwrap().len(), 1);
+    assert_ne!(
+        second["transactions"][0]["id"],
+        first["transactions"][0]["id"]
+    );
+    assert!(second["next_cursor"].is_null());
+
+    let (status, _) = call(router, Method::GET, "/api/transactions?cursor=zz", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Exports take the listing filters and stream every matching row
+    let filters = format!("search=END%20TO&created_from={since}");
+    let (status, csv) = download(
+        router,
+        &format!("/api/transactions/export?format=csv&{filters}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let lines: Vec<_> = csv.lines().collect();
+    assert!(lines[0].starts_with("id,tx_type,status,origin,payment_hash"));
+    assert_eq!(lines.len(), 3, "{csv}");
+
+    let (_, jsonl) = download(
+        router,
+        &format!("/api/transactions/export?format=jsonl&{filters}"),
+    )
+    .await;
+    let rows: Vec<Value> = jsonl
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().all(|row| row["payment_hash"] == payment_hash));
+
+    let (_, beancount) = download(
+        router,
+        &format!(
+            "/api/transactions/export?format=beancount&node_id={}",
+            app.bob.node.pubkey()
+        ),
+    )
+    .await;
+    assert!(
+        beancount.starts_with("1970-01-01 open Assets:Lightning BTC\n"),
+        "{beancount}"
+    );
+    for account in [
+        "Income:Lightning",
+        "Expenses:Lightning:Payments",
+        "Expenses:Lightning:Fees",
+    ] {
+        assert!(beancount.contains(&format!("1970-01-01 open {account} BTC\n")));
+    }
+    assert!(
+        beancount.contains("* \"Payment sent\" \"end to end\""),
+        "{beancount}"
+    );
+    assert!(beancount.contains("  Expenses:Lightning:Payments  0.00001500 BTC\n"));
+    assert!(beancount.contains("  Expenses:Lightning:Fees  0.000000025 BTC\n"));
+    assert!(beancount.contains("  Assets:Lightning  -0.000015025 BTC\n"));
+
+    let (status, _) = download(router, "/api/transactions/export?format=xlsx").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn payments_made_outside_thors_are_imported() {
+    let Some(app) = setup().await else {
+        return;
+    };
+    let (router, alice, bob) = (&app.router, &app.alice, &app.bob);
+    let payment = pay_invoice(&app, 1500, "imported").await;
+
+    // Payments Bob makes without THOrs are imported as external ones
+    let outside = alice
+        .node
+        .create_invoice(invoice_params(700))
+        .await
+        .unwrap();
+    let updates: Vec<_> = bob
+        .node
+        .send_payment(outside.payment_request, 0, 10_000)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(updates.len(), 2);
+    let mut preimage = app.run.to_be_bytes().to_vec();
+    preimage.resize(32, 0);
+    let unreachable = KeysendParams {
+        destination: format!("02{}", "00".repeat(32)),
+        amount_msat: 5_000,
+        preimage,
+        custom_records: Default::default(),
+    };
+    let _: Vec<_> = bob
+        .node
+        .send_keysend(unreachable, 10_000)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+
+    // One payment per page, and a second run finds nothing new
+    for _ in 0..2 {
+        background::sync_payments_from_lnd(
+            &bob.node,
+            &app.pool,
+            &app.events_tx,
+            bob.node.pubkey(),
+            1,
+        )
+        .await;
+    }
+
+    let uri = format!(
+        "/api/transactions?node_id={}&origin=external",
+        bob.node.pubkey()
+    );
+    let (status, page) = call(router, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["total_count"], 2, "{page}");
+    let imported = page["transactions"].as_array().unwrap();
+    let sent = imported
+        .iter()
+        .find(|tx| tx["payment_hash"] == hex::encode(&outside.r_hash))
+        .unwrap();
+    assert_eq!(sent["status"], "succeeded");
+    assert_eq!(sent["amount_msat"], 700_000);
+    assert_eq!(sent["fee_msat"], 1_700);
+    assert!(sent["preimage"].is_string());
+    assert_eq!(sent["destination"], alice.node.pubkey());
+    let failed = imported.iter().find(|tx| tx["status"] == "failed").unwrap();
+    assert_eq!(failed["failure_code"], "no_route");
+    assert_eq!(failed["failure_reason"], "no route to destination");
+    assert!(failed["fee_msat"].is_null());
+
+    // THOrs' own payment keeps its origin
+    let (_, own) = call(
+        router,
+        Method::GET,
+        &format!("/api/payment/{}", payment["payment_hash"].as_str().unwrap()),
+        None,
+    )
+    .await;
+    assert_eq!(own["origin"], "thors");
+
+    let paid = balance(router, bob.node.pubkey()).await;
+    assert_eq!(paid["paid_sats"], 2200);
+    let (_, check) = call(router, Method::GET, "/api/ledger/check", None).await;
+    assert_eq!(check["ok"], true, "{check}");
+}
+
+#[tokio::test]
+async fn overdue_invoices_are_swept() {
+    let Some(app) = setup().await else {
+        return;
+    };
+    let (router, pool, alice) = (&app.router, &app.pool, &app.alice);
+    let overdue_since = Some(app.started - chrono::Duration::hours(1));
+
+    // An invoice left pending past its expiry is swept to expired
+    let overdue_hash = hex::encode(app.run.to_be_bytes()).repeat(4);
+    let overdue = NewTransaction::new(
+        TxType::Invoice,
+        overdue_hash.clone(),
+        format!("lnmock1{overdue_hash}"),
+        1_000,
+        None,
+        TxStatus::Pending,
+        overdue_since,
+        alice.node.pubkey().to_string(),
+    );
+    db::create_transaction(pool, overdue, TransitionSource::Api)
+        .await
+        .unwrap();
+    // One still open on the node is cancelled there too
+    let open_hash = hex::encode((app.run + 1).to_be_bytes()).repeat(4);
+    let open = NewTransaction::new(
+        TxType::Invoice,
+        open_hash.clone(),
+        format!("lnmock1{open_hash}"),
+        1_000,
+        None,
+        TxStatus::Pending,
+        overdue_since,
+        alice.node.pubkey().to_string(),
+    );
+    db::create_transaction(pool, open, TransitionSource::Api)
+        .await
+        .unwrap();
+    alice
+        .node
+        .create_hold_invoice(invoice_params(1), hex::decode(&open_hash).unwrap())
+        .await
+        .unwrap();
+
+    let mut events = app.events_tx.subscribe();
+    background::expire_overdue_invoices(&app.nodes, pool, &app.events_tx).await;
+    let expired = loop {
+        if let InvoiceEvent::InvoiceExpired { tx } = events.try_recv().unwrap() {
+            if tx.payment_hash == overdue_hash {
+                break tx;
+            }
+        }
+    };
+    assert_eq!(expired.status, TxStatus::Expired);
+    let (_, timeline) = call(
+        router,
+        Method::GET,
+        &format!("/api/transactions/{overdue_hash}/timeline"),
+        None,
+    )
+    .await;
+    assert_eq!(timeline[1]["to_status"], "expired");
+    assert_eq!(timeline[1]["source"], "expiry_sweep");
+
+    let listed = alice
+        .node
+        .list_invoices(lnrpc::ListInvoiceRequest {
+            num_max_invoices: 100,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let cancelled = listed
+        .invoices
+        .iter()
+        .find(|invoice| hex::encode(&invoice.r_hash) == open_hash)
+        .unwrap();
+    assert_eq!(
+        cancelled.state,
+        lnrpc::invoice::InvoiceState::Canceled as i32
+    );
+    let (_, open) = call(
+        router,
+        Method::GET,
+        &format!("/api/invoice/{open_hash}"),
+        None,
+    )
+    .await;
+    assert_eq!(open["status"], "expired");
+
+    // A settlement LND reports after the sweep is still recorded
+    let late = NewTransaction::new(
+        TxType::Invoice,
+        overdue_hash.clone(),
+        format!("lnmock1{overdue_hash}"),
+        1_000,
+        None,
+        TxStatus::Succeeded,
+        None,
+        alice.node.pubkey().to_string(),
+    );
+    let settled = db::upsert_transaction(pool, late, TransitionSource::InvoiceSubscription)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(settled.status, TxStatus::Succeeded);
+    assert!(settled.settled_at.is_some());
+    assert_eq!(
+        balance(router, alice.node.pubkey()).await["received_msat"],
+        1_000
+    );
+
+    let (_, check) = call(router, Method::GET, "/api/ledger/check", None).await;
+    assert_eq!(check["ok"], true, "{check}");
+}
+
+#[tokio::test]
+async fn invoice_sync_resumes_from_its_own_cursor() {
+    let Some(app) = setup().await else {
+        return;
+    };
+    let pool = &app.pool;
+    let network = MockNetwork::new();
+    let carol = network.node(&format!("carol-{}", app.run));
+    let node_id = carol.pubkey();
+
+    let mut older = Vec::new();
+    for amount_sats in 1..=5 {
+        older.push(
+            carol
+                .create_invoice(invoice_params(amount_sats))
+                .await
+                .unwrap(),
+        );
+    }
+
+    // The first load stops after two pages
+    let interrupted = InterruptedListing {
+        node: carol.clone(),
+        pages: AtomicUsize::new(2),
+    };
+    background::sync_invoices_from_lnd(&interrupted, pool, node_id, 2).await;
+    let indices = db::get_invoice_indices(pool, node_id).await.unwrap();
+    assert_eq!(indices.sync_add_index, 4);
+    assert!(!indices.full_sync_complete);
+
+    // Meanwhile the subscription records a new invoice and its add index
+    let live = carol.create_invoice(invoice_params(6)).await.unwrap();
+    let live_tx = NewTransaction::new(
+        TxType::Invoice,
+        hex::encode(&live.r_hash),
+        live.payment_request.clone(),
+        6_000,
+        None,
+        TxStatus::Pending,
+        None,
+        node_id.to_string(),
+    );
+    db::upsert_transaction(pool, live_tx, TransitionSource::InvoiceSubscription)
+        .await
+        .unwrap();
+    db::advance_invoice_indices(pool, node_id, live.add_index as i64, 0)
+        .await
+        .unwrap();
+
+    // The restarted load carries on after the invoices it listed, not after
+    // the subscription's newer add index
+    background::sync_invoices_from_lnd(&carol, pool, node_id, 2).await;
+    for invoice in &older {
+        let hash = hex::encode(&invoice.r_hash);
+        let recorded = db::get_transaction_by_hash(pool, TxType::Invoice, &hash)
+            .await
+            .unwrap();
+        assert!(recorded.is_some(), "invoice {hash} was skipped");
+    }
+    let indices = db::get_invoice_indices(pool, node_id).await.unwrap();
+    assert!(indices.full_sync_complete);
+    assert_eq!(indices.sync_add_index, 6);
+    assert_eq!(indices.add_index, 6);
+
+    // Later starts only fetch invoices added after the add index
+    let newer = carol.create_invoice(invoice_params(7)).await.unwrap();
+    let counting = InterruptedListing {
+        node: carol.clone(),
+        pages: AtomicUsize::new(usize::MAX),
+    };
+    background::sync_invoices_from_lnd(&counting, pool, node_id, 2).await;
+    assert_eq!(counting.pages.load(Ordering::Relaxed), usize::MAX - 1);
+    let hash = hex::encode(&newer.r_hash);
+    let recorded = db::get_transaction_by_hash(pool, TxType::Invoice, &hash)
+        .await
+        .unwrap();
+    assert!(recorded.is_some());
+}
+
+#[tokio::test]
+async fn hold_invoice_settles_through_the_api() {
+    let Some(app) = setup().await else {
+        return;
+    };
+    let router = &app.router;
+    let mut events = app.events_tx.subscribe();
+
+    let (status, invoice) = call(
+        router,
+        Method::POST,
+        "/api/invoice",
+        Some(json!({ "amount_sats": 800, "hold": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{invoice}");
+    let payment_hash = invoice["payment_hash"].as_str().unwrap().to_string();
+
+    // The payment stays in flight until the invoice is settled
+    let paying = tokio::spawn({
+        let router = router.clone();
+        let body = json!({ "payment_request": invoice["payment_request"] });
+        async move { call(&router, Method::POST, "/api/payment", Some(body)).await }
+    });
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Ok(InvoiceEvent::InvoiceAccepted { tx }) = events.recv().await {
+                if tx.payment_hash == payment_hash {
+                    break;
+                }
+            }
+        }
+    })
+    .await
+    .expect("invoice was not accepted");
+
+    // Hex case in the path does not matter
+    let (status, body) = call(
+        router,
+        Method::POST,
+        &format!("/api/invoice/{}/settle", payment_hash.to_uppercase()),
+        Some(json!({ "preimage": invoice["preimage"] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{body}");
+
+    let (status, payment) = paying.await.unwrap();
+    assert_eq!(status, StatusCode::OK, "{payment}");
+    assert_eq!(payment["preimage"], invoice["preimage"]);
+    wait_for_settlement(&mut events, &payment_hash).await;
+}